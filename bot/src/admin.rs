@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use log::{error, info};
+use shared::chat_config::{save_config, PermissionsConfig};
+use shared::usage::now_seconds;
+use teloxide::prelude::Message;
+use tokio::sync::mpsc::Sender;
+
+use crate::process_message::reply_to;
+use crate::queue::{pause_queue, requeue_failed, resume_queue, FileQueueType, QueueEvent};
+
+/// Splits a command message into the command name and its arguments
+/// Strips the bot username from commands like "/ban@file2link_bot"
+///
+/// # Arguments
+/// * `text` - Message text
+/// # Returns
+/// * `Option` containing the lowercase command name without the slash and the arguments
+/// * `None` if the text is not a command
+pub fn parse_command(text: &str) -> Option<(String, Vec<String>)> {
+    let mut parts = text.split_whitespace();
+
+    let command = parts.next()?.strip_prefix('/')?;
+    let command = command.split('@').next()?.to_lowercase();
+
+    if command.is_empty() {
+        return None;
+    }
+
+    Some((command, parts.map(|part| part.to_owned()).collect()))
+}
+
+const ADMIN_COMMANDS: [&str; 7] = ["ban", "unban", "grant", "failed", "requeue", "pause", "resume"];
+
+/// Most failed files listed by /failed, the newest are shown
+const FAILED_LIST_LIMIT: usize = 20;
+
+pub fn is_admin_command(command: &str) -> bool {
+    ADMIN_COMMANDS.contains(&command)
+}
+
+/// Handles admin commands
+///
+/// # Returns
+/// * `Result` containing `true` if the message was an admin command and was handled
+pub async fn process_admin_command(
+    bot: Arc<teloxide::Bot>,
+    msg: &Message,
+    permissions: &mut PermissionsConfig,
+    file_queue: &FileQueueType,
+    tx: &Sender<QueueEvent>,
+) -> Result<bool, Box<dyn Error>> {
+    let (command, args) = match msg.text().and_then(parse_command) {
+        Some(command) => command,
+        None => return Ok(false),
+    };
+
+    let reply = match command.as_str() {
+        "ban" => match parse_user_id(&args) {
+            Some(user_id) => ban(permissions, user_id).await,
+            None => "Usage: /ban <user_id>".to_owned(),
+        },
+        "unban" => match parse_user_id(&args) {
+            Some(user_id) => unban(permissions, user_id).await,
+            None => "Usage: /unban <user_id>".to_owned(),
+        },
+        "grant" => match (parse_user_id(&args), args.get(1)) {
+            (Some(user_id), None) => grant(permissions, user_id, None).await,
+            (Some(user_id), Some(chat_id)) => match chat_id.parse::<i64>() {
+                Ok(chat_id) => grant(permissions, user_id, Some(chat_id.to_string())).await,
+                Err(_) => "Usage: /grant <user_id> [chat_id]".to_owned(),
+            },
+            (None, _) => "Usage: /grant <user_id> [chat_id]".to_owned(),
+        },
+        "failed" => list_failed(file_queue).await,
+        "requeue" => match args.first().and_then(|id| id.trim_start_matches('#').parse::<u64>().ok()) {
+            Some(id) => match requeue_failed(file_queue, tx, id).await {
+                Ok(position) => format!("Failed file #{} queued again at position {}", id, position),
+                Err(e) => e,
+            },
+            None => "Usage: /requeue <id>".to_owned(),
+        },
+        "pause" => pause_queue(file_queue).await,
+        "resume" => resume_queue(file_queue, tx).await,
+        _ => return Ok(false),
+    };
+
+    reply_to(&bot, msg, reply).await?;
+
+    Ok(true)
+}
+
+fn parse_user_id(args: &[String]) -> Option<String> {
+    args.first()
+        .and_then(|id| id.parse::<i64>().ok())
+        .map(|id| id.to_string())
+}
+
+async fn ban(permissions: &mut PermissionsConfig, user_id: String) -> String {
+    if !permissions.ban(user_id.clone()) {
+        return format!("User {} is already banned", user_id);
+    }
+
+    info!("User {} has been banned", user_id);
+
+    persist(permissions, format!("User {} has been banned", user_id)).await
+}
+
+async fn unban(permissions: &mut PermissionsConfig, user_id: String) -> String {
+    if !permissions.unban(&user_id) {
+        return format!("User {} is not banned", user_id);
+    }
+
+    info!("User {} has been unbanned", user_id);
+
+    persist(permissions, format!("User {} has been unbanned", user_id)).await
+}
+
+async fn grant(permissions: &mut PermissionsConfig, user_id: String, chat_id: Option<String>) -> String {
+    let target = match &chat_id {
+        Some(chat_id) => format!("chat {}", chat_id),
+        None => "all chats".to_owned(),
+    };
+
+    if !permissions.grant(&user_id, chat_id) {
+        return format!("User {} already has access to {}", user_id, target);
+    }
+
+    info!("User {} has been granted access to {}", user_id, target);
+
+    let mut reply = format!("User {} has been granted access to {}", user_id, target);
+
+    if permissions.is_banned(&user_id) {
+        reply.push_str(". Note: the user is banned, use /unban to lift the ban");
+    }
+
+    persist(permissions, reply).await
+}
+
+async fn list_failed(file_queue: &FileQueueType) -> String {
+    let files = file_queue.lock().await.failed_files();
+
+    if files.is_empty() {
+        return "No failed files".to_owned();
+    }
+
+    let now = now_seconds();
+
+    let mut lines: Vec<String> = files.iter()
+        .rev()
+        .take(FAILED_LIST_LIMIT)
+        .map(|file| file.summary(now))
+        .collect();
+
+    if files.len() > FAILED_LIST_LIMIT {
+        lines.push(format!("...and {} older, see f2l-cli failed", files.len() - FAILED_LIST_LIMIT));
+    }
+
+    lines.push("Queue a file again with /requeue <id>".to_owned());
+
+    lines.join("\n")
+}
+
+/// Saves the permissions config, the in-memory config is already updated
+async fn persist(permissions: &PermissionsConfig, reply: String) -> String {
+    match save_config(permissions).await {
+        Ok(_) => reply,
+        Err(e) => {
+            error!("Failed to save permissions config: {}", e);
+
+            format!("{}, but saving the config failed. The change will be lost on restart.", reply)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_command() {
+        assert_eq!(parse_command("/ban 123"), Some(("ban".to_owned(), vec!["123".to_owned()])));
+        assert_eq!(parse_command("/UnBan@file2link_bot  123 "), Some(("unban".to_owned(), vec!["123".to_owned()])));
+        assert_eq!(parse_command("/ping"), Some(("ping".to_owned(), vec![])));
+    }
+
+    #[tokio::test]
+    async fn test_parse_command_not_a_command() {
+        assert_eq!(parse_command("ban 123"), None);
+        assert_eq!(parse_command("/"), None);
+        assert_eq!(parse_command(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_is_admin_command() {
+        assert!(is_admin_command("ban"));
+        assert!(is_admin_command("grant"));
+        assert!(is_admin_command("requeue"));
+        assert!(!is_admin_command("url"));
+    }
+
+    #[tokio::test]
+    async fn test_parse_user_id() {
+        assert_eq!(parse_user_id(&["123".to_owned()]), Some("123".to_owned()));
+        assert_eq!(parse_user_id(&["-100123".to_owned()]), Some("-100123".to_owned()));
+        assert_eq!(parse_user_id(&["abc".to_owned()]), None);
+        assert_eq!(parse_user_id(&[]), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn};
+use shared::config::{Config, SharedConfig};
+use shared::metadata::MetadataStore;
+use shared::metrics::METRICS;
+use shared::stats::StorageStats;
+use shared::utils::{create_directory, format_size};
+use teloxide::prelude::{ChatId, Requester};
+use tokio::sync::Mutex;
+use tokio::time::interval;
+
+/// Number of files failing in a row after which the admin chat is alerted
+pub const FAILURE_ALERT_THRESHOLD: u32 = 3;
+
+const STORAGE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sends an alert to the chat set with `ADMIN_CHAT_ID`, does nothing if it is not set
+/// Failing to send the alert is only logged
+pub async fn notify_admin(bot: &teloxide::Bot, config: &Config, text: String) {
+    let chat_id = match config.admin_chat_id() {
+        Some(chat_id) => ChatId(chat_id),
+        None => return,
+    };
+
+    info!("Sending alert to admin chat {}: {}", chat_id, text);
+
+    if let Err(e) = bot.send_message(chat_id, format!("⚠️ {}", text)).await {
+        warn!("Failed to send alert to admin chat {}: {}", chat_id, e);
+    }
+}
+
+/// Tracks files failing in a row, so one broken link does not alert but a broken download path does
+#[derive(Debug, Default)]
+pub struct FailureCounter {
+    consecutive: u32,
+}
+
+impl FailureCounter {
+    pub fn success(&mut self) {
+        self.consecutive = 0;
+    }
+
+    /// # Returns
+    /// * `true` exactly when the threshold is reached, further failures in the same streak do not alert again
+    pub fn failure(&mut self) -> bool {
+        self.consecutive += 1;
+
+        self.consecutive == FAILURE_ALERT_THRESHOLD
+    }
+
+    pub fn consecutive(&self) -> u32 {
+        self.consecutive
+    }
+}
+
+/// Tracks whether the free space is low, so the admin chat is alerted once when it drops and not every check
+#[derive(Debug, Default)]
+pub struct LowSpaceAlert {
+    low: bool,
+}
+
+impl LowSpaceAlert {
+    /// # Returns
+    /// * `true` exactly when the space became low, it alerts again after the space was freed
+    pub fn check(&mut self, is_low: bool) -> bool {
+        let alert = is_low && !self.low;
+
+        self.low = is_low;
+
+        alert
+    }
+}
+
+/// Checks the usage of the storage every minute for the metrics and alerts the admin chat when the free space in the
+/// files directory drops below `LOW_SPACE_THRESHOLD`, before downloads start failing
+pub async fn watch_storage(bot: Option<Arc<teloxide::Bot>>, config: SharedConfig, metadata: Arc<Mutex<MetadataStore>>) {
+    let files_dir = config.get().files_dir();
+
+    if let Err(e) = create_directory(&files_dir).await {
+        warn!("Failed to create directory '{}': {}", files_dir, e);
+    }
+
+    let mut alert = LowSpaceAlert::default();
+    let mut interval = interval(STORAGE_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let stats = StorageStats::collect(&*metadata.lock().await, &files_dir);
+        let config = config.get();
+
+        if alert.check(stats.is_low_on_space(config.low_space_threshold())) {
+            let text = format!(
+                "Only {} free in '{}', new files may fail to store",
+                format_size(stats.free_bytes.unwrap_or_default()),
+                files_dir,
+            );
+
+            warn!("{}", text);
+
+            if let Some(bot) = &bot {
+                notify_admin(bot, &config, text).await;
+            }
+        }
+
+        METRICS.set_storage(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failure_counter() {
+        let mut counter = FailureCounter::default();
+
+        let alerts: Vec<bool> = (0..FAILURE_ALERT_THRESHOLD + 1).map(|_| counter.failure()).collect();
+
+        assert_eq!(alerts.iter().filter(|alert| **alert).count(), 1);
+        assert!(alerts[FAILURE_ALERT_THRESHOLD as usize - 1]);
+
+        counter.success();
+
+        assert_eq!(counter.consecutive(), 0);
+        assert!(!counter.failure());
+    }
+
+    #[tokio::test]
+    async fn test_low_space_alert() {
+        let mut alert = LowSpaceAlert::default();
+
+        let alerts: Vec<bool> = [false, true, true, false, true].into_iter().map(|is_low| alert.check(is_low)).collect();
+
+        assert_eq!(alerts, vec![false, true, false, false, true]);
+    }
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use log::{info, warn};
+use shared::chat_settings::{save_settings, SettingsStore};
+use teloxide::payloads::CopyMessageSetters;
+use teloxide::prelude::{ChatId, Message, Requester};
+use tokio::sync::Mutex;
+
+use crate::flood;
+use crate::process_message::reply_to;
+use crate::settings::can_change_settings;
+
+const USAGE: &str = "Usage: /forwardto <chat_id> or /forwardto off";
+
+/// Copies the stored file to the archive chat, with the link as the caption
+/// Files downloaded from a URL have no media to copy, only the link is sent
+pub async fn archive_file(bot: &teloxide::Bot, msg: &Message, link: String, archive_chat: ChatId) {
+    let has_media = msg.document().is_some()
+        || msg.photo().is_some()
+        || msg.video().is_some()
+        || msg.animation().is_some();
+
+    let result = if has_media {
+        flood::send(bot.copy_message(archive_chat, msg.chat.id, msg.id).caption(link)).await.map(|_| ())
+    } else {
+        flood::send(bot.send_message(archive_chat, link)).await.map(|_| ())
+    };
+
+    match result {
+        Ok(_) => info!("File from chat {} archived to chat {}", msg.chat.id, archive_chat),
+        Err(e) => warn!("Failed to archive file from chat {} to chat {}: {}", msg.chat.id, archive_chat, e),
+    }
+}
+
+/// Handles "/forwardto <chat_id>" and "/forwardto off"
+/// A message is sent to the archive chat first, so a chat the bot can not post to is refused
+pub async fn process_forward_to(
+    bot: Arc<teloxide::Bot>,
+    msg: &Message,
+    args: &[String],
+    settings: Arc<Mutex<SettingsStore>>,
+    is_bot_admin: bool,
+) -> Result<(), Box<dyn Error>> {
+    if !can_change_settings(&bot, &msg.chat, msg.from(), is_bot_admin).await? {
+        reply_to(&bot, msg, "Only administrators of the chat can change its settings".to_owned()).await?;
+
+        return Ok(());
+    }
+
+    let archive_chat = match args.first().map(|arg| arg.as_str()) {
+        Some("off") => None,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(chat_id) => Some(chat_id),
+            Err(_) => {
+                reply_to(&bot, msg, USAGE.to_owned()).await?;
+
+                return Ok(());
+            }
+        },
+        None => {
+            let current = settings.lock().await.get(&msg.chat.id.to_string()).archive_chat;
+
+            let reply = match current {
+                Some(chat_id) => format!("Files are archived to chat {}. {}", chat_id, USAGE),
+                None => format!("Files are not archived. {}", USAGE),
+            };
+
+            reply_to(&bot, msg, reply).await?;
+
+            return Ok(());
+        }
+    };
+
+    if let Some(chat_id) = archive_chat {
+        let notice = format!("Files sent to chat {} will be archived here", msg.chat.id);
+
+        if let Err(e) = bot.send_message(ChatId(chat_id), notice).await {
+            warn!("Failed to send a message to archive chat {}: {}", chat_id, e);
+
+            reply_to(&bot, msg, format!("Failed to send a message to chat {}, make sure the bot can post there", chat_id)).await?;
+
+            return Ok(());
+        }
+    }
+
+    let reply = {
+        let mut settings = settings.lock().await;
+
+        settings.update(&msg.chat.id.to_string(), |chat| chat.archive_chat = archive_chat);
+
+        let reply = match archive_chat {
+            Some(chat_id) => format!("Files will be archived to chat {}", chat_id),
+            None => "Files will not be archived".to_owned(),
+        };
+
+        match save_settings(&settings).await {
+            Ok(_) => reply,
+            Err(e) => {
+                warn!("Failed to save settings: {}", e);
+
+                format!("{}, but saving the settings failed. The change will be lost on restart.", reply)
+            }
+        }
+    };
+
+    info!("Archive chat of chat {} set to {:?}", msg.chat.id, archive_chat);
+
+    reply_to(&bot, msg, reply).await?;
+
+    Ok(())
+}
@@ -1,22 +1,43 @@
 use log::{debug, error, info};
 use reqwest::{Client, Url};
 use shared::chat_config::PermissionsConfig;
-use shared::config::Config;
+use shared::config::SharedConfig;
+use shared::chat_settings::SettingsStore;
+use shared::metadata::MetadataStore;
+use shared::usage::UsageTracker;
 use std::sync::Arc;
 use std::time::Duration;
-use teloxide::prelude::Message;
+use teloxide::dispatching::{Dispatcher, UpdateFilterExt, UpdateHandler};
+use teloxide::dptree;
+use teloxide::prelude::{CallbackQuery, InlineQuery, Message, ResponseResult};
+use teloxide::RequestError;
+use teloxide::types::{Me, Update};
+use tokio::sync::mpsc::Sender;
 use tokio::sync::Mutex;
-use crate::process_message::process_message;
-use crate::queue::FileQueueType;
+use crate::admin::{self, process_admin_command};
+use crate::archive::process_forward_to;
+use crate::settings::{is_settings, process_settings_callback, process_settings_command};
+use crate::callbacks::{is_retry, process_callback, process_retry};
+use crate::cancel::process_cancel;
+use crate::command_args::Command;
+use crate::inline::process_inline_query;
+use crate::mylink::process_my_link;
+use crate::ping::process_ping;
+use crate::process_message::{is_addressed, process_message, topic_id};
+use crate::queue::{FileQueueType, QueueEvent};
 
 pub trait Bot {
-    fn new(config: Arc<Config>, permissions: Arc<Mutex<PermissionsConfig>>, queue: FileQueueType) -> Result<Self, String> where Self: Sized;
-    fn run(&self, tx: tokio::sync::mpsc::Sender<()>) -> impl std::future::Future<Output=()> + Send;
+    fn new(config: SharedConfig, token: String, permissions: Arc<Mutex<PermissionsConfig>>, usage: Arc<Mutex<UsageTracker>>, metadata: Arc<Mutex<MetadataStore>>, settings: Arc<Mutex<SettingsStore>>, queue: FileQueueType) -> Result<Self, String> where Self: Sized;
+    fn run(&self, tx: tokio::sync::mpsc::Sender<QueueEvent>) -> impl std::future::Future<Output=()> + Send;
 }
 
 #[derive(Debug, Clone)]
 pub struct TeloxideBot {
+    config: SharedConfig,
     permissions: Arc<Mutex<PermissionsConfig>>,
+    usage: Arc<Mutex<UsageTracker>>,
+    metadata: Arc<Mutex<MetadataStore>>,
+    settings: Arc<Mutex<SettingsStore>>,
     queue: FileQueueType,
     teloxide_bot: Arc<teloxide::Bot>,
 }
@@ -28,109 +49,359 @@ impl TeloxideBot {
 }
 
 impl Bot for TeloxideBot {
-    fn new(config: Arc<Config>, permissions: Arc<Mutex<PermissionsConfig>>, queue: FileQueueType) -> Result<Self, String> {
-        let client = Client::builder()
-            .connect_timeout(Duration::from_secs(5))
-            .timeout(Duration::from_secs(300))
-            .tcp_nodelay(true)
+    fn new(config: SharedConfig, token: String, permissions: Arc<Mutex<PermissionsConfig>>, usage: Arc<Mutex<UsageTracker>>, metadata: Arc<Mutex<MetadataStore>>, settings: Arc<Mutex<SettingsStore>>, queue: FileQueueType) -> Result<Self, String> {
+        let current_config = config.get();
+
+        let mut client = Client::builder()
+            .connect_timeout(Duration::from_secs(current_config.connect_timeout()))
+            .tcp_nodelay(true);
+
+        if let Some(timeout) = current_config.request_timeout() {
+            client = client.timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(proxy) = current_config.proxy() {
+            info!("Connecting to Telegram through the proxy {}", proxy.redacted_url());
+
+            client = client.proxy(proxy.proxy().map_err(|e| format!("Failed to set up the proxy: {}", e))?);
+        }
+
+        let client = client
             .build()
             .unwrap_or_else(|e| {
                 error!("Failed to create client: {}", e);
                 Client::new()
             });
 
-        let token = match config.bot_token() {
-            Ok(t) => { t }
-            Err(_) => {
-                error!("Failed to get bot token");
+        if token.is_empty() {
+            error!("Failed to get bot token");
 
-                return Err("Failed to get bot token".to_owned());
-            }
-        };
+            return Err("Failed to get bot token".to_owned());
+        }
 
         let mut bot = teloxide::Bot::with_client(token, client);
 
-        bot = bot.set_api_url(Url::parse(config.telegram_api_url().as_str()).unwrap());
+        let api_url = Url::parse(current_config.telegram_api_url().as_str())
+            .map_err(|e| format!("Failed to parse TELEGRAM_API_URL: {}", e))?;
+
+        bot = bot.set_api_url(api_url);
 
         let bot_ref = Arc::new(bot);
 
         Ok(TeloxideBot {
             teloxide_bot: bot_ref,
+            config,
             permissions,
+            usage,
+            metadata,
+            settings,
             queue,
         })
     }
 
-    async fn run(&self, tx: tokio::sync::mpsc::Sender<()>) {
-        let file_queue = Arc::clone(&self.queue);
-        let permissions = Arc::clone(&self.permissions);
-        let bot = self.teloxide_bot.clone();
-
-        teloxide::repl(bot.clone(), move |msg: Message| {
-            debug!("Received message: {:?}", msg);
-
-            let bot = Arc::clone(&bot);
-            let bot_clone = Arc::clone(&bot);
-            let permissions = Arc::clone(&permissions);
-            let file_queue = Arc::clone(&file_queue);
-            let tx = tx.clone();
-
-            async move {
-                let permissions = permissions.lock().await;
-
-                let from = match msg.from() {
-                    Some(from) => from,
-                    None => {
-                        info!("Message does not have a sender");
-                        return Ok(());
-                    }
-                };
-
-                if !permissions.user_has_access(msg.chat.id.to_string(), &from.id.to_string()) {
-                    info!(
-                        "User {} does not have access to chat {}",
-                        msg.from().unwrap().id,
-                        msg.clone().chat.id
-                    );
-
-                    return Ok(());
-                }
-
-                info!(
-                    "User {} has access to chat {}",
-                    msg.from().unwrap().id,
-                    msg.clone().chat.id
-                );
-
-                if let Err(e) = process_message(bot_clone.clone(), msg.clone(), file_queue, tx).await {
-                    error!("Failed to process message: {}", e);
-                }
-
-                Ok(())
-            }
-        }).await;
+    async fn run(&self, tx: Sender<QueueEvent>) {
+        Dispatcher::builder(self.teloxide_bot.clone(), Self::schema())
+            .dependencies(dptree::deps![
+                self.config.clone(),
+                Arc::clone(&self.permissions),
+                Arc::clone(&self.usage),
+                Arc::clone(&self.metadata),
+                Arc::clone(&self.settings),
+                Arc::clone(&self.queue),
+                tx
+            ])
+            .default_handler(|_| async {})
+            .enable_ctrlc_handler()
+            .build()
+            .dispatch()
+            .await;
+    }
+}
+
+impl TeloxideBot {
+    /// The handler tree of the dispatcher
+    ///
+    /// Messages from users go through admin commands first, then the access check, then other commands and at last
+    /// media and links. Posts on behalf of a chat (channel posts, anonymous admins) have no user to check.
+    pub fn schema() -> UpdateHandler<RequestError> {
+        let message_handler = Update::filter_message()
+            .branch(dptree::filter(is_chat_post).filter_async(responds_in_chat).endpoint(handle_chat_post))
+            .branch(
+                dptree::filter_map(|msg: Message| Command::from_message(&msg))
+                    .filter_async(is_admin_command)
+                    .endpoint(handle_admin_command)
+            )
+            .branch(
+                dptree::filter_async(has_access)
+                    .branch(
+                        dptree::filter_map(|msg: Message| Command::from_message(&msg))
+                            .filter(|command: Command| BOT_COMMANDS.contains(&command.name.as_str()))
+                            .endpoint(handle_command)
+                    )
+                    // "/url", "/fileid" and unknown commands are handled with media
+                    .branch(dptree::filter_async(responds_in_chat).endpoint(handle_media))
+            );
+
+        dptree::entry()
+            .branch(message_handler)
+            .branch(Update::filter_channel_post().endpoint(handle_chat_post))
+            .branch(
+                Update::filter_callback_query()
+                    .branch(dptree::filter(|query: CallbackQuery| is_retry(&query)).endpoint(handle_retry))
+                    .branch(dptree::filter(|query: CallbackQuery| is_settings(&query)).endpoint(handle_settings_callback))
+                    .branch(dptree::endpoint(handle_callback_query))
+            )
+            .branch(Update::filter_inline_query().endpoint(handle_inline_query))
+    }
+}
+
+/// Commands handled by the bot itself, other commands like "/url" are handled with media
+const BOT_COMMANDS: [&str; 5] = ["ping", "forwardto", "settings", "mylink", "cancel"];
+
+/// Anonymous group admins send messages on behalf of the group
+fn is_chat_post(msg: Message) -> bool {
+    msg.from().is_none() || msg.sender_chat().is_some()
+}
+
+async fn is_admin_command(msg: Message, command: Command, permissions: Arc<Mutex<PermissionsConfig>>) -> bool {
+    let from = match msg.from() {
+        Some(from) => from,
+        None => return false,
+    };
+
+    admin::is_admin_command(&command.name) && permissions.lock().await.is_admin(&from.id.to_string())
+}
+
+/// Groups can ask the bot to only respond to messages meant for it, see `ChatSettings::addressed_only`
+async fn responds_in_chat(msg: Message, me: Me, settings: Arc<Mutex<SettingsStore>>) -> bool {
+    let is_group = msg.chat.is_group() || msg.chat.is_supergroup();
+
+    if !is_group || !settings.lock().await.get(&msg.chat.id.to_string()).addressed_only {
+        return true;
     }
+
+    let username = me.username.as_deref().unwrap_or_default();
+
+    if !is_addressed(&msg, me.id, username) {
+        debug!("Message {} in chat {} is not meant for the bot", msg.id, msg.chat.id);
+
+        return false;
+    }
+
+    true
+}
+
+async fn has_access(msg: Message, permissions: Arc<Mutex<PermissionsConfig>>) -> bool {
+    let from = match msg.from() {
+        Some(from) => from,
+        None => return false,
+    };
+
+    if !permissions.lock().await.user_has_topic_access(msg.chat.id.to_string(), topic_id(&msg), &from.id.to_string()) {
+        info!("User {} does not have access to chat {}", from.id, msg.chat.id);
+
+        return false;
+    }
+
+    info!("User {} has access to chat {}", from.id, msg.chat.id);
+
+    true
+}
+
+async fn hints_enabled(msg: &Message, permissions: &Arc<Mutex<PermissionsConfig>>) -> bool {
+    permissions.lock().await.hints_enabled(&msg.chat.id.to_string(), msg.chat.is_private())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_chat_post(
+    bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
+    msg: Message,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+    usage: Arc<Mutex<UsageTracker>>,
+    settings: Arc<Mutex<SettingsStore>>,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+) -> ResponseResult<()> {
+    debug!("Received post on behalf of a chat: {:?}", msg);
+
+    if !permissions.lock().await.channel_has_access(&msg.chat.id.to_string()) {
+        info!("Posts on behalf of a chat are not allowed in chat {}", msg.chat.id);
+
+        return Ok(());
+    }
+
+    info!("Processing a post on behalf of a chat in chat {}", msg.chat.id);
+
+    let hint = hints_enabled(&msg, &permissions).await;
+
+    if let Err(e) = process_message(bot, config, msg, file_queue, usage, settings, tx, hint).await {
+        error!("Failed to process message: {}", e);
+    }
+
+    Ok(())
+}
+
+async fn handle_admin_command(
+    bot: Arc<teloxide::Bot>,
+    msg: Message,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+) -> ResponseResult<()> {
+    debug!("Received admin command: {:?}", msg);
+
+    if let Err(e) = process_admin_command(bot, &msg, &mut *permissions.lock().await, &file_queue, &tx).await {
+        error!("Failed to process admin command: {}", e);
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_command(
+    bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
+    msg: Message,
+    command: Command,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+    settings: Arc<Mutex<SettingsStore>>,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+) -> ResponseResult<()> {
+    debug!("Received command: {:?}", msg);
+
+    let is_admin = match msg.from() {
+        Some(from) => permissions.lock().await.is_admin(&from.id.to_string()),
+        None => false,
+    };
+
+    let result = match command.name.as_str() {
+        "ping" => process_ping(bot, &config.get(), &msg, file_queue).await,
+        "forwardto" => process_forward_to(bot, &msg, &command.args, settings, is_admin).await,
+        "settings" => process_settings_command(bot, &msg, settings, is_admin).await,
+        "mylink" => process_my_link(bot, &config.get(), &msg).await,
+        "cancel" => process_cancel(bot, &msg, file_queue, tx, is_admin).await,
+        _ => unreachable!(),
+    };
+
+    if let Err(e) = result {
+        error!("Failed to process {} command: {}", command.name, e);
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_media(
+    bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
+    msg: Message,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+    usage: Arc<Mutex<UsageTracker>>,
+    settings: Arc<Mutex<SettingsStore>>,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+) -> ResponseResult<()> {
+    debug!("Received message: {:?}", msg);
+
+    let hint = hints_enabled(&msg, &permissions).await;
+
+    if let Err(e) = process_message(bot, config, msg, file_queue, usage, settings, tx, hint).await {
+        error!("Failed to process message: {}", e);
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_retry(
+    bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
+    query: CallbackQuery,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+    usage: Arc<Mutex<UsageTracker>>,
+    settings: Arc<Mutex<SettingsStore>>,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+) -> ResponseResult<()> {
+    debug!("Received retry: {:?}", query);
+
+    if let Err(e) = process_retry(bot, config, query, permissions, usage, settings, file_queue, tx).await {
+        error!("Failed to process retry: {}", e);
+    }
+
+    Ok(())
+}
+
+async fn handle_settings_callback(
+    bot: Arc<teloxide::Bot>,
+    query: CallbackQuery,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+    settings: Arc<Mutex<SettingsStore>>,
+) -> ResponseResult<()> {
+    debug!("Received settings change: {:?}", query);
+
+    if let Err(e) = process_settings_callback(bot, query, settings, permissions).await {
+        error!("Failed to process settings change: {}", e);
+    }
+
+    Ok(())
+}
+
+async fn handle_callback_query(
+    bot: Arc<teloxide::Bot>,
+    query: CallbackQuery,
+    metadata: Arc<Mutex<MetadataStore>>,
+) -> ResponseResult<()> {
+    debug!("Received callback query: {:?}", query);
+
+    if let Err(e) = process_callback(bot, query, metadata).await {
+        error!("Failed to process callback query: {}", e);
+    }
+
+    Ok(())
+}
+
+async fn handle_inline_query(
+    bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
+    query: InlineQuery,
+    metadata: Arc<Mutex<MetadataStore>>,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+) -> ResponseResult<()> {
+    debug!("Received inline query: {:?}", query);
+
+    if let Err(e) = process_inline_query(bot, &config.get(), query, metadata, permissions).await {
+        error!("Failed to process inline query: {}", e);
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use crate::bot::{Bot, TeloxideBot};
+    use crate::queue::QueueManager;
     use shared::chat_config::PermissionsConfig;
-    use shared::config::Config;
-    use std::env;
+    use shared::config::{Config, SharedConfig};
+    use shared::chat_settings::SettingsStore;
+    use shared::metadata::MetadataStore;
+    use shared::usage::UsageTracker;
     use std::sync::Arc;
     use tokio::sync::Mutex;
 
     #[tokio::test]
-    #[serial_test::serial]
     async fn test_teloxide_bot_new() {
-        env::set_var("BOT_TOKEN", "test_token");
-
-        let config = Arc::new(Config::new());
+        let config = SharedConfig::new(Config::from_variables([("BOT_TOKEN", "test_token")]));
+        let token = config.get().bot_tokens().expect("Failed to get bot tokens")[0].clone();
         let permissions = Arc::new(Mutex::new(PermissionsConfig::init_allow_all()));
-        let queue = Arc::new(Mutex::new(Vec::new()));
+        let usage = Arc::new(Mutex::new(UsageTracker::default()));
+        let metadata = Arc::new(Mutex::new(MetadataStore::default()));
+        let settings = Arc::new(Mutex::new(SettingsStore::default()));
+        let queue = Arc::new(Mutex::new(QueueManager::new()));
 
-        let bot = match TeloxideBot::new(config, permissions, queue) {
+        let bot = match TeloxideBot::new(config, token, permissions, usage, metadata, settings, queue) {
             Ok(b) => { b }
             Err(_) => {
                 panic!("Failed to create bot");
@@ -139,7 +410,25 @@ mod tests {
 
         assert_eq!(bot.get_teloxide_bot().token(), "test_token");
         assert_eq!(bot.get_teloxide_bot().api_url().as_str(), "https://api.telegram.org/");
+    }
+
+    #[tokio::test]
+    async fn test_teloxide_bot_new_multiple_tokens() {
+        let config = SharedConfig::new(Config::from_variables([("BOT_TOKEN", "public_token,private_token")]));
+        let permissions = Arc::new(Mutex::new(PermissionsConfig::init_allow_all()));
+        let usage = Arc::new(Mutex::new(UsageTracker::default()));
+        let metadata = Arc::new(Mutex::new(MetadataStore::default()));
+        let settings = Arc::new(Mutex::new(SettingsStore::default()));
+        let queue = Arc::new(Mutex::new(QueueManager::new()));
+
+        let bots: Vec<TeloxideBot> = config.get().bot_tokens().expect("Failed to get bot tokens")
+            .into_iter()
+            .map(|token| TeloxideBot::new(config.clone(), token, permissions.clone(), usage.clone(), metadata.clone(), settings.clone(), queue.clone())
+                .expect("Failed to create bot"))
+            .collect();
 
-        env::remove_var("BOT_TOKEN")
+        assert_eq!(bots.len(), 2);
+        assert_eq!(bots[0].get_teloxide_bot().token(), "public_token");
+        assert_eq!(bots[1].get_teloxide_bot().token(), "private_token");
     }
 }
\ No newline at end of file
@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use log::{info, warn};
+use shared::chat_config::PermissionsConfig;
+use shared::chat_settings::SettingsStore;
+use shared::config::SharedConfig;
+use shared::metadata::{save_metadata, MetadataStore};
+use shared::usage::{now_seconds, UsageTracker};
+use teloxide::payloads::AnswerCallbackQuerySetters;
+use teloxide::prelude::{CallbackQuery, Requester};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+
+use crate::process_message::process_message;
+use crate::queue::{FileQueueType, QueueEvent};
+
+const EXPIRE_PREFIX: &str = "expire";
+const RETRY_DATA: &str = "retry";
+
+/// Expiry choices offered for uploaded media, zero seconds means the link never expires
+pub(crate) const EXPIRY_OPTIONS: [(&str, u64); 4] = [
+    ("1h", 60 * 60),
+    ("24h", 24 * 60 * 60),
+    ("7d", 7 * 24 * 60 * 60),
+    ("Never", 0),
+];
+
+/// Builds the keyboard letting the uploader choose when the link expires
+///
+/// # Arguments
+/// * `file_id` - The random ID of the stored file, see `FileMetadata::id`
+pub fn expiry_keyboard(file_id: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([EXPIRY_OPTIONS.iter().map(|(label, seconds)| {
+        InlineKeyboardButton::callback(*label, format!("{}:{}:{}", EXPIRE_PREFIX, seconds, file_id))
+    })])
+}
+
+/// Builds the keyboard shown under a failed download
+pub fn retry_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("Retry", RETRY_DATA)]])
+}
+
+pub fn is_retry(query: &CallbackQuery) -> bool {
+    query.data.as_deref() == Some(RETRY_DATA)
+}
+
+/// Handles presses on the retry button by processing the original message again
+/// Only the sender of the original message may retry
+#[allow(clippy::too_many_arguments)]
+pub async fn process_retry(
+    bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
+    query: CallbackQuery,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+    usage: Arc<Mutex<UsageTracker>>,
+    settings: Arc<Mutex<SettingsStore>>,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+) -> Result<(), Box<dyn Error>> {
+    let message = match &query.message {
+        Some(message) => message,
+        None => {
+            bot.answer_callback_query(query.id).text("The message is too old").await?;
+
+            return Ok(());
+        }
+    };
+
+    let original = match message.reply_to_message() {
+        Some(original) if original.from().map(|user| user.id) == Some(query.from.id) => original.clone(),
+        Some(_) => {
+            bot.answer_callback_query(query.id).text("Only the sender can retry").await?;
+
+            return Ok(());
+        }
+        None => {
+            bot.answer_callback_query(query.id).text("The original message was deleted").await?;
+
+            return Ok(());
+        }
+    };
+
+    if permissions.lock().await.is_banned(&query.from.id.to_string()) {
+        bot.answer_callback_query(query.id).await?;
+
+        return Ok(());
+    }
+
+    info!("User {} retries message {}", query.from.id, original.id);
+
+    bot.answer_callback_query(query.id).text("Retrying...").await?;
+
+    if let Err(e) = bot.delete_message(message.chat.id, message.id).await {
+        warn!("Failed to delete the failed status message: {}", e);
+
+        bot.edit_message_reply_markup(message.chat.id, message.id).await?;
+    }
+
+    process_message(bot, config, original, file_queue, usage, settings, tx, false).await
+}
+
+/// Parses callback data created by `expiry_keyboard`
+///
+/// # Returns
+/// * `Option` containing the expiry in seconds and the file ID
+fn parse_expire_data(data: &str) -> Option<(u64, String)> {
+    let mut parts = data.splitn(3, ':');
+
+    if parts.next()? != EXPIRE_PREFIX {
+        return None;
+    }
+
+    let seconds = parts.next()?.parse().ok()?;
+    let file_id = parts.next().filter(|id| !id.is_empty())?;
+
+    Some((seconds, file_id.to_owned()))
+}
+
+/// Handles presses on the expiry keyboard, only the uploader of the file may change its expiry
+pub async fn process_callback(
+    bot: Arc<teloxide::Bot>,
+    query: CallbackQuery,
+    metadata: Arc<Mutex<MetadataStore>>,
+) -> Result<(), Box<dyn Error>> {
+    let (seconds, file_id) = match query.data.as_deref().and_then(parse_expire_data) {
+        Some(data) => data,
+        None => {
+            bot.answer_callback_query(query.id).await?;
+
+            return Ok(());
+        }
+    };
+
+    let message = match &query.message {
+        Some(message) => message,
+        None => {
+            bot.answer_callback_query(query.id).text("The message is too old").await?;
+
+            return Ok(());
+        }
+    };
+
+    let uploader = message.reply_to_message().and_then(|reply| reply.from());
+
+    if uploader.map(|user| user.id) != Some(query.from.id) {
+        info!("User {} tried to change the expiry of file {}", query.from.id, file_id);
+
+        bot.answer_callback_query(query.id).text("Only the uploader can change the expiry").await?;
+
+        return Ok(());
+    }
+
+    let reply = {
+        let mut metadata = metadata.lock().await;
+
+        match metadata.find_by_id_mut(&file_id) {
+            Some(file) => {
+                file.expires_at = if seconds == 0 { None } else { Some(now_seconds() + seconds) };
+
+                info!("File {} expiry set to {:?}", file.file_name, file.expires_at);
+
+                if let Err(e) = save_metadata(&metadata).await {
+                    warn!("Failed to save metadata: {}", e);
+                }
+
+                match EXPIRY_OPTIONS.iter().find(|(_, option)| *option == seconds) {
+                    Some((_, 0)) => "The link will not expire".to_owned(),
+                    Some((label, _)) => format!("The link expires in {}", label),
+                    None => format!("The link expires in {} seconds", seconds),
+                }
+            }
+            None => "File not found".to_owned(),
+        }
+    };
+
+    bot.answer_callback_query(query.id).text(reply).await?;
+    bot.edit_message_reply_markup(message.chat.id, message.id).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_expire_data() {
+        assert_eq!(parse_expire_data("expire:3600:abcde"), Some((3600, "abcde".to_owned())));
+        assert_eq!(parse_expire_data("expire:0:abcde"), Some((0, "abcde".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn test_parse_expire_data_invalid() {
+        assert_eq!(parse_expire_data("expire:abc:abcde"), None);
+        assert_eq!(parse_expire_data("expire:3600:"), None);
+        assert_eq!(parse_expire_data("other:3600:abcde"), None);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_keyboard_round_trip() {
+        let keyboard = expiry_keyboard("abcde");
+
+        let parsed: Vec<Option<(u64, String)>> = keyboard.inline_keyboard[0].iter()
+            .map(|button| match &button.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => parse_expire_data(data),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(parsed.len(), EXPIRY_OPTIONS.len());
+        assert!(parsed.iter().all(|data| data.as_ref().is_some_and(|(_, id)| id == "abcde")));
+    }
+}
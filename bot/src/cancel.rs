@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use teloxide::prelude::Message;
+use tokio::sync::mpsc::Sender;
+
+use crate::admin::parse_command;
+use crate::process_message::{reply_to, sender_id};
+use crate::queue::{cancel_files, cancel_job, FileQueueType, QueueEvent};
+
+const USAGE: &str = "Reply with /cancel to a file or to its queue message, or send /cancel <job id>";
+
+/// Cancels the file with the job ID given as argument, or the queued files of the replied message, the message with
+/// the files or the status message of the bot
+/// Users can only cancel their own files, bot admins can cancel any
+pub async fn process_cancel(
+    bot: Arc<teloxide::Bot>,
+    msg: &Message,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+    is_bot_admin: bool,
+) -> Result<(), Box<dyn Error>> {
+    let job = msg.text()
+        .and_then(parse_command)
+        .and_then(|(_, args)| args.into_iter().next());
+
+    let owner = match (is_bot_admin, sender_id(msg)) {
+        (true, _) => None,
+        (false, Some(sender_id)) => Some(sender_id),
+        (false, None) => {
+            reply_to(&bot, msg, "Could not determine who sent the command.".to_owned()).await?;
+
+            return Ok(());
+        }
+    };
+
+    let cancelled = match (job, msg.reply_to_message()) {
+        (Some(job), _) => cancel_job(&file_queue, &tx, &job, owner.as_deref()).await,
+        (None, Some(target)) => cancel_files(&file_queue, &tx, msg.chat.id, target.id, owner.as_deref()).await,
+        (None, None) => {
+            reply_to(&bot, msg, USAGE.to_owned()).await?;
+
+            return Ok(());
+        }
+    };
+
+    let reply = match cancelled {
+        0 => "Nothing to cancel. The files are already processed or were sent by someone else.".to_owned(),
+        count => format!("Cancelled {} file(s)", count),
+    };
+
+    reply_to(&bot, msg, reply).await?;
+
+    Ok(())
+}
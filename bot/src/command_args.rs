@@ -0,0 +1,350 @@
+use reqwest::header::{HeaderName, HeaderValue};
+use shared::config::OffPeakWindow;
+use shared::utils::{next_time_of_day, parse_duration, parse_time_of_day};
+use teloxide::prelude::Message;
+
+use crate::admin::parse_command;
+use crate::queue::RequestOptions;
+
+/// A command sent to the bot, e.g. "/ban 123"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    /// Lowercase name without the slash and the bot username
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl Command {
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let (name, args) = msg.text().and_then(parse_command)?;
+
+        Some(Self { name, args })
+    }
+}
+
+/// Flags which take the next argument as their value
+const FLAGS_WITH_VALUE: [&str; 5] = ["--expire", "--header", "--cookie", "--auth", "--at"];
+
+/// Flags which are set by their presence alone
+const BOOLEAN_FLAGS: [&str; 4] = ["--strip", "--later", "--extract", "--private"];
+
+/// Options given with flags, e.g. "/url https://example.com --expire 24h --auth user:password"
+#[derive(Debug, Default, PartialEq)]
+pub struct Flags {
+    /// Seconds until the link expires
+    pub expires_in: Option<u64>,
+    pub request_options: RequestOptions,
+    /// Remove EXIF and similar metadata from photos
+    pub strip_metadata: bool,
+    /// Extract zip and tar archives into separately linked files
+    pub extract_archive: bool,
+    /// Serve the file only with the token of the uploader, see `FileMetadata::private`
+    pub private: bool,
+    /// Download at this time of day (UTC), in seconds after midnight
+    pub at: Option<u64>,
+    /// Download in the off-peak window, see `Config::off_peak_window`
+    pub later: bool,
+}
+
+impl Flags {
+    /// Get the Unix time the download may start at, `None` to start it in turn
+    ///
+    /// # Returns
+    /// * `Err` containing a message for the user if `--later` is used without an off-peak window
+    pub fn start_time(&self, window: Option<OffPeakWindow>, now: u64) -> Result<Option<u64>, String> {
+        if let Some(at) = self.at {
+            return Ok(Some(next_time_of_day(now, at)));
+        }
+
+        if !self.later {
+            return Ok(None);
+        }
+
+        match window {
+            Some(window) => Ok(Some(window.next_start(now)).filter(|start| *start > now)),
+            None => Err("--later is not available, no off-peak window is configured. Use --at 03:00 instead".to_owned()),
+        }
+    }
+
+    /// Checks that private links can be handed out for the file
+    ///
+    /// # Arguments
+    /// * `secret` - The configured `LISTING_SECRET` the tokens are signed with
+    /// * `owner` - ID of the uploader, messages sent on behalf of a channel have none
+    ///
+    /// # Returns
+    /// * `Err` containing a message for the user if `--private` is used but no token can be signed
+    pub fn check_private(&self, secret: Option<&str>, owner: Option<&str>) -> Result<(), String> {
+        if !self.private {
+            return Ok(());
+        }
+
+        match (secret, owner) {
+            (Some(_), Some(_)) => Ok(()),
+            (None, _) => Err("--private is not available, private links are not configured".to_owned()),
+            (_, None) => Err("--private needs a user to own the file, send it from your own account".to_owned()),
+        }
+    }
+}
+
+/// Splits the text into arguments by whitespace, text in double quotes is kept as one argument
+///
+/// # Example
+/// `/url https://example.com --header "Authorization: Bearer token"` gives
+/// `["/url", "https://example.com", "--header", "Authorization: Bearer token"]`
+pub fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+
+    for c in text.chars() {
+        match c {
+            // Telegram apps on some platforms replace straight quotes with typographic ones
+            '"' | '“' | '”' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
+            }
+        }
+    }
+
+    if has_arg {
+        args.push(current);
+    }
+
+    args
+}
+
+/// Get the arguments which are neither flags nor flag values
+pub fn positional_args(args: &[String]) -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if FLAGS_WITH_VALUE.contains(&arg.as_str()) {
+            args.next();
+        } else if !BOOLEAN_FLAGS.contains(&arg.as_str()) {
+            positional.push(arg.clone());
+        }
+    }
+
+    positional
+}
+
+/// Parses the flags from the arguments, unknown flags are ignored
+///
+/// # Returns
+/// * `Err` containing a message for the user if a flag has a missing or invalid value
+pub fn parse_flags(args: &[String]) -> Result<Flags, String> {
+    let mut flags = Flags::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let flag = arg.as_str();
+
+        if flag == "--strip" {
+            flags.strip_metadata = true;
+            continue;
+        }
+
+        if flag == "--later" {
+            flags.later = true;
+            continue;
+        }
+
+        if flag == "--extract" {
+            flags.extract_archive = true;
+            continue;
+        }
+
+        if flag == "--private" {
+            flags.private = true;
+            continue;
+        }
+
+        if !FLAGS_WITH_VALUE.contains(&flag) {
+            continue;
+        }
+
+        let value = args.next().map(|value| value.as_str());
+
+        match flag {
+            "--expire" => {
+                flags.expires_in = Some(value.and_then(parse_duration)
+                    .ok_or("Invalid expiry. Use a duration like 30m, 24h or 7d")?);
+            }
+            "--header" => {
+                let header = value.and_then(parse_header)
+                    .ok_or("Invalid header. Use --header \"Name: value\"")?;
+
+                flags.request_options.headers.push(header);
+            }
+            "--cookie" => {
+                let cookie = value.filter(|cookie| cookie.contains('='))
+                    .ok_or("Invalid cookie. Use --cookie \"name=value\"")?;
+
+                flags.request_options.cookies.push(cookie.to_owned());
+            }
+            "--at" => {
+                flags.at = Some(value.and_then(parse_time_of_day)
+                    .ok_or("Invalid time. Use a time of day in UTC like 03:00")?);
+            }
+            "--auth" => {
+                let auth = value.filter(|auth| !auth.is_empty())
+                    .ok_or("Invalid credentials. Use --auth user:password")?;
+
+                flags.request_options.basic_auth = Some(match auth.split_once(':') {
+                    Some((user, password)) => (user.to_owned(), Some(password.to_owned())),
+                    None => (auth.to_owned(), None),
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(flags)
+}
+
+fn parse_header(header: &str) -> Option<(String, String)> {
+    let (name, value) = header.split_once(':')?;
+    let (name, value) = (name.trim(), value.trim());
+
+    HeaderName::from_bytes(name.as_bytes()).ok()?;
+    HeaderValue::from_str(value).ok()?;
+
+    Some((name.to_owned(), value.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        split_args(text)
+    }
+
+    #[tokio::test]
+    async fn test_split_args() {
+        assert_eq!(
+            args(r#"/url https://example.com --header "Authorization: Bearer token""#),
+            vec!["/url", "https://example.com", "--header", "Authorization: Bearer token"]
+        );
+        assert_eq!(args("  /url   --cookie “a=b; c=d”  "), vec!["/url", "--cookie", "a=b; c=d"]);
+        assert_eq!(args(r#"--header """#), vec!["--header", ""]);
+        assert!(args("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_positional_args() {
+        let positional = positional_args(&args("/url https://a.com --expire 24h --strip --referer https://b.com --auth user:pass"));
+
+        assert_eq!(positional, vec!["/url", "https://a.com", "--referer", "https://b.com"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags() {
+        let flags = parse_flags(&args(
+            r#"/url https://example.com --expire 24h --header "Referer: https://example.com" --cookie session=abc --auth user:pa:ss"#
+        )).unwrap();
+
+        assert_eq!(flags.expires_in, Some(24 * 60 * 60));
+        assert_eq!(flags.request_options.headers, vec![("Referer".to_owned(), "https://example.com".to_owned())]);
+        assert_eq!(flags.request_options.cookies, vec!["session=abc".to_owned()]);
+        assert_eq!(flags.request_options.basic_auth, Some(("user".to_owned(), Some("pa:ss".to_owned()))));
+        assert!(!flags.strip_metadata);
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags_strip() {
+        let flags = parse_flags(&args("photo --strip --expire 1h")).unwrap();
+
+        assert!(flags.strip_metadata);
+        assert_eq!(flags.expires_in, Some(60 * 60));
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags_extract() {
+        let flags = parse_flags(&args("/url https://example.com/a.zip --extract")).unwrap();
+
+        assert!(flags.extract_archive);
+        assert!(!flags.strip_metadata);
+        assert_eq!(positional_args(&args("/url https://example.com/a.zip --extract")), vec!["/url", "https://example.com/a.zip"]);
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags_private() {
+        let flags = parse_flags(&args("document --private --expire 1h")).unwrap();
+
+        assert!(flags.private);
+        assert_eq!(flags.check_private(Some("secret"), Some("42")), Ok(()));
+        assert!(flags.check_private(None, Some("42")).is_err());
+        assert!(flags.check_private(Some("secret"), None).is_err());
+        assert_eq!(Flags::default().check_private(None, None), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags_deferred() {
+        let flags = parse_flags(&args("/url https://example.com --at 03:30")).unwrap();
+
+        assert_eq!(flags.at, Some(3 * 60 * 60 + 30 * 60));
+        assert!(!flags.later);
+
+        assert!(parse_flags(&args("/url https://example.com --later")).unwrap().later);
+        assert_eq!(positional_args(&args("/url https://a.com --at 03:00 --later")), vec!["/url", "https://a.com"]);
+    }
+
+    #[tokio::test]
+    async fn test_start_time() {
+        // 2023-02-01 05:25:40 UTC
+        let now = 1675229140;
+        let midnight = now - now % (24 * 60 * 60);
+        let window = OffPeakWindow::parse("01:00-06:00");
+
+        assert_eq!(Flags::default().start_time(window, now), Ok(None));
+
+        let at = parse_flags(&args("/url https://a.com --at 06:00")).unwrap();
+
+        assert_eq!(at.start_time(None, now), Ok(Some(midnight + 6 * 60 * 60)));
+
+        let later = parse_flags(&args("/url https://a.com --later")).unwrap();
+
+        // already in the window
+        assert_eq!(later.start_time(window, now), Ok(None));
+        assert_eq!(later.start_time(OffPeakWindow::parse("22:00-23:00"), now), Ok(Some(midnight + 22 * 60 * 60)));
+        assert!(later.start_time(None, now).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags_without_flags() {
+        assert_eq!(parse_flags(&args("/url https://example.com")), Ok(Flags::default()));
+        assert_eq!(parse_flags(&args("")), Ok(Flags::default()));
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags_auth_without_password() {
+        let flags = parse_flags(&args("/url https://example.com --auth token")).unwrap();
+
+        assert_eq!(flags.request_options.basic_auth, Some(("token".to_owned(), None)));
+    }
+
+    #[tokio::test]
+    async fn test_parse_flags_invalid() {
+        assert!(parse_flags(&args("/url https://example.com --expire")).is_err());
+        assert!(parse_flags(&args("/url https://example.com --expire soon")).is_err());
+        assert!(parse_flags(&args("/url https://example.com --header Referer")).is_err());
+        assert!(parse_flags(&args(r#"/url https://example.com --header "Bad Name: value""#)).is_err());
+        assert!(parse_flags(&args("/url https://example.com --cookie session")).is_err());
+        assert!(parse_flags(&args("/url https://example.com --auth")).is_err());
+        assert!(parse_flags(&args("/url https://example.com --at 3am")).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use log::debug;
+
+const JPEG_SIGNATURE: [u8; 2] = [0xFF, 0xD8];
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// APP1 holds EXIF and XMP, APP13 holds Photoshop IPTC data. JFIF (APP0) and ICC profiles (APP2) are kept.
+const JPEG_METADATA_MARKERS: [u8; 2] = [0xE1, 0xED];
+/// Start of scan, the compressed image data follows
+const JPEG_START_OF_SCAN: u8 = 0xDA;
+
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// Result of `strip_metadata`
+#[derive(Debug, PartialEq)]
+pub enum StripResult {
+    /// The image without its metadata
+    Stripped(Vec<u8>),
+    /// The image has no metadata to remove, or the data is not a JPEG or PNG image
+    Clean,
+    /// The image could not be read to the end, it may still hold metadata
+    Malformed,
+}
+
+/// Removes EXIF, XMP and similar metadata (GPS position, camera model, ...) from JPEG and PNG images
+pub fn strip_metadata(data: &[u8]) -> StripResult {
+    let stripped = if data.starts_with(&JPEG_SIGNATURE) {
+        strip_jpeg(data)
+    } else if data.starts_with(&PNG_SIGNATURE) {
+        strip_png(data)
+    } else {
+        return StripResult::Clean;
+    };
+
+    match stripped {
+        Some((stripped, true)) => StripResult::Stripped(stripped),
+        Some((_, false)) => StripResult::Clean,
+        None => StripResult::Malformed,
+    }
+}
+
+/// Checks if the file name has an extension of a supported image, other files are not read
+pub fn is_supported_image(file_name: &str) -> bool {
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase());
+
+    matches!(extension.as_deref(), Some("jpg" | "jpeg" | "png"))
+}
+
+/// # Returns
+/// * The image without metadata and whether anything was removed, `None` if the image is malformed
+fn strip_jpeg(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let mut stripped = JPEG_SIGNATURE.to_vec();
+    let mut position = JPEG_SIGNATURE.len();
+    let mut removed = false;
+
+    loop {
+        if *data.get(position)? != 0xFF {
+            debug!("Malformed JPEG, expected a marker at {}", position);
+
+            return None;
+        }
+
+        let marker = *data.get(position + 1)?;
+
+        // fill bytes before a marker
+        if marker == 0xFF {
+            position += 1;
+            continue;
+        }
+
+        if marker == JPEG_START_OF_SCAN {
+            stripped.extend_from_slice(&data[position..]);
+            break;
+        }
+
+        let length = u16::from_be_bytes([*data.get(position + 2)?, *data.get(position + 3)?]) as usize;
+        let end = position + 2 + length;
+
+        if length < 2 || end > data.len() {
+            debug!("Malformed JPEG, segment at {} is out of bounds", position);
+
+            return None;
+        }
+
+        if JPEG_METADATA_MARKERS.contains(&marker) {
+            removed = true;
+        } else {
+            stripped.extend_from_slice(&data[position..end]);
+        }
+
+        position = end;
+    }
+
+    Some((stripped, removed))
+}
+
+/// # Returns
+/// * The image without metadata and whether anything was removed, `None` if the image is malformed
+fn strip_png(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let mut stripped = PNG_SIGNATURE.to_vec();
+    let mut position = PNG_SIGNATURE.len();
+    let mut removed = false;
+
+    while position < data.len() {
+        let length = u32::from_be_bytes(data.get(position..position + 4)?.try_into().ok()?) as usize;
+        let chunk_type = data.get(position + 4..position + 8)?;
+        // length, type, data and CRC
+        let end = position + 12 + length;
+
+        if end > data.len() {
+            debug!("Malformed PNG, chunk at {} is out of bounds", position);
+
+            return None;
+        }
+
+        if PNG_METADATA_CHUNKS.iter().any(|metadata| metadata.as_slice() == chunk_type) {
+            removed = true;
+        } else {
+            stripped.extend_from_slice(&data[position..end]);
+        }
+
+        position = end;
+    }
+
+    Some((stripped, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg_segment(marker: u8, content: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&((content.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(content);
+        segment
+    }
+
+    fn png_chunk(chunk_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut chunk = (content.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(chunk_type);
+        chunk.extend_from_slice(content);
+        // the CRC is not checked
+        chunk.extend_from_slice(&[0, 0, 0, 0]);
+        chunk
+    }
+
+    #[tokio::test]
+    async fn test_strip_jpeg() {
+        let jfif = jpeg_segment(0xE0, b"JFIF\0");
+        let exif = jpeg_segment(0xE1, b"Exif\0\0GPS");
+        let scan = [0xFF, 0xDA, 0x00, 0x02, 0x12, 0xFF, 0xE1, 0xFF, 0xD9];
+
+        let image = [JPEG_SIGNATURE.to_vec(), jfif.clone(), exif, scan.to_vec()].concat();
+        let expected = [JPEG_SIGNATURE.to_vec(), jfif.clone(), scan.to_vec()].concat();
+
+        assert_eq!(strip_metadata(&image), StripResult::Stripped(expected.clone()));
+        assert_eq!(strip_metadata(&expected), StripResult::Clean);
+    }
+
+    #[tokio::test]
+    async fn test_strip_png() {
+        let header = png_chunk(b"IHDR", &[0; 13]);
+        let exif = png_chunk(b"eXIf", b"MM\0*GPS");
+        let end = png_chunk(b"IEND", &[]);
+
+        let image = [PNG_SIGNATURE.to_vec(), header.clone(), exif, end.clone()].concat();
+        let expected = [PNG_SIGNATURE.to_vec(), header, end].concat();
+
+        assert_eq!(strip_metadata(&image), StripResult::Stripped(expected.clone()));
+        assert_eq!(strip_metadata(&expected), StripResult::Clean);
+    }
+
+    #[tokio::test]
+    async fn test_strip_metadata_malformed() {
+        assert_eq!(strip_metadata(b"plain text"), StripResult::Clean);
+        assert_eq!(strip_metadata(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0xFF]), StripResult::Malformed);
+        assert_eq!(strip_metadata(&[PNG_SIGNATURE.as_slice(), &[0, 0, 1, 0, b'e']].concat()), StripResult::Malformed);
+
+        // the metadata before the broken segment is not a reason to serve the image
+        let exif = jpeg_segment(0xE1, b"Exif\0\0GPS");
+
+        assert_eq!(strip_metadata(&[JPEG_SIGNATURE.as_slice(), &exif, &[0xFF, 0xDB, 0x00]].concat()), StripResult::Malformed);
+    }
+
+    #[tokio::test]
+    async fn test_is_supported_image() {
+        assert!(is_supported_image("abc_photo.JPG"));
+        assert!(is_supported_image("abc_image.png"));
+        assert!(!is_supported_image("abc_video.mp4"));
+        assert!(!is_supported_image("abc_png"));
+    }
+}
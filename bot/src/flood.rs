@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use log::warn;
+use teloxide::requests::{Output, Request};
+use teloxide::RequestError;
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
+
+/// Requests refused with `RetryAfter` are sent again at most this many times
+const MAX_RETRIES: u32 = 3;
+
+/// Telegram limits the bot as a whole, so one flood wait holds back every request sent through `send`
+static BLOCKED_UNTIL: Mutex<Option<Instant>> = Mutex::const_new(None);
+
+/// Sends the request, waiting out Telegram flood control
+/// When Telegram answers with `RetryAfter`, all requests sent through this function wait for the given time
+/// before the request is sent again
+pub async fn send<R>(request: R) -> Result<Output<R>, RequestError>
+where
+    R: Request<Err = RequestError>,
+{
+    let mut retries = 0;
+
+    loop {
+        wait().await;
+
+        match request.send_ref().await {
+            Err(RequestError::RetryAfter(delay)) if retries < MAX_RETRIES => {
+                retries += 1;
+
+                warn!("Hit Telegram flood control, retrying in {:?} (retry {} of {})", delay, retries, MAX_RETRIES);
+
+                block_for(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Holds back requests for the given time, a longer wait which is already in place is kept
+async fn block_for(delay: Duration) {
+    let until = Instant::now() + delay;
+    let mut blocked_until = BLOCKED_UNTIL.lock().await;
+
+    *blocked_until = Some(blocked_until.map_or(until, |current| current.max(until)));
+}
+
+async fn wait() {
+    let blocked_until = *BLOCKED_UNTIL.lock().await;
+
+    if let Some(until) = blocked_until.filter(|until| *until > Instant::now()) {
+        sleep_until(until).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_block_for() {
+        let start = Instant::now();
+
+        block_for(Duration::from_millis(100)).await;
+        // a shorter wait does not cut the longer one
+        block_for(Duration::from_millis(10)).await;
+
+        wait().await;
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let start = Instant::now();
+
+        wait().await;
+
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use log::info;
+use shared::chat_config::PermissionsConfig;
+use shared::config::Config;
+use shared::listing::{owner_token, with_token};
+use shared::metadata::{FileMetadata, MetadataStore};
+use shared::usage::now_seconds;
+use teloxide::payloads::AnswerInlineQuerySetters;
+use teloxide::prelude::{InlineQuery, Requester};
+use teloxide::types::{InlineQueryResult, InlineQueryResultArticle, InputMessageContent, InputMessageContentText};
+use tokio::sync::Mutex;
+
+/// Telegram accepts at most 50 results per answer
+const PAGE_SIZE: usize = 20;
+
+/// Answers inline queries like "@file2link_bot report" with the matching files of the user
+pub async fn process_inline_query(
+    bot: Arc<teloxide::Bot>,
+    config: &Config,
+    query: InlineQuery,
+    metadata: Arc<Mutex<MetadataStore>>,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+) -> Result<(), Box<dyn Error>> {
+    let user_id = query.from.id.to_string();
+
+    if permissions.lock().await.is_banned(&user_id) {
+        info!("Banned user {} sent an inline query", user_id);
+
+        bot.answer_inline_query(query.id, Vec::new()).await?;
+
+        return Ok(());
+    }
+
+    let offset: usize = query.offset.parse().unwrap_or(0);
+    let file_domain = config.file_domain();
+    // the private files of the user are linked with their token
+    let token = config.listing_secret().map(|secret| owner_token(&secret, &user_id));
+
+    let (results, has_more) = {
+        let metadata = metadata.lock().await;
+
+        let files = metadata.search(&user_id, &query.query, now_seconds());
+
+        let results: Vec<InlineQueryResult> = files.iter()
+            .skip(offset)
+            .take(PAGE_SIZE)
+            .map(|file| file_result(file, &file_domain, token.as_deref()))
+            .collect();
+
+        (results, files.len() > offset + PAGE_SIZE)
+    };
+
+    let next_offset = if has_more { (offset + PAGE_SIZE).to_string() } else { String::new() };
+
+    bot.answer_inline_query(query.id, results)
+        .is_personal(true)
+        .cache_time(0)
+        .next_offset(next_offset)
+        .await?;
+
+    Ok(())
+}
+
+fn file_result(file: &FileMetadata, file_domain: &str, token: Option<&str>) -> InlineQueryResult {
+    let link = match (file.private, token) {
+        (true, Some(token)) => with_token(&format!("{}{}", file_domain, file.file_name), token),
+        _ => format!("{}{}", file_domain, file.file_name),
+    };
+
+    InlineQueryResult::Article(
+        InlineQueryResultArticle::new(
+            file.id(),
+            file.file_name.clone(),
+            InputMessageContent::Text(InputMessageContentText::new(link.clone())),
+        )
+            .description(format!("{} bytes\n{}", file.size, link))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_result() {
+        let mut file = FileMetadata {
+            file_name: "abcde_report.pdf".to_owned(),
+            id: None,
+            size: 10,
+            sha256: None,
+            file_unique_id: None,
+            created_at: 0,
+            expires_at: None,
+            downloaded_at: None,
+            owners: vec!["1".to_owned()],
+            chats: Vec::new(),
+            private: false,
+            content_addressed: false,
+            folder: None,
+            scanned: false,
+            mirror: None,
+            archive_entries: Vec::new(),
+            versions: Vec::new(),
+        };
+
+        match file_result(&file, "http://localhost:8080/files/", Some("ab12")) {
+            InlineQueryResult::Article(article) => {
+                assert_eq!(article.id, "abcde");
+                assert_eq!(article.title, "abcde_report.pdf");
+
+                match article.input_message_content {
+                    InputMessageContent::Text(text) => {
+                        assert_eq!(text.message_text, "http://localhost:8080/files/abcde_report.pdf");
+                    }
+                    _ => panic!("Expected a text message"),
+                }
+            }
+            _ => panic!("Expected an article"),
+        }
+
+        file.private = true;
+
+        match file_result(&file, "http://localhost:8080/files/", Some("ab12")) {
+            InlineQueryResult::Article(article) => match article.input_message_content {
+                InputMessageContent::Text(text) => {
+                    assert_eq!(text.message_text, "http://localhost:8080/files/abcde_report.pdf?token=ab12");
+                }
+                _ => panic!("Expected a text message"),
+            },
+            _ => panic!("Expected an article"),
+        }
+    }
+}
@@ -1,4 +1,22 @@
+pub mod admin;
+pub mod alerts;
+pub mod archive;
 pub mod bot;
+pub mod callbacks;
+pub mod cancel;
+pub mod command_args;
+pub mod exif;
+pub mod flood;
+pub mod inline;
+pub mod mylink;
+pub mod ping;
+pub mod post_process;
 pub mod queue;
+pub mod retry;
+pub mod scan;
+pub mod settings;
+pub mod telegram;
+pub mod throttle;
+pub mod unpack;
 pub mod process_message;
 
@@ -0,0 +1,29 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use shared::config::Config;
+use shared::listing::{listing_link, LISTING_LINK_LIFETIME};
+use shared::usage::now_seconds;
+use shared::utils::format_duration;
+use teloxide::prelude::Message;
+
+use crate::process_message::{reply_to, sender_id};
+
+/// Replies with a signed link to the page listing the files of the sender
+/// The link gives access to all files of the sender, so it is only sent in private chats
+pub async fn process_my_link(bot: Arc<teloxide::Bot>, config: &Config, msg: &Message) -> Result<(), Box<dyn Error>> {
+    let text = match (config.listing_secret(), sender_id(msg)) {
+        (None, _) => "Personal file lists are not enabled on this server.".to_owned(),
+        (Some(_), _) if !msg.chat.is_private() => "Send /mylink in a private chat with the bot.".to_owned(),
+        (Some(_), None) => "Could not determine who sent the command.".to_owned(),
+        (Some(secret), Some(owner)) => format!(
+            "Your files: {}\n\nThe link is valid for {}. Do not share it, anyone with the link can see your files.",
+            listing_link(&config.domain(), &secret, &owner, now_seconds()),
+            format_duration(LISTING_LINK_LIFETIME)
+        ),
+    };
+
+    reply_to(&bot, msg, text).await?;
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use log::warn;
+use shared::config::Config;
+use shared::utils::{create_directory, format_duration, format_size, get_free_space};
+use teloxide::prelude::Message;
+
+use crate::process_message::reply_to;
+use crate::queue::FileQueueType;
+
+struct BuildInfo {
+    version: &'static str,
+    commit: Option<&'static str>,
+    started_at: Instant,
+}
+
+static BUILD_INFO: OnceLock<BuildInfo> = OnceLock::new();
+
+/// Records the application version and the start time reported by /ping
+/// Only the first call has an effect
+///
+/// # Arguments
+/// * `version` - Version of the application crate
+/// * `commit` - Git commit the binary was built from, if known
+pub fn mark_started(version: &'static str, commit: Option<&'static str>) {
+    BUILD_INFO.get_or_init(|| BuildInfo {
+        version,
+        // an unset build argument gives an empty variable
+        commit: commit.filter(|commit| !commit.is_empty()),
+        started_at: Instant::now(),
+    });
+}
+
+/// Replies with the version, uptime, queue length and free space in the files directory
+pub async fn process_ping(
+    bot: Arc<teloxide::Bot>,
+    config: &Config,
+    msg: &Message,
+    file_queue: FileQueueType,
+) -> Result<(), Box<dyn Error>> {
+    let queue_length = file_queue.lock().await.len();
+
+    let files_dir = config.files_dir();
+
+    let free_space = match create_directory(&files_dir).await.and_then(|_| get_free_space(&files_dir)) {
+        Ok(free_space) => format_size(free_space),
+        Err(e) => {
+            warn!("Failed to get free space of '{}': {}", files_dir, e);
+
+            "unknown".to_owned()
+        }
+    };
+
+    reply_to(&bot, msg, ping_text(BUILD_INFO.get(), queue_length, &free_space)).await?;
+
+    Ok(())
+}
+
+fn ping_text(info: Option<&BuildInfo>, queue_length: usize, free_space: &str) -> String {
+    let version = match info {
+        Some(info) => format!("{} ({})", info.version, info.commit.unwrap_or("unknown commit")),
+        None => env!("CARGO_PKG_VERSION").to_owned(),
+    };
+
+    let uptime = info
+        .map(|info| format_duration(info.started_at.elapsed().as_secs()))
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    format!(
+        "Pong!\nVersion: {}\nUptime: {}\nQueue: {} file(s)\nFree space: {}",
+        version, uptime, queue_length, free_space
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ping_text() {
+        let info = BuildInfo {
+            version: "1.2.3",
+            commit: Some("abc1234"),
+            started_at: Instant::now(),
+        };
+
+        assert_eq!(
+            ping_text(Some(&info), 2, "1.5 GB"),
+            "Pong!\nVersion: 1.2.3 (abc1234)\nUptime: 0s\nQueue: 2 file(s)\nFree space: 1.5 GB"
+        );
+    }
+}
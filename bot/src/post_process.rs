@@ -0,0 +1,511 @@
+use std::io;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{debug, error, info, warn};
+use sha2::{Digest, Sha256};
+use shared::config::{ArchiveLimits, Config, PostProcessorKind};
+use shared::metadata::{ArchiveEntry, TelegramMirror};
+use teloxide::types::ChatId;
+
+use crate::exif::{self, StripResult};
+use crate::queue::{FileQueueItem, ProcessError, PUBLIC_API_URL};
+use crate::scan::{quarantine_file, remove_unscanned_file, ScanVerdict, Scanner};
+use crate::unpack::{self, ArchiveKind, ExtractedFile};
+
+/// A downloaded file, before it is handed to the storage and its link is sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadedFile {
+    /// Random ID in the file name, see `FileNaming`
+    pub id: String,
+    pub file_name: String,
+    /// Local path of the file until it is handed to the storage
+    pub path: String,
+    pub size: u64,
+    /// Hex encoded SHA-256 of the content
+    pub sha256: String,
+    /// The virus scanner found the file clean
+    pub scanned: bool,
+    /// Files in the archive, see `PostProcessorKind::Archive`
+    pub archive_entries: Vec<ArchiveEntry>,
+    /// Files extracted from the archive, they are stored with links of their own
+    pub extracted: Vec<ExtractedFile>,
+    /// Why the archive sent with `--extract` was not extracted
+    pub not_extracted: Option<String>,
+    /// Copy uploaded to `MIRROR_CHAT_ID`, see `PostProcessorKind::Mirror`
+    pub mirror: Option<TelegramMirror>,
+}
+
+impl DownloadedFile {
+    pub fn new(id: String, file_name: String, path: String, size: u64, sha256: String) -> Self {
+        Self {
+            id,
+            file_name,
+            path,
+            size,
+            sha256,
+            scanned: false,
+            archive_entries: Vec::new(),
+            extracted: Vec::new(),
+            not_extracted: None,
+            mirror: None,
+        }
+    }
+}
+
+/// A step run on every downloaded file, see `PostProcessors`
+/// A processor which changes the content updates the size and hash of the file. A processor which rejects the file
+/// removes or moves it first, it must not be served.
+pub trait PostProcessor: Send + Sync {
+    /// Name of the step in the logs
+    fn name(&self) -> &'static str;
+
+    fn process<'a>(&'a self, file: &'a mut DownloadedFile, queue_item: &'a FileQueueItem) -> BoxFuture<'a, Result<(), ProcessError>>;
+}
+
+/// Steps run in order on a file after it is downloaded, configured with `POST_PROCESSORS`
+/// The first step to fail stops the pipeline and the file fails with its error.
+#[derive(Default)]
+pub struct PostProcessors {
+    processors: Vec<Box<dyn PostProcessor>>,
+}
+
+impl PostProcessors {
+    pub fn new(processors: Vec<Box<dyn PostProcessor>>) -> Self {
+        Self { processors }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let processors = config.post_processors()
+            .into_iter()
+            .filter_map(|kind| -> Option<Box<dyn PostProcessor>> {
+                match kind {
+                    PostProcessorKind::StripMetadata => Some(Box::new(StripMetadata)),
+                    PostProcessorKind::Scan => Scanner::from_config(config).map(|scanner| Box::new(Scan { scanner }) as _),
+                    PostProcessorKind::Archive => Some(Box::new(Archive { limits: config.archive_limits() })),
+                    PostProcessorKind::Mirror => config.mirror_chat_id().map(|chat_id| {
+                        Box::new(Mirror { chat_id, max_size: mirror_size_limit(&config.telegram_api_url()) }) as _
+                    }),
+                }
+            })
+            .collect();
+
+        Self::new(processors)
+    }
+
+    /// Names of the steps, in the order they run
+    pub fn names(&self) -> Vec<&'static str> {
+        self.processors.iter().map(|processor| processor.name()).collect()
+    }
+
+    pub async fn run(&self, file: &mut DownloadedFile, queue_item: &FileQueueItem) -> Result<(), ProcessError> {
+        for processor in &self.processors {
+            debug!("[{}] Running {} on {}", queue_item.short_id(), processor.name(), file.file_name);
+
+            processor.process(file, queue_item).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes EXIF and similar metadata from photos of files sent with `--strip`, other files are left as they are
+/// The stripped image is written to a new file, so a hardlink to a local Bot API server file is not changed
+/// A photo which is malformed or bigger than `MAX_STRIP_SIZE` is removed and fails, it may still hold the metadata
+struct StripMetadata;
+
+/// Biggest photo read into memory to remove its metadata
+const MAX_STRIP_SIZE: u64 = 100 * 1024 * 1024;
+
+impl PostProcessor for StripMetadata {
+    fn name(&self) -> &'static str {
+        "strip_metadata"
+    }
+
+    fn process<'a>(&'a self, file: &'a mut DownloadedFile, queue_item: &'a FileQueueItem) -> BoxFuture<'a, Result<(), ProcessError>> {
+        async move {
+            if !queue_item.strip_metadata() || !exif::is_supported_image(&file.file_name) {
+                return Ok(());
+            }
+
+            let path = file.path.clone();
+            let size = file.size;
+
+            let result: io::Result<Option<(u64, String)>> = async {
+                if size > MAX_STRIP_SIZE {
+                    return Err(io::Error::other(format!("the image is bigger than {} bytes", MAX_STRIP_SIZE)));
+                }
+
+                let data = tokio::fs::read(&path).await?;
+
+                // the image is parsed and hashed off the async workers
+                let stripped = tokio::task::spawn_blocking(move || match exif::strip_metadata(&data) {
+                    StripResult::Stripped(stripped) => {
+                        let sha256 = hex::encode(Sha256::digest(&stripped));
+
+                        Ok(Some((stripped, sha256)))
+                    }
+                    StripResult::Clean => Ok(None),
+                    StripResult::Malformed => Err(io::Error::new(io::ErrorKind::InvalidData, "the image is malformed")),
+                }).await.unwrap_or_else(|e| Err(io::Error::other(e)))?;
+
+                let (stripped, sha256) = match stripped {
+                    Some(stripped) => stripped,
+                    None => return Ok(None),
+                };
+
+                let stripped_path = format!("{}.stripped", path);
+                let hashed = (stripped.len() as u64, sha256);
+
+                tokio::fs::write(&stripped_path, stripped).await?;
+                tokio::fs::rename(&stripped_path, &path).await?;
+
+                info!("Removed metadata from {}", file.file_name);
+
+                Ok(Some(hashed))
+            }.await;
+
+            match result {
+                Ok(Some((size, sha256))) => {
+                    file.size = size;
+                    file.sha256 = sha256;
+
+                    Ok(())
+                }
+                Ok(None) => Ok(()),
+                Err(e) => {
+                    error!("Failed to remove metadata from {}: {}", file.file_name, e);
+
+                    // the photo must not be served with the metadata the uploader wanted removed
+                    let _ = tokio::fs::remove_file(&path).await;
+
+                    Err(ProcessError::Other(format!("Failed to remove metadata: {}", e)))
+                }
+            }
+        }.boxed()
+    }
+}
+
+/// Scans the file with clamd or `SCAN_COMMAND`. Infected files are moved to quarantine, files which could not be
+/// scanned are removed.
+struct Scan {
+    scanner: Scanner,
+}
+
+impl PostProcessor for Scan {
+    fn name(&self) -> &'static str {
+        "scan"
+    }
+
+    fn process<'a>(&'a self, file: &'a mut DownloadedFile, _queue_item: &'a FileQueueItem) -> BoxFuture<'a, Result<(), ProcessError>> {
+        async move {
+            match self.scanner.scan(&file.path).await {
+                Ok(ScanVerdict::Clean) => {
+                    debug!("File {} is clean", file.file_name);
+
+                    file.scanned = true;
+
+                    Ok(())
+                }
+                Ok(ScanVerdict::Infected(report)) => {
+                    warn!("File {} is infected: {}", file.file_name, report);
+
+                    if let Err(e) = quarantine_file(&file.path, &file.file_name).await {
+                        error!("Failed to quarantine file {}, removing it: {}", file.file_name, e);
+
+                        remove_unscanned_file(&file.path).await;
+                    }
+
+                    Err(ProcessError::Quarantined(format!("{}: {}", file.file_name, report)))
+                }
+                Err(e) => {
+                    remove_unscanned_file(&file.path).await;
+
+                    Err(ProcessError::Other(e))
+                }
+            }
+        }.boxed()
+    }
+}
+
+/// Lists the files in zip and tar archives and extracts archives sent with `--extract`, see `unpack`
+/// An archive which can not be read or is over the limits is stored as it is
+struct Archive {
+    limits: ArchiveLimits,
+}
+
+impl PostProcessor for Archive {
+    fn name(&self) -> &'static str {
+        "archive"
+    }
+
+    fn process<'a>(&'a self, file: &'a mut DownloadedFile, queue_item: &'a FileQueueItem) -> BoxFuture<'a, Result<(), ProcessError>> {
+        async move {
+            let kind = match ArchiveKind::from_file_name(&file.file_name) {
+                Some(kind) => kind,
+                None => return Ok(()),
+            };
+
+            let path = file.path.clone();
+            let limits = self.limits;
+
+            if queue_item.extract_archive() {
+                let extracted = tokio::task::spawn_blocking(move || unpack::extract(&path, kind, limits))
+                    .await.unwrap_or_else(|e| Err(e.to_string()));
+
+                match extracted {
+                    Ok((entries, extracted)) => {
+                        info!("Extracted {} file(s) from {}", extracted.len(), file.file_name);
+
+                        file.archive_entries = entries;
+                        file.extracted = extracted;
+
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        warn!("Failed to extract {}: {}", file.file_name, e);
+
+                        let _ = tokio::fs::remove_dir_all(unpack::extract_dir(&file.path)).await;
+
+                        file.not_extracted = Some(e);
+                    }
+                }
+            }
+
+            let path = file.path.clone();
+
+            match tokio::task::spawn_blocking(move || unpack::list(&path, kind, limits)).await.unwrap_or_else(|e| Err(e.to_string())) {
+                Ok(entries) => file.archive_entries = entries,
+                Err(e) => warn!("Failed to list the files in {}: {}", file.file_name, e),
+            }
+
+            Ok(())
+        }.boxed()
+    }
+}
+
+/// Biggest file the Bot API server accepts from a bot: 50 MB for the public Bot API, 2000 MB for a local server
+fn mirror_size_limit(api_url: &str) -> u64 {
+    match api_url.trim_end_matches('/') == PUBLIC_API_URL {
+        true => 50 * 1000 * 1000,
+        false => 2000 * 1000 * 1000,
+    }
+}
+
+/// Uploads the file to `MIRROR_CHAT_ID` as a backup kept by Telegram, the message ID is stored in the metadata
+/// Files over the upload limit of the Bot API server are skipped. A failed upload does not fail the file.
+struct Mirror {
+    chat_id: i64,
+    max_size: u64,
+}
+
+impl PostProcessor for Mirror {
+    fn name(&self) -> &'static str {
+        "mirror"
+    }
+
+    fn process<'a>(&'a self, file: &'a mut DownloadedFile, queue_item: &'a FileQueueItem) -> BoxFuture<'a, Result<(), ProcessError>> {
+        async move {
+            if file.size > self.max_size {
+                debug!("File {} is too big to mirror", file.file_name);
+
+                return Ok(());
+            }
+
+            match queue_item.client().send_file(ChatId(self.chat_id), &file.path, &file.file_name).await {
+                Ok(message_id) => {
+                    info!("Mirrored {} to chat {}", file.file_name, self.chat_id);
+
+                    file.mirror = Some(TelegramMirror { chat_id: self.chat_id, message_id: message_id.0 });
+                }
+                Err(e) => {
+                    warn!("Failed to mirror {} to chat {}: {}", file.file_name, self.chat_id, e);
+                }
+            }
+
+            Ok(())
+        }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::stream::BoxStream;
+    use futures::StreamExt;
+    use reqwest::Url;
+    use shared::config::SharedConfig;
+    use teloxide::types::{InlineKeyboardMarkup, Message, MessageId};
+    use teloxide::{ApiError, RequestError};
+    use tokio_util::bytes::Bytes;
+
+    use crate::queue::DownloadSource;
+    use crate::telegram::{RangedStream, TelegramClient};
+
+    use super::*;
+
+    fn queue_item() -> FileQueueItem {
+        let message: Message = serde_json::from_str(
+            r#"{"chat":{"id":42,"type":"private","first_name":"user"},"from":{"id":42,"is_bot":false,"first_name":"user"},"date":1675229140,"message_id":1,"text":"/url https://example.com/a.jpg"}"#,
+        ).unwrap();
+        let message = Arc::new(message);
+
+        FileQueueItem::new(
+            Arc::new(teloxide::Bot::new("token")),
+            SharedConfig::new(Config::default()),
+            message.clone(),
+            message,
+            DownloadSource::Url(Url::parse("https://example.com/a.jpg").unwrap()),
+        )
+    }
+
+    /// Records that it ran and fails if asked to
+    struct Step {
+        name: &'static str,
+        fail: bool,
+        ran: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl PostProcessor for Step {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn process<'a>(&'a self, file: &'a mut DownloadedFile, _queue_item: &'a FileQueueItem) -> BoxFuture<'a, Result<(), ProcessError>> {
+            async move {
+                self.ran.lock().unwrap().push(self.name);
+                file.size += 1;
+
+                match self.fail {
+                    true => Err(ProcessError::Other(format!("{} failed", self.name))),
+                    false => Ok(()),
+                }
+            }.boxed()
+        }
+    }
+
+    fn step(name: &'static str, fail: bool, ran: &Arc<Mutex<Vec<&'static str>>>) -> Box<dyn PostProcessor> {
+        Box::new(Step { name, fail, ran: ran.clone() })
+    }
+
+    #[tokio::test]
+    async fn test_post_processors_run_in_order() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let processors = PostProcessors::new(vec![step("first", false, &ran), step("second", false, &ran)]);
+
+        let mut file = DownloadedFile::new("abcde".to_owned(), "a.jpg".to_owned(), "files/.tmp/a.jpg".to_owned(), 10, "hash".to_owned());
+
+        assert_eq!(processors.run(&mut file, &queue_item()).await, Ok(()));
+        assert_eq!(*ran.lock().unwrap(), vec!["first", "second"]);
+        assert_eq!(file.size, 12);
+    }
+
+    #[tokio::test]
+    async fn test_post_processors_stop_at_failure() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let processors = PostProcessors::new(vec![step("first", true, &ran), step("second", false, &ran)]);
+
+        let mut file = DownloadedFile::new("abcde".to_owned(), "a.jpg".to_owned(), "files/.tmp/a.jpg".to_owned(), 10, "hash".to_owned());
+
+        assert_eq!(processors.run(&mut file, &queue_item()).await, Err(ProcessError::Other("first failed".to_owned())));
+        assert_eq!(*ran.lock().unwrap(), vec!["first"]);
+    }
+
+    #[tokio::test]
+    async fn test_strip_metadata_skips_files_sent_without_strip() {
+        let mut file = DownloadedFile::new("abcde".to_owned(), "file2link-missing.jpg".to_owned(), "files/.tmp/file2link-missing.jpg".to_owned(), 10, "hash".to_owned());
+
+        assert_eq!(StripMetadata.process(&mut file, &queue_item()).await, Ok(()));
+        assert_eq!(file, DownloadedFile::new("abcde".to_owned(), "file2link-missing.jpg".to_owned(), "files/.tmp/file2link-missing.jpg".to_owned(), 10, "hash".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_strip_metadata_removes_malformed_photo() {
+        let path = std::env::temp_dir().join("file2link_test_malformed.jpg").to_str().unwrap().to_owned();
+
+        // the EXIF segment is cut off, it can not be removed
+        tokio::fs::write(&path, [0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x20, b'E', b'x', b'i', b'f']).await.unwrap();
+
+        let mut file = DownloadedFile::new("abcde".to_owned(), "malformed.jpg".to_owned(), path.clone(), 10, "hash".to_owned());
+
+        assert!(StripMetadata.process(&mut file, &queue_item().with_strip_metadata(true)).await.is_err());
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_post_processors_from_config() {
+        let config = Config::from_variables([("POST_PROCESSORS", "scan,strip_metadata")]);
+
+        assert_eq!(PostProcessors::from_config(&config).names(), vec!["strip_metadata"]);
+
+        let config = Config::from_variables([("POST_PROCESSORS", "scan,strip_metadata"), ("SCAN_COMMAND", "clamdscan")]);
+
+        assert_eq!(PostProcessors::from_config(&config).names(), vec!["scan", "strip_metadata"]);
+
+        let config = Config::from_variables([("POST_PROCESSORS", "scan,strip_metadata"), ("CLAMD_ADDRESS", "127.0.0.1:3310")]);
+
+        assert_eq!(PostProcessors::from_config(&config).names(), vec!["scan", "strip_metadata"]);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_from_config() {
+        let config = Config::from_variables([("POST_PROCESSORS", "mirror")]);
+
+        assert!(PostProcessors::from_config(&config).names().is_empty());
+
+        let config = Config::from_variables([("POST_PROCESSORS", "mirror"), ("MIRROR_CHAT_ID", "-1009876543210")]);
+
+        assert_eq!(PostProcessors::from_config(&config).names(), vec!["mirror"]);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_size_limit() {
+        assert_eq!(mirror_size_limit("https://api.telegram.org/"), 50 * 1000 * 1000);
+        assert_eq!(mirror_size_limit("http://localhost:8081/"), 2000 * 1000 * 1000);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_skips_big_files() {
+        let mirror = Mirror { chat_id: -1009876543210, max_size: 10 };
+        let mut file = DownloadedFile::new("abcde".to_owned(), "a.jpg".to_owned(), "files/.tmp/a.jpg".to_owned(), 11, "hash".to_owned());
+
+        assert_eq!(mirror.process(&mut file, &queue_item()).await, Ok(()));
+        assert_eq!(file.mirror, None);
+    }
+
+    /// Accepts every upload as message 7
+    #[derive(Debug)]
+    struct Uploads;
+
+    impl TelegramClient for Uploads {
+        fn edit_text(&self, _chat_id: ChatId, _message_id: MessageId, _text: String, _keyboard: Option<InlineKeyboardMarkup>) -> BoxFuture<'_, Result<(), RequestError>> {
+            async { Ok(()) }.boxed()
+        }
+
+        fn file_info<'a>(&'a self, _file_id: &'a str) -> BoxFuture<'a, Result<(String, u64), RequestError>> {
+            async { Err(RequestError::Api(ApiError::FileIdInvalid)) }.boxed()
+        }
+
+        fn file_stream(&self, _path: &str) -> BoxStream<'static, Result<Bytes, reqwest::Error>> {
+            futures::stream::empty().boxed()
+        }
+
+        fn file_stream_from<'a>(&'a self, _path: &'a str, _offset: u64) -> BoxFuture<'a, Result<RangedStream, String>> {
+            async { Err("404 Not Found".to_owned()) }.boxed()
+        }
+
+        fn send_file<'a>(&'a self, _chat_id: ChatId, _path: &'a str, _file_name: &'a str) -> BoxFuture<'a, Result<MessageId, RequestError>> {
+            async { Ok(MessageId(7)) }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mirror_stores_message_id() {
+        let mirror = Mirror { chat_id: -1009876543210, max_size: 10 };
+        let mut file = DownloadedFile::new("abcde".to_owned(), "a.jpg".to_owned(), "files/.tmp/a.jpg".to_owned(), 10, "hash".to_owned());
+
+        assert_eq!(mirror.process(&mut file, &queue_item().with_client(Arc::new(Uploads))).await, Ok(()));
+        assert_eq!(file.mirror, Some(TelegramMirror { chat_id: -1009876543210, message_id: 7 }));
+    }
+}
@@ -1,136 +1,591 @@
 use std::error::Error;
 use std::sync::Arc;
 
-use crate::queue::{FileQueueItem, FileQueueType};
-use log::{debug, info};
+use crate::command_args::{parse_flags, positional_args, split_args};
+use crate::flood;
+use crate::queue::{new_short_id, notify_queue, probe_size, queue_status_text, save_queue, BatchStatus, DownloadSource, FileQueueItem, FileQueueType, QueueEvent, RequestOptions};
+use futures::future::join_all;
+use log::{debug, error, info, warn};
 use regex::Regex;
-use teloxide::payloads::SendMessageSetters;
+use reqwest::Url;
+use shared::config::{Config, QueueOrder, SharedConfig};
+use shared::chat_settings::SettingsStore;
+use shared::templates::{render, Language, MessageTemplates};
+use shared::usage::{now_seconds, UsageTracker};
+use teloxide::payloads::{SendMessage, SendMessageSetters};
 use teloxide::prelude::{Message, Requester};
+use teloxide::requests::JsonRequest;
+use teloxide::types::{FileMeta, MessageKind, ParseMode, UserId};
+use teloxide::utils::html;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
 
-/// Get URL from a message
-/// Returns the first URL found in the message
-/// If the message starts with "/url", it will return the URL from the reply message
-/// If the message starts with "/url <URL>", it will return the URL
-/// If no URL is found, it will return None
+struct FileInfo {
+    source: DownloadSource,
+    expires_in: Option<u64>,
+    request_options: RequestOptions,
+    strip_metadata: bool,
+    extract_archive: bool,
+    private: bool,
+    /// Unix time the download may start at, see `Flags::start_time`
+    not_before: Option<u64>,
+    /// Size known before the download, see `QueueOrder::SmallestFirst`
+    size: Option<u64>,
+}
+
+impl FileInfo {
+    fn telegram(file: &FileMeta, file_name: Option<String>) -> Self {
+        Self {
+            source: DownloadSource::Telegram {
+                file_id: file.id.clone(),
+                file_unique_id: Some(file.unique_id.clone()),
+                file_name,
+            },
+            expires_in: None,
+            request_options: RequestOptions::default(),
+            strip_metadata: false,
+            extract_archive: false,
+            private: false,
+            not_before: None,
+            size: Some(u64::from(file.size)),
+        }
+    }
+
+    /// A file given by its raw Telegram file ID, the unique ID is not known
+    fn file_id(file_id: String, file_name: Option<String>) -> Self {
+        Self {
+            source: DownloadSource::Telegram {
+                file_id,
+                file_unique_id: None,
+                file_name,
+            },
+            expires_in: None,
+            request_options: RequestOptions::default(),
+            strip_metadata: false,
+            extract_archive: false,
+            private: false,
+            not_before: None,
+            size: None,
+        }
+    }
+
+    fn url(url: Url) -> Self {
+        Self {
+            source: DownloadSource::Url(url),
+            expires_in: None,
+            request_options: RequestOptions::default(),
+            strip_metadata: false,
+            extract_archive: false,
+            private: false,
+            not_before: None,
+            size: None,
+        }
+    }
+}
+
+/// Get URLs from a "/url" command
+/// Returns the URLs from the command arguments and from the reply message
+/// If no URL is found, it will return an empty list
 ///
 /// # Arguments
 /// * `msg` - Message
+/// * `args` - Command arguments without flags, see `positional_args`
 /// # Returns
-/// * `Option<String>` containing the URL
-/// * `None` if no URL is found
-/// # Example
-fn get_url_from_message(msg: &Message) -> Option<String> {
-    fn extract_first_link(text: &str) -> Option<String> {
+/// * `Vec<String>` containing the unique URLs in the order they appear
+fn get_urls_from_message(msg: &Message, args: &[String]) -> Vec<String> {
+    fn extract_links(text: &str) -> Vec<String> {
         let link_regex = Regex::new(r"https?://\S+").unwrap();
 
-        if let Some(mat) = link_regex.find(text) {
-            Some(mat.as_str().to_string())
-        } else {
-            None
-        }
+        link_regex.find_iter(text)
+            .map(|mat| mat.as_str().to_string())
+            .collect()
     }
 
-    if let Some(text) = msg.text() {
-        if text.starts_with("/url") {
-            if text.len() < 6 {
-                if let Some(reply) = msg.reply_to_message() {
-                    if let Some(reply_text) = reply.text() {
-                        return extract_first_link(reply_text);
-                    }
-                }
-            } else {
-                let url_text = &text[5..];
+    let mut urls: Vec<String> = Vec::new();
 
-                return extract_first_link(url_text);
-            }
+    let links = args.iter()
+        .flat_map(|arg| extract_links(arg))
+        .chain(msg.reply_to_message().and_then(|reply| reply.text()).map(extract_links).unwrap_or_default());
+
+    for link in links {
+        if !urls.contains(&link) {
+            urls.push(link);
         }
     }
 
-    None
+    urls
+}
+
+/// Get the file from a "/fileid <file_id> [name]" command
+///
+/// # Arguments
+/// * `args` - Command arguments without flags, see `positional_args`
+/// # Returns
+/// * `None` if the file ID is missing
+fn get_file_from_args(args: &[String]) -> Option<FileInfo> {
+    let file_id = args.get(1)?;
+
+    // the name becomes part of the stored file path
+    let file_name = Some(args[2..].join(" ").replace('/', "_"))
+        .filter(|name| !name.trim().is_empty());
+
+    Some(FileInfo::file_id(file_id.to_owned(), file_name))
+}
+
+/// Get the forum topic of a message
+/// Replies in regular groups also have a thread ID, it is only a topic in forum groups
+pub fn topic_id(msg: &Message) -> Option<i32> {
+    match &msg.kind {
+        MessageKind::Common(common) if common.is_topic_message => msg.thread_id,
+        _ => None,
+    }
+}
+
+/// Checks if the message is meant for the bot: a command, a mention of the bot or a reply to a message of the bot
+///
+/// # Arguments
+/// * `bot_id` - User ID of the bot
+/// * `username` - Username of the bot without the "@"
+pub fn is_addressed(msg: &Message, bot_id: UserId, username: &str) -> bool {
+    let text = msg.text().or(msg.caption()).unwrap_or_default();
+
+    let is_command = text.starts_with('/');
+    let is_mention = text.to_lowercase().contains(&format!("@{}", username.to_lowercase()));
+    let is_reply = msg.reply_to_message()
+        .and_then(|reply| reply.from())
+        .is_some_and(|from| from.id == bot_id);
+
+    is_command || is_mention || is_reply
+}
+
+/// Builds a reply to the message, sent into the same forum topic
+pub fn reply_to(bot: &teloxide::Bot, msg: &Message, text: String) -> JsonRequest<SendMessage> {
+    let request = bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id);
+
+    match topic_id(msg) {
+        Some(topic_id) => request.message_thread_id(topic_id),
+        None => request,
+    }
+}
+
+/// Get the ID the quota is counted for
+/// Posts on behalf of a chat (channel posts, anonymous admins) are counted for the chat
+pub fn sender_id(msg: &Message) -> Option<String> {
+    msg.sender_chat()
+        .map(|chat| chat.id.to_string())
+        .or_else(|| msg.from().map(|from| from.id.to_string()))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_message(
     bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
     msg: Message,
     file_queue: FileQueueType,
-    tx: Sender<()>,
+    usage: Arc<Mutex<UsageTracker>>,
+    settings: Arc<Mutex<SettingsStore>>,
+    tx: Sender<QueueEvent>,
+    hint: bool,
 ) -> Result<(), Box<dyn Error>> {
     let msg_copy = Arc::new(msg.clone());
 
-    let file_info = if let Some(document) = msg_copy.document() {
+    let args = split_args(msg_copy.text().or(msg_copy.caption()).unwrap_or_default());
+
+    let mut files = if let Some(document) = msg_copy.document() {
         info!("Processing document file with ID: {}", document.file.id);
 
-        Some((Some(document.file.id.clone()), document.file_name.clone(), None))
+        vec![FileInfo::telegram(&document.file, document.file_name.clone())]
     } else if let Some(photo) = msg_copy.photo().and_then(|p| p.last()) {
         info!("Processing photo file with ID: {}", photo.file.id);
 
-        Some((Some(photo.file.id.clone()), None, None))
+        vec![FileInfo::telegram(&photo.file, None)]
     } else if let Some(video) = msg_copy.video() {
         info!("Processing video file with ID: {}", video.file.id);
 
-        Some((Some(video.file.id.clone()), video.file_name.clone(), None))
+        vec![FileInfo::telegram(&video.file, video.file_name.clone())]
     } else if let Some(animation) = msg_copy.animation() {
         info!("Processing animation file with ID: {}", animation.file.id);
 
-        Some((Some(animation.file.id.clone()), animation.file_name.clone(), None))
+        vec![FileInfo::telegram(&animation.file, animation.file_name.clone())]
     } else if let Some(text) = msg_copy.text() {
         if text.starts_with("/url") {
-            if let Some(url) = get_url_from_message(&msg_copy) {
-                Some((None, None, Some(url)))
-            } else {
-                None
+            if !config.get().features().url_downloads {
+                reply_to(&bot, &msg_copy, "Downloading links is disabled.".to_owned()).await?;
+
+                return Ok(());
+            }
+
+            get_urls_from_message(&msg_copy, &positional_args(&args)).into_iter()
+                .filter_map(|url| match Url::parse(&url) {
+                    Ok(url) => Some(FileInfo::url(url)),
+                    Err(e) => {
+                        info!("Skipping invalid URL {}: {}", url, e);
+
+                        None
+                    }
+                })
+                .collect()
+        } else if text.starts_with("/fileid") {
+            match get_file_from_args(&positional_args(&args)) {
+                Some(file) => vec![file],
+                None => {
+                    reply_to(&bot, &msg_copy, "Usage: /fileid <file_id> [name]".to_owned()).await?;
+
+                    return Ok(());
+                }
             }
         } else {
-            None
+            Vec::new()
         }
     } else {
-        None
+        Vec::new()
     };
 
-    if let Some((file_id, file_name, url)) = file_info {
-        handle_file(
+    let chat_settings = settings.lock().await.get(&msg_copy.chat.id.to_string());
+
+    if !files.is_empty() {
+        let current_config = config.get();
+        let window = current_config.off_peak_window();
+        let secret = current_config.listing_secret();
+        let owner = sender_id(&msg_copy);
+
+        let flags = parse_flags(&args).and_then(|flags| {
+            flags.check_private(secret.as_deref(), owner.as_deref())?;
+
+            Ok((flags.start_time(window, now_seconds())?, flags))
+        });
+
+        match flags {
+            Ok((not_before, flags)) => files.iter_mut().for_each(|file| {
+                file.expires_in = flags.expires_in.or(chat_settings.expires_in);
+                file.request_options = flags.request_options.clone();
+                file.strip_metadata = flags.strip_metadata || chat_settings.strip_metadata;
+                file.extract_archive = flags.extract_archive;
+                file.private = flags.private;
+                file.not_before = not_before;
+            }),
+            Err(e) => {
+                reply_to(&bot, &msg_copy, e).await?;
+
+                return Ok(());
+            }
+        }
+
+        let files_count = files.len() as u64;
+        let sender = sender_id(&msg_copy);
+
+        if let Some(sender_id) = &sender {
+            let mut usage = usage.lock().await;
+
+            // files are recorded once stored, the ones still in the queue count against the quota too
+            let queued = file_queue.lock().await.queued_by(sender_id);
+
+            if let Err(exceeded) = usage.check(sender_id, queued + files_count, &current_config.quota_limits(), now_seconds()) {
+                info!("User {} exceeded daily quota", sender_id);
+
+                reply_to(&bot, &msg_copy, exceeded.message()).await?;
+
+                return Ok(());
+            }
+
+            if let Some(limit) = current_config.submissions_per_minute() {
+                if let Err(limited) = usage.submit(sender_id, files_count, limit, now_seconds()) {
+                    info!("User {} exceeded the rate limit", sender_id);
+
+                    reply_to(&bot, &msg_copy, limited.message()).await?;
+
+                    return Ok(());
+                }
+            }
+
+            // counted until `handle_files` has queued them, so another message of the user can not pass the check meanwhile
+            usage.reserve(sender_id, files_count);
+        }
+
+        if current_config.queue_order() == QueueOrder::SmallestFirst {
+            probe_sizes(&current_config, &mut files).await;
+        }
+
+        let queued = handle_files(
             bot.clone(),
+            config,
             msg_copy.clone(),
-            file_id,
-            file_name,
-            url,
+            files,
             file_queue,
+            chat_settings.language,
             &tx,
-        ).await.expect("Failed to process file");
+        ).await.map_err(|e| e.to_string());
+
+        // the queued files are counted by the queue now, refused ones are not counted at all
+        if let Some(sender_id) = &sender {
+            usage.lock().await.release(sender_id, files_count);
+        }
+
+        // the error is not Send, only its message is kept across the reply
+        if let Err(e) = queued {
+            error!("Failed to queue files of message {}: {}", msg_copy.id, e);
+
+            reply_to(&bot, &msg_copy, "Failed to queue the file. Please try again later.".to_owned()).await?;
+        }
     } else {
         debug!("Received a non-file message");
+
+        // service messages like "user joined" are not sent by the user, they never get a hint
+        if hint && matches!(msg_copy.kind, MessageKind::Common(_)) {
+            let hint_text = MessageTemplates::for_language(chat_settings.language).await.unsupported.clone();
+
+            reply_to(&bot, &msg_copy, hint_text).await?;
+        }
     }
 
     Ok(())
 }
 
-async fn handle_file(
+/// Looks up the sizes of the URLs, so they are queued behind the smaller files
+async fn probe_sizes(config: &Config, files: &mut [FileInfo]) {
+    let probes = files.iter_mut()
+        .filter(|file| file.size.is_none())
+        .filter_map(|file| {
+            let url = file.source.url()?.clone();
+
+            Some(async move {
+                file.size = probe_size(config, url.as_str(), &file.request_options).await;
+            })
+        });
+
+    join_all(probes).await;
+}
+
+/// Adds the files to the queue and replies with their queue positions
+/// Several files share one status message with a line per file
+async fn handle_files(
     bot: Arc<teloxide::Bot>,
+    config: SharedConfig,
     msg: Arc<Message>,
-    file_id: Option<String>,
-    file_name: Option<String>,
-    url: Option<String>,
+    mut files: Vec<FileInfo>,
     file_queue: FileQueueType,
-    tx: &Sender<()>,
+    language: Language,
+    tx: &Sender<QueueEvent>,
 ) -> Result<(), Box<dyn Error>> {
-    {
-        let mut queue = file_queue.lock().await;
+    // the positions and IDs are reserved under the lock, the messages are sent without it
+    let (duplicates, positions) = {
+        let queue = file_queue.lock().await;
 
-        let position = queue.len() + 1;
+        if queue.is_closed() {
+            drop(queue);
 
-        let queue_message = bot.send_message(msg.chat.id, format!("Queue position: {}", position))
-            .reply_to_message_id(msg.id)
-            .await.expect("Failed to send message");
+            info!("Refusing files of message {}, the queue is shutting down", msg.id);
 
-        let queue_message_clone = Arc::new(queue_message);
+            reply_to(&bot, &msg, "The bot is restarting. Please send the file again in a minute.".to_owned()).await?;
 
-        queue.push(FileQueueItem::new(msg.clone(), queue_message_clone, file_id.clone(), file_name.clone(), url.clone()));
+            return Ok(());
+        }
+
+        let mut duplicates = Vec::new();
+
+        files.retain(|file| match queue.find_duplicate(msg.chat.id, &file.source) {
+            Some(duplicate) => {
+                duplicates.push(duplicate);
+
+                false
+            }
+            None => true,
+        });
+
+        if let Some(max_queue_size) = config.get().limits().max_queue_size {
+            let waiting = queue.len() - queue.in_progress();
+
+            if !files.is_empty() && waiting + files.len() > max_queue_size {
+                drop(queue);
+
+                info!("Refusing {} file(s) of message {}, {} of {} files are waiting", files.len(), msg.id, waiting, max_queue_size);
+
+                reply_to(&bot, &msg, "The queue is full. Please send the file again later.".to_owned()).await?;
+
+                return Ok(());
+            }
+        }
+
+        let sizes: Vec<Option<u64>> = files.iter().map(|file| file.size).collect();
+
+        (duplicates, queue.next_positions(&msg, &sizes))
+    };
 
-        info!("Added item to queue. Current queue position: {}", position);
+    // the reply to the status message of the queued file shows where it is
+    for (position, queue_message) in duplicates {
+        info!("File of message {} is already queued at position {}", msg.id, position);
+
+        let text = format!("This file is already queued at position {}.", position);
+
+        flood::send(reply_to(&bot, &queue_message, text)).await?;
     }
 
-    tx.send(()).await?;
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let files_count = files.len();
+
+    let queue_position_template = MessageTemplates::for_language(language).await.queue_position.clone();
+
+    let queue_text = |position: usize, short_id: &str, file: &FileInfo| {
+        queue_status_text(render(&queue_position_template, &[("position", position.to_string())]), short_id, file.not_before)
+    };
+
+    // the IDs are shown in the status message, which is sent before the files are queued
+    let short_ids: Vec<String> = files.iter().map(|_| new_short_id()).collect();
+
+    let batch = if files_count > 1 {
+        let mut batch = BatchStatus::default();
+
+        for (index, file) in files.iter().enumerate() {
+            let title = match &file.source {
+                DownloadSource::Url(url) => url.to_string(),
+                DownloadSource::Telegram { file_name, .. } => file_name.clone().unwrap_or_default(),
+            };
+
+            batch.push(html::escape(&title), html::escape(&queue_text(positions[index], &short_ids[index], file)));
+        }
+
+        Some(Arc::new(Mutex::new(batch)))
+    } else {
+        None
+    };
+
+    let request = match &batch {
+        Some(batch) => reply_to(&bot, &msg, batch.lock().await.render())
+            .parse_mode(ParseMode::Html),
+        None => reply_to(&bot, &msg, queue_text(positions[0], &short_ids[0], &files[0])),
+    };
+
+    let queue_message = flood::send(request).await?;
+
+    let queue_message_clone = Arc::new(queue_message);
+
+    let job_ids = {
+        let mut queue = file_queue.lock().await;
+
+        let mut job_ids = Vec::new();
+
+        for (index, (file_info, short_id)) in files.into_iter().zip(short_ids).enumerate() {
+            let mut item = FileQueueItem::new(
+                bot.clone(),
+                config.clone(),
+                msg.clone(),
+                queue_message_clone.clone(),
+                file_info.source,
+            )
+                .with_expires_in(file_info.expires_in)
+                .with_request_options(file_info.request_options)
+                .with_strip_metadata(file_info.strip_metadata)
+                .with_extract_archive(file_info.extract_archive)
+                .with_private(file_info.private)
+                .with_not_before(file_info.not_before)
+                .with_short_id(short_id)
+                .with_size(file_info.size);
+
+            if let Some(batch) = &batch {
+                item = item.with_batch(batch.clone(), index);
+            }
+
+            let short_id = item.short_id().to_owned();
+            let (job_id, position) = queue.enqueue(item);
+
+            info!("[{}] Added item to queue. Current queue position: {}", short_id, position);
+
+            job_ids.push(job_id);
+        }
+
+        if let Err(e) = save_queue(&queue).await {
+            warn!("Failed to save queue: {}", e);
+        }
+
+        job_ids
+    };
+
+    for job_id in job_ids {
+        notify_queue(tx, QueueEvent::Enqueued(job_id))?;
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str, extra: &str) -> Message {
+        serde_json::from_str(&format!(
+            r#"{{"chat":{{"id":-100123,"title":"group","type":"supergroup"}},"date":1675229140,"message_id":5,"text":{}{}}}"#,
+            serde_json::to_string(text).unwrap(),
+            extra
+        )).unwrap()
+    }
+
+    fn urls(msg: &Message) -> Vec<String> {
+        get_urls_from_message(msg, &positional_args(&split_args(msg.text().unwrap())))
+    }
+
+    #[tokio::test]
+    async fn test_topic_id() {
+        assert_eq!(topic_id(&message("/url", r#","is_topic_message":true,"message_thread_id":4"#)), Some(4));
+        assert_eq!(topic_id(&message("/url", r#","message_thread_id":4"#)), None);
+        assert_eq!(topic_id(&message("/url", "")), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_urls_from_message() {
+        let msg = message("/url https://example.com/a.zip http://example.com/b.zip --expire 24h", "");
+
+        assert_eq!(urls(&msg), vec!["https://example.com/a.zip", "http://example.com/b.zip"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_urls_from_message_with_reply() {
+        let reply = r#","reply_to_message":{"chat":{"id":-100123,"title":"group","type":"supergroup"},"date":1675229139,"message_id":4,"text":"https://example.com/a.zip and https://example.com/c.zip"}"#;
+        let msg = message("/url https://example.com/a.zip https://example.com/b.zip", reply);
+
+        assert_eq!(urls(&msg), vec![
+            "https://example.com/a.zip",
+            "https://example.com/b.zip",
+            "https://example.com/c.zip",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_get_urls_from_message_ignores_flag_values() {
+        let msg = message(r#"/url https://example.com/a.zip --header "Referer: https://example.com/""#, "");
+
+        assert_eq!(urls(&msg), vec!["https://example.com/a.zip"]);
+        assert!(urls(&message("/url", "")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_is_addressed() {
+        let bot_id = UserId(42);
+        let reply_to_bot = r#","reply_to_message":{"chat":{"id":-100123,"title":"group","type":"supergroup"},"date":1675229139,"message_id":4,"text":"Downloaded","from":{"id":42,"is_bot":true,"first_name":"file2link"}}"#;
+
+        assert!(is_addressed(&message("/url https://example.com/a.zip", ""), bot_id, "file2link_bot"));
+        assert!(is_addressed(&message("save this @File2Link_Bot", ""), bot_id, "file2link_bot"));
+        assert!(is_addressed(&message("this one", reply_to_bot), bot_id, "file2link_bot"));
+        assert!(!is_addressed(&message("hello everyone", ""), bot_id, "file2link_bot"));
+        assert!(!is_addressed(&message("this one", reply_to_bot), UserId(7), "other_bot"));
+    }
+
+    #[tokio::test]
+    async fn test_get_file_from_args() {
+        let file = |text: &str| get_file_from_args(&positional_args(&split_args(text)))
+            .map(|file| file.source);
+        let telegram = |file_name: Option<&str>| DownloadSource::Telegram {
+            file_id: "AgADBAAD".to_owned(),
+            file_unique_id: None,
+            file_name: file_name.map(str::to_owned),
+        };
+
+        assert_eq!(file("/fileid AgADBAAD --expire 24h"), Some(telegram(None)));
+        assert_eq!(file(r#"/fileid AgADBAAD "annual report.pdf""#), Some(telegram(Some("annual report.pdf"))));
+        assert_eq!(file("/fileid AgADBAAD ../report.pdf"), Some(telegram(Some(".._report.pdf"))));
+        assert!(file("/fileid").is_none());
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use shared::config::Config;
+
+/// Longest wait between two attempts of a queued file
+const MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// How often a queued file is attempted again after a failure which may go away, e.g. the source being down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_attempts: config.retry_attempts(),
+            base_delay: Duration::from_secs(config.retry_base_delay()),
+        }
+    }
+
+    /// Get the wait after the failed attempt, the base delay doubles with every attempt
+    /// Half of the wait is random, so files which failed together are not attempted again at the same moment
+    ///
+    /// # Arguments
+    /// * `attempt` - 1-based number of the failed attempt
+    /// * `jitter` - Random value in `0.0..1.0`, see `jitter`
+    pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        let exponential = self.base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_DELAY);
+
+        exponential / 2 + exponential.mul_f64(jitter.clamp(0.0, 1.0) / 2.0)
+    }
+}
+
+/// Get a random value in `0.0..1.0` from the clock, good enough to spread retries
+pub fn jitter() -> f64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+
+    nanos as f64 / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_delay() {
+        let policy = RetryPolicy { max_attempts: 5, base_delay: Duration::from_secs(10) };
+
+        assert_eq!(policy.delay(1, 0.0), Duration::from_secs(5));
+        assert_eq!(policy.delay(1, 1.0), Duration::from_secs(10));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_secs(30));
+        assert_eq!(policy.delay(30, 1.0), MAX_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_jitter() {
+        let jitter = jitter();
+
+        assert!((0.0..1.0).contains(&jitter));
+    }
+}
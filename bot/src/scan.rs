@@ -0,0 +1,236 @@
+use std::io;
+
+use log::{info, warn};
+use shared::config::{ClamdAddress, Config};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::process::Command;
+
+/// Directory for files the virus scanner flagged, they are kept for inspection but not served
+const QUARANTINE_PATH: &str = "quarantine";
+
+/// Files are sent to clamd in chunks of this size
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum ScanVerdict {
+    Clean,
+    Infected(String),
+}
+
+/// How downloaded files are scanned, clamd is preferred when both are configured
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scanner {
+    /// Sends the file to clamd, see `CLAMD_ADDRESS`
+    Clamd(ClamdAddress),
+    /// Runs `SCAN_COMMAND`, see `scan_file`
+    Command(String),
+}
+
+impl Scanner {
+    /// # Returns
+    /// * `None` if neither `CLAMD_ADDRESS` nor `SCAN_COMMAND` is set
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.clamd_address().map(Scanner::Clamd)
+            .or_else(|| config.scan_command().map(Scanner::Command))
+    }
+
+    pub async fn scan(&self, path: &str) -> Result<ScanVerdict, String> {
+        match self {
+            Scanner::Clamd(address) => scan_with_clamd(address, path).await,
+            Scanner::Command(command) => scan_file(command, path).await,
+        }
+    }
+}
+
+/// Runs the scan command with the file path as the last argument
+/// Exit codes follow ClamAV: 0 means clean, 1 means a virus was found, anything else is an error
+///
+/// # Arguments
+/// * `command` - Scan command with its arguments, e.g. `clamdscan --no-summary`
+/// * `path` - Path of the file to scan
+pub async fn scan_file(command: &str, path: &str) -> Result<ScanVerdict, String> {
+    let mut parts = command.split_whitespace();
+    let program = parts.next().ok_or("Scan command is empty")?;
+
+    let output = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run '{}': {}", program, e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+
+    match output.status.code() {
+        Some(0) => Ok(ScanVerdict::Clean),
+        Some(1) => Ok(ScanVerdict::Infected(stdout)),
+        code => Err(format!(
+            "Scan of '{}' failed with exit code {:?}: {}",
+            path,
+            code,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+/// Streams the file to clamd with the INSTREAM command, so clamd does not need access to the files directory
+pub async fn scan_with_clamd(address: &ClamdAddress, path: &str) -> Result<ScanVerdict, String> {
+    let reply = match address {
+        ClamdAddress::Tcp(host) => match TcpStream::connect(host).await {
+            Ok(stream) => send_to_clamd(stream, path).await,
+            Err(e) => return Err(format!("Failed to connect to clamd at {}: {}", host, e)),
+        },
+        ClamdAddress::Unix(socket) => match UnixStream::connect(socket).await {
+            Ok(stream) => send_to_clamd(stream, path).await,
+            Err(e) => return Err(format!("Failed to connect to clamd at {}: {}", socket, e)),
+        },
+    };
+
+    match reply {
+        Ok(reply) => parse_clamd_reply(&reply),
+        Err(e) => Err(format!("Failed to scan '{}' with clamd: {}", path, e)),
+    }
+}
+
+/// # Returns
+/// * `Result` containing the reply of clamd
+async fn send_to_clamd<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, path: &str) -> io::Result<String> {
+    let mut file = File::open(path).await?;
+    let mut buffer = vec![0; CLAMD_CHUNK_SIZE];
+
+    stream.write_all(b"zINSTREAM\0").await?;
+
+    // every chunk is prefixed with its length, an empty chunk ends the file
+    loop {
+        let read = file.read(&mut buffer).await?;
+
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        stream.write_all(&buffer[..read]).await?;
+    }
+
+    stream.flush().await?;
+
+    // clamd closes the connection after the reply
+    let mut reply = Vec::new();
+
+    stream.read_to_end(&mut reply).await?;
+
+    Ok(String::from_utf8_lossy(&reply).trim_end_matches('\0').trim().to_owned())
+}
+
+/// Parses replies like "stream: OK" and "stream: Eicar-Test-Signature FOUND"
+fn parse_clamd_reply(reply: &str) -> Result<ScanVerdict, String> {
+    let result = reply.strip_prefix("stream:").map(str::trim);
+
+    match result {
+        Some("OK") => Ok(ScanVerdict::Clean),
+        Some(result) if result.ends_with(" FOUND") => Ok(ScanVerdict::Infected(result.trim_end_matches(" FOUND").to_owned())),
+        _ => Err(format!("clamd failed to scan the file: {}", reply)),
+    }
+}
+
+/// Moves a downloaded file to quarantine instead of the storage, so it is never served
+pub async fn quarantine_file(path: &str, file_name: &str) -> io::Result<()> {
+    tokio::fs::create_dir_all(QUARANTINE_PATH).await?;
+
+    tokio::fs::rename(path, format!("{}/{}", QUARANTINE_PATH, file_name)).await?;
+
+    info!("File {} has been moved to {}/", file_name, QUARANTINE_PATH);
+
+    Ok(())
+}
+
+/// Removes a file which could not be scanned, it must not be served unchecked
+pub async fn remove_unscanned_file(path: &str) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        warn!("Failed to remove unscanned file {}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scan_file() {
+        assert_eq!(scan_file("true", "file.txt").await, Ok(ScanVerdict::Clean));
+        assert_eq!(scan_file("echo", "file.txt").await, Ok(ScanVerdict::Clean));
+        assert_eq!(scan_file("false", "file.txt").await, Ok(ScanVerdict::Infected("".to_owned())));
+        assert!(scan_file("file2link-missing-scanner", "file.txt").await.is_err());
+        assert!(scan_file("  ", "file.txt").await.is_err());
+    }
+
+    #[test]
+    fn test_parse_clamd_reply() {
+        assert_eq!(parse_clamd_reply("stream: OK"), Ok(ScanVerdict::Clean));
+        assert_eq!(parse_clamd_reply("stream: Eicar-Test-Signature FOUND"), Ok(ScanVerdict::Infected("Eicar-Test-Signature".to_owned())));
+        assert!(parse_clamd_reply("INSTREAM size limit exceeded. ERROR").is_err());
+        assert!(parse_clamd_reply("").is_err());
+    }
+
+    /// Answers one INSTREAM command like clamd, files containing "EICAR" are infected
+    async fn fake_clamd(listener: tokio::net::TcpListener) -> Vec<u8> {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut command = [0; 10];
+        stream.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command, b"zINSTREAM\0");
+
+        let mut content = Vec::new();
+
+        loop {
+            let length = stream.read_u32().await.unwrap() as usize;
+
+            if length == 0 {
+                break;
+            }
+
+            let mut chunk = vec![0; length];
+            stream.read_exact(&mut chunk).await.unwrap();
+            content.extend(chunk);
+        }
+
+        let reply: &[u8] = match content.windows(5).any(|window| window == b"EICAR") {
+            true => b"stream: Eicar-Test-Signature FOUND\0",
+            false => b"stream: OK\0",
+        };
+
+        stream.write_all(reply).await.unwrap();
+
+        content
+    }
+
+    #[tokio::test]
+    async fn test_scan_with_clamd() {
+        let dir = std::env::temp_dir().join("file2link_test_clamd");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        for (name, content) in [("clean.bin", vec![7; CLAMD_CHUNK_SIZE * 2 + 1]), ("eicar.txt", b"X5O EICAR test".to_vec())] {
+            let path = dir.join(name).to_string_lossy().into_owned();
+            tokio::fs::write(&path, &content).await.unwrap();
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = ClamdAddress::Tcp(listener.local_addr().unwrap().to_string());
+            let clamd = tokio::spawn(fake_clamd(listener));
+
+            let verdict = Scanner::Clamd(address).scan(&path).await;
+
+            assert_eq!(clamd.await.unwrap(), content);
+            assert_eq!(verdict, match name {
+                "eicar.txt" => Ok(ScanVerdict::Infected("Eicar-Test-Signature".to_owned())),
+                _ => Ok(ScanVerdict::Clean),
+            });
+        }
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+
+        assert!(scan_with_clamd(&ClamdAddress::Unix("/nonexistent/clamd.ctl".to_owned()), "file.txt").await.is_err());
+    }
+}
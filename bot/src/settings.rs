@@ -0,0 +1,295 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use log::{info, warn};
+use shared::chat_config::PermissionsConfig;
+use shared::chat_settings::{save_settings, ChatSettings, FileNameStyle, LinkStyle, SettingsStore};
+use shared::templates::Language;
+use teloxide::payloads::{AnswerCallbackQuerySetters, EditMessageReplyMarkupSetters, SendMessageSetters};
+use teloxide::prelude::{CallbackQuery, Message, Requester};
+use teloxide::types::{Chat, InlineKeyboardButton, InlineKeyboardMarkup, User};
+use tokio::sync::Mutex;
+
+use crate::callbacks::EXPIRY_OPTIONS;
+use crate::process_message::reply_to;
+
+const SETTINGS_PREFIX: &str = "settings";
+
+/// Rows of the settings menu, pressing a row switches the setting to the next value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Setting {
+    Expiry,
+    LinkStyle,
+    FileNameStyle,
+    Language,
+    AddressedOnly,
+    StripMetadata,
+}
+
+impl Setting {
+    const ALL: [Setting; 6] = [
+        Setting::Expiry,
+        Setting::LinkStyle,
+        Setting::FileNameStyle,
+        Setting::Language,
+        Setting::AddressedOnly,
+        Setting::StripMetadata,
+    ];
+
+    fn key(&self) -> &'static str {
+        match self {
+            Setting::Expiry => "expiry",
+            Setting::LinkStyle => "link",
+            Setting::FileNameStyle => "name",
+            Setting::Language => "language",
+            Setting::AddressedOnly => "addressed",
+            Setting::StripMetadata => "metadata",
+        }
+    }
+
+    fn label(&self, settings: &ChatSettings) -> String {
+        match self {
+            Setting::Expiry => {
+                let seconds = settings.expires_in.unwrap_or(0);
+
+                let expiry = match EXPIRY_OPTIONS.iter().find(|(_, option)| *option == seconds) {
+                    Some((label, _)) => label.to_string(),
+                    None => format!("{} seconds", seconds),
+                };
+
+                format!("Link expiry: {}", expiry)
+            }
+            Setting::LinkStyle => match settings.link_style {
+                LinkStyle::Attachment => "Links: download".to_owned(),
+                LinkStyle::Inline => "Links: open in browser".to_owned(),
+            },
+            Setting::FileNameStyle => match settings.file_name_style {
+                FileNameStyle::Original => "File names: original".to_owned(),
+                FileNameStyle::Random => "File names: random".to_owned(),
+            },
+            Setting::Language => format!("Language: {}", settings.language.name()),
+            Setting::AddressedOnly => match settings.addressed_only {
+                false => "In groups: all messages".to_owned(),
+                true => "In groups: only when addressed".to_owned(),
+            },
+            Setting::StripMetadata => match settings.strip_metadata {
+                false => "Photo metadata: keep".to_owned(),
+                true => "Photo metadata: remove".to_owned(),
+            },
+        }
+    }
+
+    fn switch(&self, settings: &mut ChatSettings) {
+        match self {
+            Setting::Expiry => {
+                let seconds = settings.expires_in.unwrap_or(0);
+
+                // a custom value set in the settings file starts over from the first option
+                let next = EXPIRY_OPTIONS.iter()
+                    .position(|(_, option)| *option == seconds)
+                    .map_or(0, |index| (index + 1) % EXPIRY_OPTIONS.len());
+
+                settings.expires_in = Some(EXPIRY_OPTIONS[next].1).filter(|seconds| *seconds > 0);
+            }
+            Setting::LinkStyle => {
+                settings.link_style = match settings.link_style {
+                    LinkStyle::Attachment => LinkStyle::Inline,
+                    LinkStyle::Inline => LinkStyle::Attachment,
+                };
+            }
+            Setting::FileNameStyle => {
+                settings.file_name_style = match settings.file_name_style {
+                    FileNameStyle::Original => FileNameStyle::Random,
+                    FileNameStyle::Random => FileNameStyle::Original,
+                };
+            }
+            Setting::Language => {
+                let index = Language::ALL.iter().position(|language| *language == settings.language).unwrap_or(0);
+
+                settings.language = Language::ALL[(index + 1) % Language::ALL.len()];
+            }
+            Setting::AddressedOnly => settings.addressed_only = !settings.addressed_only,
+            Setting::StripMetadata => settings.strip_metadata = !settings.strip_metadata,
+        }
+    }
+}
+
+fn settings_keyboard(settings: &ChatSettings) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(Setting::ALL.iter().map(|setting| {
+        [InlineKeyboardButton::callback(
+            setting.label(settings),
+            format!("{}:{}", SETTINGS_PREFIX, setting.key()),
+        )]
+    }))
+}
+
+/// Parses callback data created by `settings_keyboard`
+fn parse_settings_data(data: &str) -> Option<Setting> {
+    let key = data.strip_prefix(SETTINGS_PREFIX)?.strip_prefix(':')?;
+
+    Setting::ALL.into_iter().find(|setting| setting.key() == key)
+}
+
+pub fn is_settings(query: &CallbackQuery) -> bool {
+    query.data.as_deref().is_some_and(|data| data.starts_with(SETTINGS_PREFIX))
+}
+
+/// Checks if the user may change the settings of the chat
+/// Anyone may in a private chat, in groups only bot admins and administrators of the group
+pub async fn can_change_settings(
+    bot: &teloxide::Bot,
+    chat: &Chat,
+    user: Option<&User>,
+    is_bot_admin: bool,
+) -> Result<bool, Box<dyn Error>> {
+    if is_bot_admin || chat.is_private() {
+        return Ok(true);
+    }
+
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(false),
+    };
+
+    Ok(bot.get_chat_member(chat.id, user.id).await?.is_privileged())
+}
+
+/// Handles "/settings" by replying with the settings menu of the chat
+pub async fn process_settings_command(
+    bot: Arc<teloxide::Bot>,
+    msg: &Message,
+    settings: Arc<Mutex<SettingsStore>>,
+    is_bot_admin: bool,
+) -> Result<(), Box<dyn Error>> {
+    if !can_change_settings(&bot, &msg.chat, msg.from(), is_bot_admin).await? {
+        reply_to(&bot, msg, "Only administrators of the chat can change its settings".to_owned()).await?;
+
+        return Ok(());
+    }
+
+    let chat_settings = settings.lock().await.get(&msg.chat.id.to_string());
+
+    reply_to(&bot, msg, "Settings of this chat, press a button to change it".to_owned())
+        .reply_markup(settings_keyboard(&chat_settings))
+        .await?;
+
+    Ok(())
+}
+
+/// Handles presses on the settings menu
+pub async fn process_settings_callback(
+    bot: Arc<teloxide::Bot>,
+    query: CallbackQuery,
+    settings: Arc<Mutex<SettingsStore>>,
+    permissions: Arc<Mutex<PermissionsConfig>>,
+) -> Result<(), Box<dyn Error>> {
+    let setting = match query.data.as_deref().and_then(parse_settings_data) {
+        Some(setting) => setting,
+        None => {
+            bot.answer_callback_query(query.id).await?;
+
+            return Ok(());
+        }
+    };
+
+    let message = match &query.message {
+        Some(message) => message,
+        None => {
+            bot.answer_callback_query(query.id).text("The message is too old").await?;
+
+            return Ok(());
+        }
+    };
+
+    let (is_banned, is_admin) = {
+        let permissions = permissions.lock().await;
+        let user_id = query.from.id.to_string();
+
+        (permissions.is_banned(&user_id), permissions.is_admin(&user_id))
+    };
+
+    if is_banned || !can_change_settings(&bot, &message.chat, Some(&query.from), is_admin).await? {
+        bot.answer_callback_query(query.id).text("Only administrators of the chat can change its settings").await?;
+
+        return Ok(());
+    }
+
+    let chat_id = message.chat.id.to_string();
+
+    let chat_settings = {
+        let mut settings = settings.lock().await;
+
+        settings.update(&chat_id, |chat| setting.switch(chat));
+
+        if let Err(e) = save_settings(&settings).await {
+            warn!("Failed to save settings: {}", e);
+        }
+
+        settings.get(&chat_id)
+    };
+
+    info!("User {} changed {:?} of chat {}: {:?}", query.from.id, setting, chat_id, chat_settings);
+
+    bot.answer_callback_query(query.id).await?;
+    bot.edit_message_reply_markup(message.chat.id, message.id)
+        .reply_markup(settings_keyboard(&chat_settings))
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_settings_data() {
+        assert_eq!(parse_settings_data("settings:expiry"), Some(Setting::Expiry));
+        assert_eq!(parse_settings_data("settings:language"), Some(Setting::Language));
+        assert_eq!(parse_settings_data("settings:other"), None);
+        assert_eq!(parse_settings_data("expire:3600:abcde"), None);
+    }
+
+    #[tokio::test]
+    async fn test_switch_expiry() {
+        let mut settings = ChatSettings::default();
+
+        let expiries: Vec<Option<u64>> = (0..EXPIRY_OPTIONS.len())
+            .map(|_| {
+                Setting::Expiry.switch(&mut settings);
+
+                settings.expires_in
+            })
+            .collect();
+
+        assert_eq!(expiries, vec![Some(60 * 60), Some(24 * 60 * 60), Some(7 * 24 * 60 * 60), None]);
+
+        settings.expires_in = Some(123);
+        Setting::Expiry.switch(&mut settings);
+
+        assert_eq!(settings.expires_in, Some(60 * 60));
+    }
+
+    #[tokio::test]
+    async fn test_switch_cycles_back() {
+        let mut settings = ChatSettings::default();
+
+        for setting in [Setting::LinkStyle, Setting::FileNameStyle, Setting::Language, Setting::AddressedOnly, Setting::StripMetadata] {
+            setting.switch(&mut settings);
+
+            assert_ne!(settings, ChatSettings::default());
+
+            setting.switch(&mut settings);
+
+            assert_eq!(settings, ChatSettings::default());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_settings_keyboard() {
+        let keyboard = settings_keyboard(&ChatSettings::default());
+
+        assert_eq!(keyboard.inline_keyboard.len(), Setting::ALL.len());
+        assert_eq!(keyboard.inline_keyboard[0][0].text, "Link expiry: Never");
+    }
+}
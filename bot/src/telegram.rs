@@ -0,0 +1,105 @@
+use std::fmt::Debug;
+
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use reqwest::StatusCode;
+use teloxide::net::Download;
+use teloxide::payloads::EditMessageTextSetters;
+use teloxide::prelude::{ChatId, Requester};
+use teloxide::types::{InlineKeyboardMarkup, InputFile, MessageId, ParseMode};
+use teloxide::RequestError;
+use tokio_util::bytes::Bytes;
+
+use crate::flood;
+use crate::queue::is_resumed_from;
+
+/// Answer of the server to a download from a byte, see `TelegramClient::file_stream_from`
+pub enum RangedStream {
+    /// The file from the requested byte
+    Resumed(BoxStream<'static, Result<Bytes, reqwest::Error>>),
+    /// The whole file, the server does not support ranges
+    Whole(BoxStream<'static, Result<Bytes, reqwest::Error>>),
+}
+
+/// Requests the queue sends to Telegram while processing files
+/// Implemented by `teloxide::Bot`, tests give queue items a mock with `FileQueueItem::with_client`
+pub trait TelegramClient: Debug + Send + Sync {
+    /// Replaces the text of a message, the text is HTML. Waits out Telegram flood control, see `flood::send`
+    fn edit_text(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: String,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> BoxFuture<'_, Result<(), RequestError>>;
+
+    /// Get the path and the size of a file for `file_stream`
+    fn file_info<'a>(&'a self, file_id: &'a str) -> BoxFuture<'a, Result<(String, u64), RequestError>>;
+
+    /// Downloads the file at the path given by `file_info`
+    fn file_stream(&self, path: &str) -> BoxStream<'static, Result<Bytes, reqwest::Error>>;
+
+    /// Downloads the file at the path given by `file_info` from the byte `offset` with a `Range` request
+    ///
+    /// # Returns
+    /// * `Err` containing the reason if the server refused the request
+    fn file_stream_from<'a>(&'a self, path: &'a str, offset: u64) -> BoxFuture<'a, Result<RangedStream, String>>;
+
+    /// Uploads a local file as a document. Waits out Telegram flood control, see `flood::send`
+    ///
+    /// # Returns
+    /// * `Result` containing the ID of the sent message
+    fn send_file<'a>(&'a self, chat_id: ChatId, path: &'a str, file_name: &'a str) -> BoxFuture<'a, Result<MessageId, RequestError>>;
+}
+
+impl TelegramClient for teloxide::Bot {
+    fn edit_text(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: String,
+        keyboard: Option<InlineKeyboardMarkup>,
+    ) -> BoxFuture<'_, Result<(), RequestError>> {
+        let mut request = self.edit_message_text(chat_id, message_id, text).parse_mode(ParseMode::Html);
+
+        if let Some(keyboard) = keyboard {
+            request = request.reply_markup(keyboard);
+        }
+
+        async move { flood::send(request).await.map(|_| ()) }.boxed()
+    }
+
+    fn file_info<'a>(&'a self, file_id: &'a str) -> BoxFuture<'a, Result<(String, u64), RequestError>> {
+        async move { self.get_file(file_id).await.map(|file| (file.path.clone(), u64::from(file.size))) }.boxed()
+    }
+
+    fn file_stream(&self, path: &str) -> BoxStream<'static, Result<Bytes, reqwest::Error>> {
+        self.download_file_stream(path).boxed()
+    }
+
+    fn file_stream_from<'a>(&'a self, path: &'a str, offset: u64) -> BoxFuture<'a, Result<RangedStream, String>> {
+        async move {
+            let url = self.api_url().join(&format!("file/bot{}/{}", self.token(), path))
+                .map_err(|e| format!("Invalid file URL: {}", e))?;
+
+            let response = self.client().get(url)
+                .header(reqwest::header::RANGE, format!("bytes={}-", offset))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            match response.status() {
+                StatusCode::PARTIAL_CONTENT if is_resumed_from(&response, offset) => Ok(RangedStream::Resumed(response.bytes_stream().boxed())),
+                StatusCode::OK => Ok(RangedStream::Whole(response.bytes_stream().boxed())),
+                status => Err(status.to_string()),
+            }
+        }.boxed()
+    }
+
+    fn send_file<'a>(&'a self, chat_id: ChatId, path: &'a str, file_name: &'a str) -> BoxFuture<'a, Result<MessageId, RequestError>> {
+        let request = self.send_document(chat_id, InputFile::file(path).file_name(file_name.to_owned()));
+
+        async move { flood::send(request).await.map(|message| message.id) }.boxed()
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use shared::config::Config;
+use tokio::sync::{Mutex, OnceCell};
+use tokio::time::{sleep_until, Instant};
+
+/// Limits the throughput of downloads, the bytes of a chunk are let through once the time they take at the limit
+/// has passed since the previous chunk
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_second: u64,
+    /// When the bytes let through so far are used up
+    next: Mutex<Option<Instant>>,
+}
+
+/// Shared by all downloads, see `Config::bandwidth_limit`
+static GLOBAL: OnceCell<Option<Throttle>> = OnceCell::const_new();
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self { bytes_per_second, next: Mutex::new(None) }
+    }
+
+    /// Waits until the bytes fit into the limit
+    pub async fn consume(&self, bytes: usize) {
+        let until = {
+            let mut next = self.next.lock().await;
+
+            let until = reserve(*next, Instant::now(), bytes, self.bytes_per_second);
+
+            *next = Some(until);
+            until
+        };
+
+        sleep_until(until).await;
+    }
+}
+
+/// Get when the bytes are used up, the time of the previous chunks in the past is not made up for
+fn reserve(next: Option<Instant>, now: Instant, bytes: usize, bytes_per_second: u64) -> Instant {
+    let start = next.map_or(now, |next| next.max(now));
+
+    start + Duration::from_secs_f64(bytes as f64 / bytes_per_second as f64)
+}
+
+/// Get the throttle shared by all downloads, `None` if the overall bandwidth is not limited
+/// It is created with the config of the first download, the limit is only read at startup
+pub async fn global(config: &Config) -> Option<&'static Throttle> {
+    GLOBAL.get_or_init(|| async { config.bandwidth_limit().map(Throttle::new) })
+        .await
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reserve() {
+        let now = Instant::now();
+
+        assert_eq!(reserve(None, now, 1000, 500), now + Duration::from_secs(2));
+
+        // chunks coming faster than the limit queue up behind each other
+        assert_eq!(reserve(Some(now + Duration::from_secs(2)), now, 250, 500), now + Duration::from_millis(2500));
+
+        // an idle stream does not get to burst
+        assert_eq!(reserve(Some(now - Duration::from_secs(10)), now, 500, 500), now + Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_consume() {
+        let throttle = Throttle::new(10_000);
+        let start = Instant::now();
+
+        throttle.consume(1000).await;
+        throttle.consume(1000).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
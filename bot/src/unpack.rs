@@ -0,0 +1,356 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Component, Path};
+
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use shared::config::ArchiveLimits;
+use shared::metadata::ArchiveEntry;
+use shared::utils::format_size;
+use zip::ZipArchive;
+
+/// Archives which are listed and extracted, recognized by the file name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let file_name = file_name.to_lowercase();
+
+        if file_name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if file_name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file extracted from an archive, waiting to be stored like a downloaded file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedFile {
+    /// Index of the file in the entries of the archive
+    pub index: usize,
+    pub path: String,
+    pub size: u64,
+    /// Hex encoded SHA-256 of the content
+    pub sha256: String,
+}
+
+/// Get the directory the files of an archive are extracted to, next to the downloaded archive
+pub fn extract_dir(archive_path: &str) -> String {
+    format!("{}.extracted", archive_path)
+}
+
+/// Lists the regular files in the archive, folders and links are skipped
+///
+/// # Returns
+/// * `Err` containing the reason for the user if the archive can not be read or has too many files
+pub fn list(path: &str, kind: ArchiveKind, limits: ArchiveLimits) -> Result<Vec<ArchiveEntry>, String> {
+    let mut entries = Vec::new();
+
+    read_archive(path, kind, |entry_path, size, _| {
+        entries.push(ArchiveEntry { path: entry_path, size, file_name: None });
+
+        check_entries(entries.len(), limits)
+    })?;
+
+    Ok(entries)
+}
+
+/// Extracts the regular files of the archive into `extract_dir`, the files are named by their index
+/// The limits are checked against the extracted bytes, the sizes in the archive headers may lie
+///
+/// # Returns
+/// * `Result` containing the entries of the archive and the extracted files
+/// * `Err` containing the reason for the user, the files extracted so far are not removed
+pub fn extract(path: &str, kind: ArchiveKind, limits: ArchiveLimits) -> Result<(Vec<ArchiveEntry>, Vec<ExtractedFile>), String> {
+    let dir = extract_dir(path);
+    let mut entries = Vec::new();
+    let mut extracted = Vec::new();
+    let mut remaining = limits.max_extracted_size;
+
+    std::fs::create_dir_all(&dir).map_err(|e| format!("failed to create a folder for the files: {}", e))?;
+
+    read_archive(path, kind, |entry_path, _, data| {
+        check_entries(entries.len() + 1, limits)?;
+
+        let target = format!("{}/{}", dir, entries.len());
+
+        let (written, sha256) = copy_limited(data, &target, &mut remaining)
+            .map_err(|e| match e {
+                CopyError::TooBig => format!("the files are bigger than {}", format_size(limits.max_extracted_size)),
+                CopyError::Io(e) => format!("failed to extract {}: {}", entry_path, e),
+            })?;
+
+        extracted.push(ExtractedFile { index: entries.len(), path: target, size: written, sha256 });
+        entries.push(ArchiveEntry { path: entry_path, size: written, file_name: None });
+
+        Ok(())
+    })?;
+
+    Ok((entries, extracted))
+}
+
+fn check_entries(count: usize, limits: ArchiveLimits) -> Result<(), String> {
+    match count > limits.max_entries {
+        true => Err(format!("the archive has more than {} files", limits.max_entries)),
+        false => Ok(()),
+    }
+}
+
+/// Calls `on_file` with the path, size in the header and content of every regular file in the archive
+fn read_archive<F>(path: &str, kind: ArchiveKind, mut on_file: F) -> Result<(), String>
+where
+    F: FnMut(String, u64, &mut dyn Read) -> Result<(), String>,
+{
+    let file = File::open(path).map_err(|e| format!("failed to open the archive: {}", e))?;
+    let reader = BufReader::new(file);
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = ZipArchive::new(reader).map_err(|e| format!("not a valid zip archive: {}", e))?;
+
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index).map_err(|e| format!("not a valid zip archive: {}", e))?;
+
+                if !entry.is_file() || entry.is_symlink() {
+                    continue;
+                }
+
+                let entry_path = match entry.enclosed_name().as_deref().and_then(entry_path) {
+                    Some(entry_path) => entry_path,
+                    None => continue,
+                };
+                let size = entry.size();
+
+                on_file(entry_path, size, &mut entry)?;
+            }
+
+            Ok(())
+        }
+        ArchiveKind::Tar => read_tar(reader, on_file),
+        ArchiveKind::TarGz => read_tar(GzDecoder::new(reader), on_file),
+    }
+}
+
+fn read_tar<R, F>(reader: R, mut on_file: F) -> Result<(), String>
+where
+    R: Read,
+    F: FnMut(String, u64, &mut dyn Read) -> Result<(), String>,
+{
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive.entries().map_err(|e| format!("not a valid tar archive: {}", e))?;
+
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("not a valid tar archive: {}", e))?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let entry_path = match entry.path().ok().as_deref().and_then(entry_path) {
+            Some(entry_path) => entry_path,
+            None => continue,
+        };
+        let size = entry.size();
+
+        on_file(entry_path, size, &mut entry)?;
+    }
+
+    Ok(())
+}
+
+/// Get the path of an entry with '/' separators, `None` for paths out of the archive like "../a" or "/etc/passwd"
+fn entry_path(path: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+enum CopyError {
+    /// The limit of extracted bytes is reached
+    TooBig,
+    Io(io::Error),
+}
+
+/// Copies the content into a new file, counting the bytes against `remaining`
+///
+/// # Returns
+/// * `Result` containing the size and the hex encoded SHA-256 of the content
+fn copy_limited(data: &mut dyn Read, target: &str, remaining: &mut u64) -> Result<(u64, String), CopyError> {
+    let mut file = File::create(target).map_err(CopyError::Io)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let read = data.read(&mut buffer).map_err(CopyError::Io)?;
+
+        if read == 0 {
+            break;
+        }
+
+        size += read as u64;
+
+        if size > *remaining {
+            return Err(CopyError::TooBig);
+        }
+
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read]).map_err(CopyError::Io)?;
+    }
+
+    *remaining -= size;
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    const LIMITS: ArchiveLimits = ArchiveLimits { max_entries: 10, max_extracted_size: 1024 };
+
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("file2link_test_unpack_{}", name));
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir.to_string_lossy().into_owned()
+    }
+
+    fn write_zip(path: &str, files: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+        zip.add_directory("docs/", SimpleFileOptions::default()).unwrap();
+
+        for (name, content) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+
+        std::fs::write(path, zip.finish().unwrap().into_inner()).unwrap();
+    }
+
+    fn write_tar(path: &str, files: &[(&str, &[u8])]) {
+        let mut tar = tar::Builder::new(Vec::new());
+
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            tar.append_data(&mut header, name, *content).unwrap();
+        }
+
+        std::fs::write(path, tar.into_inner().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_archive_kind() {
+        assert_eq!(ArchiveKind::from_file_name("abcde_Photos.ZIP"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::from_file_name("backup.tar"), Some(ArchiveKind::Tar));
+        assert_eq!(ArchiveKind::from_file_name("backup.tar.gz"), Some(ArchiveKind::TarGz));
+        assert_eq!(ArchiveKind::from_file_name("backup.tgz"), Some(ArchiveKind::TarGz));
+        assert_eq!(ArchiveKind::from_file_name("report.pdf"), None);
+    }
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(entry_path(Path::new("docs/readme.txt")), Some("docs/readme.txt".to_owned()));
+        assert_eq!(entry_path(Path::new("./docs/readme.txt")), Some("docs/readme.txt".to_owned()));
+        assert_eq!(entry_path(Path::new("../readme.txt")), None);
+        assert_eq!(entry_path(Path::new("/etc/passwd")), None);
+        assert_eq!(entry_path(Path::new(".")), None);
+    }
+
+    #[test]
+    fn test_list_and_extract_zip() {
+        let dir = test_dir("zip");
+        let path = format!("{}/a.zip", dir);
+
+        write_zip(&path, &[("docs/readme.txt", b"hello"), ("data.bin", &[7; 100])]);
+
+        let entries = list(&path, ArchiveKind::Zip, LIMITS).unwrap();
+
+        assert_eq!(entries, vec![
+            ArchiveEntry { path: "docs/readme.txt".to_owned(), size: 5, file_name: None },
+            ArchiveEntry { path: "data.bin".to_owned(), size: 100, file_name: None },
+        ]);
+
+        let (extracted_entries, extracted) = extract(&path, ArchiveKind::Zip, LIMITS).unwrap();
+
+        assert_eq!(extracted_entries, entries);
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(extracted[0].index, 0);
+        assert_eq!(extracted[0].size, 5);
+        assert_eq!(extracted[0].sha256, hex::encode(Sha256::digest(b"hello")));
+        assert_eq!(std::fs::read(&extracted[0].path).unwrap(), b"hello");
+        assert_eq!(std::fs::read(&extracted[1].path).unwrap(), vec![7; 100]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_extract_tar() {
+        let dir = test_dir("tar");
+        let path = format!("{}/a.tar", dir);
+
+        write_tar(&path, &[("notes/a.txt", b"first"), ("b.txt", b"second")]);
+
+        let (entries, extracted) = extract(&path, ArchiveKind::Tar, LIMITS).unwrap();
+
+        assert_eq!(entries.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>(), vec!["notes/a.txt", "b.txt"]);
+        assert_eq!(std::fs::read(&extracted[1].path).unwrap(), b"second");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_extract_limits() {
+        let dir = test_dir("limits");
+        let path = format!("{}/a.zip", dir);
+
+        // compresses to a few bytes, the extracted size is checked while writing
+        write_zip(&path, &[("zeros.bin", &[0; 2048])]);
+
+        assert_eq!(extract(&path, ArchiveKind::Zip, LIMITS), Err("the files are bigger than 1.0 KB".to_owned()));
+
+        let files: Vec<(String, Vec<u8>)> = (0..11).map(|i| (format!("{}.txt", i), vec![1])).collect();
+        let files: Vec<(&str, &[u8])> = files.iter().map(|(name, content)| (name.as_str(), content.as_slice())).collect();
+
+        write_zip(&path, &files);
+
+        assert_eq!(list(&path, ArchiveKind::Zip, LIMITS), Err("the archive has more than 10 files".to_owned()));
+        assert!(extract(&path, ArchiveKind::Zip, LIMITS).is_err());
+
+        std::fs::write(&path, b"not a zip").unwrap();
+
+        assert!(list(&path, ArchiveKind::Zip, LIMITS).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
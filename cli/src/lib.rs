@@ -1,5 +1,14 @@
 use crate::utils::send_command;
 use log::{error, info};
+use shared::config::{self, storage_backend, Config, Variables};
+use shared::failed::load_failed;
+use shared::metadata::load_metadata;
+use shared::migrate::{migrate, storage_names, Migrated};
+use shared::queue_snapshot::load_snapshot;
+use shared::secrets;
+use shared::storage;
+use shared::usage::now_seconds;
+use shared::utils::format_size;
 use structopt::StructOpt;
 
 pub mod utils;
@@ -27,8 +36,59 @@ pub struct Cli {
 pub enum Command {
     #[structopt(about = "Updates the permissions from the config file")]
     UpdatePermissions,
+    #[structopt(about = "Reloads the environment variables and the permissions, like SIGHUP")]
+    ReloadConfig,
     #[structopt(about = "Shutting down the system")]
     Shutdown,
+    #[structopt(about = "Lists the files which failed after all their attempts")]
+    Failed,
+    #[structopt(about = "Queues a failed file again")]
+    Requeue {
+        /// ID of the failed file, shown by the failed command
+        id: u64,
+    },
+    #[structopt(about = "Cancels a queued file")]
+    Cancel {
+        /// Job ID of the file, shown in its status message and by the queue list command
+        id: String,
+    },
+    #[structopt(about = "Stops starting queued files, downloads in progress finish")]
+    Pause,
+    #[structopt(about = "Starts queued files again after pause")]
+    Resume,
+    #[structopt(about = "Checks the storage against the metadata, the summary is logged by the bot")]
+    Gc,
+    #[structopt(about = "Copies the files from one storage to another, run it while the bot is stopped")]
+    Migrate {
+        /// Storage the files are in: local, s3 or webdav, configured like with STORAGE_BACKEND
+        #[structopt(long)]
+        from: String,
+        /// Storage the files are copied to: local, s3 or webdav
+        #[structopt(long)]
+        to: String,
+    },
+    #[structopt(about = "Shows the queued files")]
+    Queue {
+        #[structopt(subcommand)]
+        command: QueueCommand,
+    },
+    #[structopt(about = "Shows the configuration")]
+    Config {
+        #[structopt(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum QueueCommand {
+    #[structopt(about = "Lists the queued files with their progress")]
+    List,
+}
+
+#[derive(StructOpt)]
+pub enum ConfigCommand {
+    #[structopt(about = "Prints every variable with the value in use and where it comes from, secrets are redacted")]
+    Show,
 }
 
 pub struct CommandProcessor {
@@ -48,12 +108,167 @@ impl CommandProcessor {
                     Err(_) => error!("Failed to send command 'update_permissions' to {}", self.path),
                 }
             }
+            Command::ReloadConfig => {
+                match send_command(&self.path, "reload_config").await {
+                    Ok(_) => info!("Command 'reload_config' sent to {}", self.path),
+                    Err(_) => error!("Failed to send command 'reload_config' to {}", self.path),
+                }
+            }
             Command::Shutdown => {
                 match send_command(&self.path, "shutdown").await {
                     Ok(_) => info!("Command 'shutdown' sent to {}", self.path),
                     Err(_) => error!("Failed to send command 'shutdown' to {}", self.path),
                 }
             }
+            Command::Failed => {
+                match load_failed().await {
+                    Ok(failed) if failed.files.is_empty() => println!("No failed files"),
+                    Ok(failed) => {
+                        let now = now_seconds();
+
+                        for file in failed.files {
+                            println!("{}", file.summary(now));
+                        }
+                    }
+                    Err(e) => error!("Failed to load failed files: {}", e),
+                }
+            }
+            Command::Pause => {
+                match send_command(&self.path, "pause").await {
+                    Ok(_) => info!("Command 'pause' sent to {}", self.path),
+                    Err(_) => error!("Failed to send command 'pause' to {}", self.path),
+                }
+            }
+            Command::Resume => {
+                match send_command(&self.path, "resume").await {
+                    Ok(_) => info!("Command 'resume' sent to {}", self.path),
+                    Err(_) => error!("Failed to send command 'resume' to {}", self.path),
+                }
+            }
+            Command::Gc => {
+                match send_command(&self.path, "gc").await {
+                    Ok(_) => info!("Command 'gc' sent to {}", self.path),
+                    Err(_) => error!("Failed to send command 'gc' to {}", self.path),
+                }
+            }
+            Command::Migrate { from, to } => migrate_storage(&from, &to).await,
+            Command::Queue { command: QueueCommand::List } => {
+                match load_snapshot().await {
+                    Ok(snapshot) if snapshot.jobs.is_empty() => println!("The queue is empty"),
+                    Ok(snapshot) => {
+                        let now = now_seconds();
+
+                        if snapshot.paused {
+                            println!("The queue is paused");
+                        }
+
+                        for job in snapshot.jobs {
+                            println!("{}", job.summary(now));
+                        }
+                    }
+                    Err(e) => error!("Failed to load queue snapshot: {}", e),
+                }
+            }
+            Command::Config { command: ConfigCommand::Show } => show_config().await,
+            Command::Cancel { id } => {
+                match send_command(&self.path, &format!("cancel {}", id)).await {
+                    Ok(_) => info!("Command 'cancel {}' sent to {}", id, self.path),
+                    Err(_) => error!("Failed to send command 'cancel {}' to {}", id, self.path),
+                }
+            }
+            Command::Requeue { id } => {
+                match send_command(&self.path, &format!("requeue {}", id)).await {
+                    Ok(_) => info!("Command 'requeue {}' sent to {}", id, self.path),
+                    Err(_) => error!("Failed to send command 'requeue {}' to {}", id, self.path),
+                }
+            }
+        }
+    }
+}
+
+/// Prints the configuration the bot would start with, read from the environment, `.env` and the secrets provider like
+/// by the bot
+async fn show_config() {
+    config::load_env();
+    secrets::load_secrets().await;
+
+    let config = Config::new();
+
+    for setting in config.settings() {
+        println!("{:<28} {:<8} {}", setting.var, setting.source, setting.value);
+    }
+
+    if !config.errors().is_empty() {
+        println!();
+
+        for e in config.errors() {
+            println!("{}, the default is used", e);
+        }
+    }
+}
+
+/// Copies every stored file to the other storage and prints the progress, the metadata stays valid as the files keep
+/// their names
+async fn migrate_storage(from: &str, to: &str) {
+    config::load_env();
+
+    let variables = Variables::from_env();
+
+    let (from_config, to_config) = match (storage_backend(&variables, from), storage_backend(&variables, to)) {
+        (Ok(from_config), Ok(to_config)) => (from_config, to_config),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to migrate: {}", e);
+
+            return;
+        }
+    };
+
+    if from_config == to_config {
+        error!("Failed to migrate: '{}' and '{}' are the same storage", from, to);
+
+        return;
+    }
+
+    let metadata = match load_metadata().await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            error!("Failed to load metadata: {}", e);
+
+            return;
+        }
+    };
+
+    let config = Config::new();
+    let from_storage = storage::backend(from_config, &config);
+    let to_storage = storage::backend(to_config, &config);
+
+    let names = match storage_names(&metadata, &from_storage).await {
+        Ok(names) => names,
+        Err(e) => {
+            error!("Failed to list the files in '{}': {}", from, e);
+
+            return;
         }
+    };
+
+    println!("Migrating {} files from {} to {}", names.len(), from, to);
+
+    let report = migrate(&from_storage, &to_storage, &names, |index, name, result| match result {
+        Ok(Migrated::Copied(size)) => println!("[{}/{}] Copied {} ({})", index, names.len(), name, format_size(*size)),
+        Ok(Migrated::Skipped) => println!("[{}/{}] Skipped {}, it is already there", index, names.len(), name),
+        Err(e) => println!("[{}/{}] Failed to migrate {}: {}", index, names.len(), name, e),
+    }).await;
+
+    println!(
+        "Copied {} files ({}), skipped {}, failed {}",
+        report.copied,
+        format_size(report.copied_bytes),
+        report.skipped,
+        report.failed.len(),
+    );
+
+    match report.failed.is_empty() {
+        true => println!("Set STORAGE_BACKEND={} and start the bot to serve the files from it", to),
+        false => println!("Run the migration again to retry the failed files, copied files are skipped"),
     }
-}
\ No newline at end of file
+}
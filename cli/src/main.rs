@@ -19,7 +19,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
 #[cfg(test)]
 mod tests {
-    use std::env;
     use std::fs;
 
     use crate::utils::send_command;
@@ -39,7 +38,6 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial_test::serial]
     async fn test_send_command_success() {
         let binding = create_rnd_file().await;
         let path = binding.as_str();
@@ -55,7 +53,6 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial_test::serial]
     async fn test_send_command_failure() {
         let path = "/invalid/path/to/file.pipe";
 
@@ -65,7 +62,6 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial_test::serial]
     async fn test_cli_update_permissions() {
         let binding = create_rnd_file().await;
         let path = binding.as_str();
@@ -85,7 +81,6 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial_test::serial]
     async fn test_cli_shutdown() {
         let binding = create_rnd_file().await;
         let path = binding.as_str();
@@ -103,16 +98,13 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial_test::serial]
     async fn test_cli_default_path() {
         let binding = create_rnd_file().await;
         let path = binding.as_str();
 
-        env::set_var("F2L_PIPE_PATH", path);
-
         let mut cmd = Command::cargo_bin("f2l-cli").unwrap();
 
-        cmd.arg("shutdown");
+        cmd.env("F2L_PIPE_PATH", path).arg("shutdown");
         cmd.assert().success();
 
         let content = match fs::read_to_string(path) {
@@ -1,13 +1,33 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use log::{debug, error};
+use log::{debug, error, info, warn};
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::fs;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::sync::Mutex;
+
+use crate::metadata::FileMetadata;
+use crate::utils::parse_duration;
 
 const CONFIG_PATH: &str = "config/permissions.json";
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How long the permissions file has to stay unchanged before it is read again, editors write it in several steps
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Version of the permissions format written by this build, older files are upgraded by `migrate_config`
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Steps upgrading the permissions, the first one from version 1 to 2, each returns what it changed
+const MIGRATIONS: [fn(&mut Value) -> Vec<String>; (CONFIG_VERSION - 1) as usize] = [migrate_v1];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 #[derive(PartialEq)]
 enum UsersArrayConfig {
@@ -15,7 +35,7 @@ enum UsersArrayConfig {
     IntegerUser(i64),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 #[derive(PartialEq)]
 enum UsersConfig {
@@ -26,69 +46,181 @@ enum UsersConfig {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PermissionsConfig {
+    /// Version of the format, files written before the field was added are version 1
+    #[serde(default = "unversioned")]
+    version: u32,
     allow_all: UsersConfig,
     chats: HashMap<String, UsersConfig>,
+    /// Users allowed to moderate the bot with admin commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    admins: Option<UsersConfig>,
+    /// Users denied access regardless of other rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    banned: Vec<String>,
+    /// Chats where posts without a user sender are accepted: channel posts and anonymous admins
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    channels: Vec<String>,
+    /// Chats where unsupported messages get a usage hint, "private" enables it in all private chats
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    hint_chats: Vec<String>,
+    /// How long files of specific chats and users are kept, enforced by the cleanup task
+    #[serde(default, skip_serializing_if = "Retention::is_empty")]
+    retention: Retention,
+}
+
+/// How long files are kept after they are uploaded, by the chat they were uploaded in or by the uploader
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Retention {
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    chats: HashMap<String, RetentionPeriod>,
+    /// Takes precedence over the retention of the chat
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    users: HashMap<String, RetentionPeriod>,
+}
+
+/// A duration like "90d" or "24h", kept as written so saving the config does not change it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct RetentionPeriod {
+    text: String,
+    seconds: u64,
+}
+
+impl TryFrom<String> for RetentionPeriod {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        match parse_duration(&text) {
+            Some(seconds) => Ok(RetentionPeriod { text, seconds }),
+            None => Err(format!("retention '{}' is not a duration like '90d'", text)),
+        }
+    }
+}
+
+impl From<RetentionPeriod> for String {
+    fn from(period: RetentionPeriod) -> Self {
+        period.text
+    }
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        self.chats.is_empty() && self.users.is_empty()
+    }
+
+    /// Get the seconds the file is kept for, the longest of the periods of its uploaders
+    /// Chats are only looked at when none of the uploaders has a period of their own
+    ///
+    /// # Returns
+    /// * `None` if neither the uploaders nor the chats of the file have a retention
+    pub fn period(&self, file: &FileMetadata) -> Option<u64> {
+        let longest = |periods: &HashMap<String, RetentionPeriod>, ids: &[String]| ids.iter()
+            .filter_map(|id| periods.get(id))
+            .map(|period| period.seconds)
+            .max();
+
+        longest(&self.users, &file.owners).or_else(|| longest(&self.chats, &file.chats))
+    }
+
+    /// Tells whether the file is older than its retention, see `period`
+    pub fn is_expired(&self, file: &FileMetadata, now: u64) -> bool {
+        self.period(file).is_some_and(|period| file.created_at.saturating_add(period) <= now)
+    }
 }
 
 impl PermissionsConfig {
     pub fn init_allow_all() -> Self {
         PermissionsConfig {
+            version: CONFIG_VERSION,
             allow_all: UsersConfig::StringUsers("*".to_string()),
             chats: HashMap::new(),
+            admins: None,
+            banned: Vec::new(),
+            channels: Vec::new(),
+            hint_chats: Vec::new(),
+            retention: Retention::default(),
         }
     }
 
-    pub fn user_has_access(&self, chat_id: String, user_id: &String) -> bool {
-        fn process_users_config(cfg: &UsersConfig, user_id: &String) -> bool {
-            match cfg {
-                UsersConfig::SingleUser(user) if user.to_string() == user_id.to_string() => {
-                    debug!("User '{}' has access due to allow_all rule", user_id);
+    pub fn retention(&self) -> &Retention {
+        &self.retention
+    }
 
-                    return true;
-                }
-                UsersConfig::StringUsers(users) => {
-                    if users == "*" || users.to_owned() == user_id.to_owned() {
-                        debug!("User '{}' has access due to allow_all rule", user_id);
+    pub fn is_admin(&self, user_id: &String) -> bool {
+        match &self.admins {
+            Some(admins) => process_users_config(admins, user_id),
+            None => false,
+        }
+    }
 
-                        return true;
-                    }
+    pub fn is_banned(&self, user_id: &String) -> bool {
+        self.banned.contains(user_id)
+    }
 
-                    if users.contains(',') {
-                        let ids: Vec<&str> = users.split(',').map(|u| u.trim()).collect();
+    /// Adds the user to the deny list
+    ///
+    /// # Returns
+    /// * `false` if the user is already banned
+    pub fn ban(&mut self, user_id: String) -> bool {
+        if self.is_banned(&user_id) {
+            return false;
+        }
 
-                        if ids.contains(&user_id.as_str()) {
-                            debug!("User '{}' has access due to allow_all rule", user_id);
+        debug!("Banning user '{}'", user_id);
 
-                            return true;
-                        }
-                    }
+        self.banned.push(user_id);
 
-                    debug!("User '{}' does not have access due to allow_all rule", user_id);
+        true
+    }
 
-                    return false;
-                }
-                UsersConfig::ArrayUsers(users) => {
-                    let ids: Vec<String> = users.iter().map(|user| match user {
-                        UsersArrayConfig::StringUser(id) => id.trim().to_owned(),
-                        UsersArrayConfig::IntegerUser(id) => id.to_string().trim().to_owned(),
-                    }).collect();
+    /// Removes the user from the deny list
+    ///
+    /// # Returns
+    /// * `false` if the user is not banned
+    pub fn unban(&mut self, user_id: &String) -> bool {
+        if !self.is_banned(user_id) {
+            return false;
+        }
 
-                    if ids.contains(&user_id) {
-                        debug!("User '{}' has access due to allow_all specific users rule", user_id);
+        debug!("Unbanning user '{}'", user_id);
 
-                        return true;
-                    }
+        self.banned.retain(|banned| banned != user_id);
 
-                    debug!("User '{}' does not have access due to allow_all specific users rule", user_id);
+        true
+    }
 
-                    return false;
-                }
-                _ => { false }
-            }
+    /// Grants the user access to the chat, or to all chats if no chat is given
+    ///
+    /// # Returns
+    /// * `false` if the user already has access by that rule
+    pub fn grant(&mut self, user_id: &String, chat_id: Option<String>) -> bool {
+        let users = match chat_id {
+            Some(chat_id) => self.chats.entry(chat_id)
+                .or_insert_with(|| UsersConfig::ArrayUsers(Vec::new())),
+            None => &mut self.allow_all,
+        };
+
+        if process_users_config(users, user_id) {
+            return false;
         }
 
+        debug!("Granting access to user '{}'", user_id);
+
+        *users = add_user(users, user_id);
+
+        true
+    }
+
+    pub fn user_has_access(&self, chat_id: String, user_id: &String) -> bool {
         debug!("Checking access for user '{}' in chat '{}'", user_id, chat_id);
 
+        if self.is_banned(user_id) {
+            debug!("User '{}' does not have access due to ban", user_id);
+
+            return false;
+        }
+
         if process_users_config(&self.allow_all, user_id) {
             return true;
         }
@@ -99,6 +231,191 @@ impl PermissionsConfig {
 
         false
     }
+
+    /// Checks access for a message in a forum topic
+    /// A `chats` entry keyed "<chat_id>/<topic_id>" takes precedence over the entry of the whole chat
+    pub fn user_has_topic_access(&self, chat_id: String, topic_id: Option<i32>, user_id: &String) -> bool {
+        let topic = topic_id.and_then(|topic_id| self.chats.get(&format!("{}/{}", chat_id, topic_id)));
+
+        let topic = match topic {
+            Some(topic) => topic,
+            None => return self.user_has_access(chat_id, user_id),
+        };
+
+        debug!("Checking access for user '{}' in topic {:?} of chat '{}'", user_id, topic_id, chat_id);
+
+        if self.is_banned(user_id) {
+            return false;
+        }
+
+        process_users_config(&self.allow_all, user_id) || process_users_config(topic, user_id)
+    }
+
+    pub fn hints_enabled(&self, chat_id: &String, is_private: bool) -> bool {
+        self.hint_chats.iter()
+            .any(|chat| chat == chat_id || (is_private && chat == "private"))
+    }
+
+    /// Checks access for posts sent on behalf of a chat, they have no user to check
+    pub fn channel_has_access(&self, chat_id: &String) -> bool {
+        debug!("Checking access for channel posts in chat '{}'", chat_id);
+
+        self.channels.contains(chat_id)
+    }
+}
+
+fn process_users_config(cfg: &UsersConfig, user_id: &String) -> bool {
+    match cfg {
+        UsersConfig::SingleUser(user) if user.to_string() == user_id.to_string() => {
+            debug!("User '{}' has access due to allow_all rule", user_id);
+
+            return true;
+        }
+        UsersConfig::StringUsers(users) => {
+            if users == "*" || users.to_owned() == user_id.to_owned() {
+                debug!("User '{}' has access due to allow_all rule", user_id);
+
+                return true;
+            }
+
+            if users.contains(',') {
+                let ids: Vec<&str> = users.split(',').map(|u| u.trim()).collect();
+
+                if ids.contains(&user_id.as_str()) {
+                    debug!("User '{}' has access due to allow_all rule", user_id);
+
+                    return true;
+                }
+            }
+
+            debug!("User '{}' does not have access due to allow_all rule", user_id);
+
+            return false;
+        }
+        UsersConfig::ArrayUsers(users) => {
+            let ids: Vec<String> = users.iter().map(|user| match user {
+                UsersArrayConfig::StringUser(id) => id.trim().to_owned(),
+                UsersArrayConfig::IntegerUser(id) => id.to_string().trim().to_owned(),
+            }).collect();
+
+            if ids.contains(&user_id) {
+                debug!("User '{}' has access due to allow_all specific users rule", user_id);
+
+                return true;
+            }
+
+            debug!("User '{}' does not have access due to allow_all specific users rule", user_id);
+
+            return false;
+        }
+        _ => { false }
+    }
+}
+
+/// Converts the users config to a list including the user
+fn add_user(cfg: &UsersConfig, user_id: &String) -> UsersConfig {
+    let mut users: Vec<UsersArrayConfig> = match cfg {
+        UsersConfig::SingleUser(user) => vec![UsersArrayConfig::IntegerUser(*user)],
+        UsersConfig::StringUsers(users) => users.split(',')
+            .map(|user| user.trim())
+            .filter(|user| !user.is_empty())
+            .map(|user| UsersArrayConfig::StringUser(user.to_owned()))
+            .collect(),
+        UsersConfig::ArrayUsers(users) => users.clone(),
+    };
+
+    users.push(match user_id.parse::<i64>() {
+        Ok(id) => UsersArrayConfig::IntegerUser(id),
+        Err(_) => UsersArrayConfig::StringUser(user_id.to_owned()),
+    });
+
+    UsersConfig::ArrayUsers(users)
+}
+
+fn unversioned() -> u32 {
+    1
+}
+
+/// Version 2 reads the IDs in `banned`, `channels` and `hint_chats` as strings only, numbers written by hand in older
+/// files are converted
+fn migrate_v1(config: &mut Value) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    for field in ["banned", "channels", "hint_chats"] {
+        let ids = match config.get_mut(field).and_then(Value::as_array_mut) {
+            Some(ids) => ids,
+            None => continue,
+        };
+
+        for id in ids.iter_mut().filter(|id| id.is_number()) {
+            changes.push(format!("'{}' entry {} is now a string", field, id));
+
+            *id = Value::String(id.to_string());
+        }
+    }
+
+    changes
+}
+
+/// Upgrades the permissions to `CONFIG_VERSION`
+///
+/// # Returns
+/// * `Result` containing the version the permissions had and what was changed, or an error if the permissions are from
+///   a newer build
+fn migrate_config(config: &mut Value) -> Result<(u32, Vec<String>), String> {
+    let version = match config.get("version") {
+        Some(version) => version.as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("version {} is not a number", version))?,
+        None => unversioned(),
+    };
+
+    if version > CONFIG_VERSION {
+        return Err(format!("version {} is newer than the supported version {}", version, CONFIG_VERSION));
+    }
+
+    let mut changes = Vec::new();
+
+    for migration in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+        changes.extend(migration(config));
+    }
+
+    if version < CONFIG_VERSION {
+        config["version"] = Value::from(CONFIG_VERSION);
+    }
+
+    Ok((version, changes))
+}
+
+/// Upgrades the permissions file if it is from an older version, the old file is kept next to it with the version as
+/// extension
+///
+/// # Returns
+/// * `Result` containing the upgraded permissions
+async fn upgrade_config(data: &str) -> Result<Value, Box<dyn Error>> {
+    let mut config: Value = serde_json::from_str(data)?;
+
+    let (version, changes) = migrate_config(&mut config)?;
+
+    if version == CONFIG_VERSION {
+        return Ok(config);
+    }
+
+    let backup_path = format!("{}.v{}.bak", CONFIG_PATH, version);
+
+    fs::write(&backup_path, data).await?;
+    fs::write(CONFIG_PATH, serde_json::to_string_pretty(&config)?).await?;
+
+    info!(
+        "Upgraded '{}' from version {} to {}, the old file is kept as '{}'",
+        CONFIG_PATH, version, CONFIG_VERSION, backup_path
+    );
+
+    for change in changes {
+        info!("Permissions migration: {}", change);
+    }
+
+    Ok(config)
 }
 
 async fn create_initial_config() -> Result<(), Box<dyn Error>> {
@@ -124,24 +441,132 @@ pub async fn load_config() -> Result<PermissionsConfig, Box<dyn Error>> {
 
                 debug!("Attempt {} to read config failed, creating initial config", attempts + 1);
 
-                create_initial_config().await.expect("Failed to create initial config");
+                create_initial_config().await.map_err(|e| format!("Failed to create initial config: {}", e))?;
                 attempts += 1;
             }
         }
     };
 
-    let config: PermissionsConfig = match serde_json::from_str(&data) {
+    let config = parse_config(&data).await?;
+
+    debug!("Successfully loaded configuration");
+
+    Ok(config)
+}
+
+/// Parses the content of the permissions file, upgrading it if it is from an older version
+async fn parse_config(data: &str) -> Result<PermissionsConfig, Box<dyn Error>> {
+    let config = match upgrade_config(data).await {
         Ok(config) => config,
+        Err(e) => {
+            error!("Failed to upgrade configuration: {}", e);
+
+            return Err("Failed to upgrade configuration".into());
+        }
+    };
+
+    match serde_json::from_value(config) {
+        Ok(config) => Ok(config),
         Err(e) => {
             error!("Failed to parse configuration: {}", e);
 
-            return Err("Failed to parse configuration".into());
+            Err("Failed to parse configuration".into())
         }
+    }
+}
+
+/// Replaces the permissions with the content of the permissions file if it differs, like after the bot saved them
+///
+/// # Returns
+/// * `Result` containing whether the permissions changed, invalid content leaves them as they are
+async fn apply_config(permissions: &mut PermissionsConfig, data: &str) -> Result<bool, Box<dyn Error>> {
+    let new_permissions = parse_config(data).await?;
+
+    if serde_json::to_value(&*permissions)? == serde_json::to_value(&new_permissions)? {
+        return Ok(false);
+    }
+
+    *permissions = new_permissions;
+
+    Ok(true)
+}
+
+/// Waits for a change and until no other change follows for `quiet`
+///
+/// # Returns
+/// * `None` once the watcher is gone
+async fn next_change(changes: &mut UnboundedReceiver<()>, quiet: Duration) -> Option<()> {
+    changes.recv().await?;
+
+    while let Ok(change) = tokio::time::timeout(quiet, changes.recv()).await {
+        change?;
+    }
+
+    Some(())
+}
+
+/// Reloads the permissions when `config/permissions.json` changes, like `f2l-cli update-permissions`
+/// A removed or invalid file keeps the permissions loaded before, the bot runs without the watcher if the file system
+/// can not be watched
+pub async fn watch_config(permissions: Arc<Mutex<PermissionsConfig>>) {
+    let path = Path::new(CONFIG_PATH);
+    let (file_name, folder) = match (path.file_name(), path.parent()) {
+        (Some(file_name), Some(folder)) => (file_name.to_owned(), folder),
+        _ => return,
     };
 
-    debug!("Successfully loaded configuration");
+    let (tx, mut changes) = mpsc::unbounded_channel();
 
-    Ok(config)
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+
+        // reads of the file are not changes
+        let changed = matches!(
+            event.kind,
+            EventKind::Create(_)
+                | EventKind::Modify(_)
+                | EventKind::Remove(_)
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        );
+
+        if changed && event.paths.iter().any(|path| path.file_name() == Some(file_name.as_os_str())) {
+            let _ = tx.send(());
+        }
+    });
+
+    // the folder is watched as editors replace the file instead of writing to it
+    let _watcher = match watcher.and_then(|mut watcher| watcher.watch(folder, RecursiveMode::NonRecursive).map(|_| watcher)) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Failed to watch '{}', reload the permissions with f2l-cli update-permissions: {}", CONFIG_PATH, e);
+
+            return;
+        }
+    };
+
+    info!("Watching '{}' for changes", CONFIG_PATH);
+
+    while next_change(&mut changes, WATCH_DEBOUNCE).await.is_some() {
+        // the bot saves the permissions while holding the lock, so the file is read as it saved them
+        let mut permissions = permissions.lock().await;
+
+        let data = match fs::read_to_string(CONFIG_PATH).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to read '{}', keeping the current permissions: {}", CONFIG_PATH, e);
+
+                continue;
+            }
+        };
+
+        match apply_config(&mut permissions, &data).await {
+            Ok(true) => info!("Permissions reloaded from '{}'", CONFIG_PATH),
+            Ok(false) => debug!("Permissions in '{}' are unchanged", CONFIG_PATH),
+            Err(e) => warn!("Invalid permissions in '{}', keeping the current ones: {}", CONFIG_PATH, e),
+        }
+    }
 }
 
 pub async fn save_config(config: &PermissionsConfig) -> Result<(), Box<dyn Error>> {
@@ -170,8 +595,14 @@ mod tests {
     impl PermissionsConfig {
         fn init_empty() -> Self {
             PermissionsConfig {
+                version: CONFIG_VERSION,
                 allow_all: UsersConfig::StringUsers("".to_string()),
                 chats: HashMap::new(),
+                admins: None,
+                banned: Vec::new(),
+                channels: Vec::new(),
+                hint_chats: Vec::new(),
+                retention: Retention::default(),
             }
         }
     }
@@ -253,4 +684,235 @@ mod tests {
         assert!(config.user_has_access("chat3".to_string(), &"123".to_string()));
         assert!(!config.user_has_access("chat3".to_string(), &"user2".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_ban_and_unban() {
+        let mut config = PermissionsConfig::init_allow_all();
+
+        assert!(config.ban("123".to_string()));
+        assert!(!config.ban("123".to_string()));
+        assert!(!config.user_has_access("any_chat".to_string(), &"123".to_string()));
+        assert!(config.user_has_access("any_chat".to_string(), &"456".to_string()));
+
+        assert!(config.unban(&"123".to_string()));
+        assert!(!config.unban(&"123".to_string()));
+        assert!(config.user_has_access("any_chat".to_string(), &"123".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_user_has_topic_access() {
+        let mut config = PermissionsConfig::init_empty();
+
+        config.chats.insert("chat1".to_string(), UsersConfig::StringUsers("*".to_string()));
+        config.chats.insert("chat1/4".to_string(), UsersConfig::SingleUser(123));
+
+        assert!(config.user_has_topic_access("chat1".to_string(), Some(4), &"123".to_string()));
+        assert!(!config.user_has_topic_access("chat1".to_string(), Some(4), &"456".to_string()));
+        assert!(config.user_has_topic_access("chat1".to_string(), Some(5), &"456".to_string()));
+        assert!(config.user_has_topic_access("chat1".to_string(), None, &"456".to_string()));
+        assert!(!config.user_has_topic_access("chat2".to_string(), Some(4), &"123".to_string()));
+
+        config.ban("123".to_string());
+
+        assert!(!config.user_has_topic_access("chat1".to_string(), Some(4), &"123".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_hints_enabled() {
+        let mut config = PermissionsConfig::init_allow_all();
+
+        assert!(!config.hints_enabled(&"123".to_string(), true));
+
+        config.hint_chats = vec!["private".to_string(), "-100123".to_string()];
+
+        assert!(config.hints_enabled(&"123".to_string(), true));
+        assert!(config.hints_enabled(&"-100123".to_string(), false));
+        assert!(!config.hints_enabled(&"-100456".to_string(), false));
+    }
+
+    #[tokio::test]
+    async fn test_channel_has_access() {
+        let mut config = PermissionsConfig::init_allow_all();
+
+        assert!(!config.channel_has_access(&"-100123".to_string()));
+
+        config.channels.push("-100123".to_string());
+
+        assert!(config.channel_has_access(&"-100123".to_string()));
+        assert!(!config.channel_has_access(&"-100456".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_channels_default() {
+        let config: PermissionsConfig = serde_json::from_str(r#"{"allow_all": "*", "chats": {}}"#).unwrap();
+
+        assert!(config.channels.is_empty());
+        assert!(!serde_json::to_string(&config).unwrap().contains("channels"));
+    }
+
+    #[tokio::test]
+    async fn test_is_admin() {
+        let mut config = PermissionsConfig::init_allow_all();
+
+        assert!(!config.is_admin(&"123".to_string()));
+
+        config.admins = Some(UsersConfig::SingleUser(123));
+
+        assert!(config.is_admin(&"123".to_string()));
+        assert!(!config.is_admin(&"456".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_parse_config_without_admins_and_banned() {
+        let config: PermissionsConfig = serde_json::from_str(r#"{"allow_all": "*", "chats": {}}"#).unwrap();
+
+        assert_eq!(config.admins, None);
+        assert!(config.banned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_grant_to_chat() {
+        let mut config = PermissionsConfig::init_empty();
+
+        assert!(!config.user_has_access("chat1".to_string(), &"123".to_string()));
+
+        assert!(config.grant(&"123".to_string(), Some("chat1".to_string())));
+        assert!(!config.grant(&"123".to_string(), Some("chat1".to_string())));
+
+        assert!(config.user_has_access("chat1".to_string(), &"123".to_string()));
+        assert!(!config.user_has_access("chat2".to_string(), &"123".to_string()));
+        assert_eq!(config.chats["chat1"], UsersConfig::ArrayUsers(vec![UsersArrayConfig::IntegerUser(123)]));
+    }
+
+    #[tokio::test]
+    async fn test_grant_to_all_chats() {
+        let mut config = PermissionsConfig::init_empty();
+
+        config.allow_all = UsersConfig::StringUsers("user1, user2".to_string());
+
+        assert!(config.grant(&"123".to_string(), None));
+
+        assert!(config.user_has_access("any_chat".to_string(), &"123".to_string()));
+        assert!(config.user_has_access("any_chat".to_string(), &"user1".to_string()));
+        assert_eq!(config.allow_all, UsersConfig::ArrayUsers(vec![
+            UsersArrayConfig::StringUser("user1".to_string()),
+            UsersArrayConfig::StringUser("user2".to_string()),
+            UsersArrayConfig::IntegerUser(123),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_retention() {
+        let config: PermissionsConfig = serde_json::from_str(r#"{
+            "allow_all": "*",
+            "chats": {},
+            "retention": {"chats": {"work": "90d", "public": "24h"}, "users": {"123": "7d"}}
+        }"#).unwrap();
+
+        let file = |owners: &[&str], chats: &[&str]| FileMetadata {
+            file_name: "abcde_report.pdf".to_owned(),
+            id: None,
+            size: 10,
+            sha256: None,
+            file_unique_id: None,
+            created_at: 1000,
+            expires_at: None,
+            downloaded_at: None,
+            owners: owners.iter().map(|owner| owner.to_string()).collect(),
+            chats: chats.iter().map(|chat| chat.to_string()).collect(),
+            private: false,
+            content_addressed: false,
+            folder: None,
+            scanned: false,
+            mirror: None,
+            archive_entries: Vec::new(),
+            versions: Vec::new(),
+        };
+
+        let retention = config.retention();
+
+        assert_eq!(retention.period(&file(&["456"], &["public"])), Some(24 * 60 * 60));
+        assert_eq!(retention.period(&file(&["456"], &["public", "work"])), Some(90 * 24 * 60 * 60));
+        assert_eq!(retention.period(&file(&["123"], &["work"])), Some(7 * 24 * 60 * 60));
+        assert_eq!(retention.period(&file(&["456"], &["other"])), None);
+
+        assert!(!retention.is_expired(&file(&["456"], &["public"]), 1000 + 24 * 60 * 60 - 1));
+        assert!(retention.is_expired(&file(&["456"], &["public"]), 1000 + 24 * 60 * 60));
+        assert!(!retention.is_expired(&file(&["456"], &["other"]), u64::MAX));
+
+        assert!(serde_json::to_string(&config).unwrap().contains(r#""users":{"123":"7d"}"#));
+        assert!(!serde_json::to_string(&PermissionsConfig::init_allow_all()).unwrap().contains("retention"));
+
+        let invalid = r#"{"allow_all": "*", "chats": {}, "retention": {"chats": {"work": "forever"}}}"#;
+
+        assert!(serde_json::from_str::<PermissionsConfig>(invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_grant_when_allow_all() {
+        let mut config = PermissionsConfig::init_allow_all();
+
+        assert!(!config.grant(&"123".to_string(), None));
+        assert_eq!(config.allow_all, UsersConfig::StringUsers("*".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_config() {
+        let mut value: Value = serde_json::from_str(r#"{"allow_all": "*", "chats": {}, "banned": [123, "456"]}"#).unwrap();
+
+        // numbers in the lists of IDs did not parse before version 2
+        assert!(serde_json::from_value::<PermissionsConfig>(value.clone()).is_err());
+
+        let (version, changes) = migrate_config(&mut value).unwrap();
+
+        assert_eq!(version, 1);
+        assert_eq!(changes, vec!["'banned' entry 123 is now a string"]);
+
+        let config: PermissionsConfig = serde_json::from_value(value.clone()).unwrap();
+
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.is_banned(&"123".to_string()));
+
+        // an upgraded file is not changed again
+        assert_eq!(migrate_config(&mut value), Ok((CONFIG_VERSION, Vec::new())));
+
+        let mut newer: Value = serde_json::from_str(r#"{"version": 99, "allow_all": "*", "chats": {}}"#).unwrap();
+
+        assert!(migrate_config(&mut newer).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply_config() {
+        let mut permissions = PermissionsConfig::init_allow_all();
+        let saved = serde_json::to_string_pretty(&permissions).unwrap();
+
+        // the file the bot saved itself changes nothing
+        assert!(!apply_config(&mut permissions, &saved).await.unwrap());
+
+        let changed = format!(r#"{{"version": {}, "allow_all": "", "chats": {{}}, "banned": ["123"]}}"#, CONFIG_VERSION);
+
+        assert!(apply_config(&mut permissions, &changed).await.unwrap());
+        assert!(permissions.is_banned(&"123".to_string()));
+
+        // a file saved halfway is not applied
+        assert!(apply_config(&mut permissions, &changed[..20]).await.is_err());
+        assert!(permissions.is_banned(&"123".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_next_change() {
+        let (tx, mut changes) = mpsc::unbounded_channel();
+
+        for _ in 0..3 {
+            tx.send(()).unwrap();
+        }
+
+        // the burst of changes is one change
+        assert_eq!(next_change(&mut changes, Duration::from_millis(10)).await, Some(()));
+        assert!(changes.try_recv().is_err());
+
+        drop(tx);
+
+        assert_eq!(next_change(&mut changes, Duration::from_millis(10)).await, None);
+    }
 }
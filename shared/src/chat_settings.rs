@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::templates::Language;
+
+const SETTINGS_PATH: &str = "config/settings.json";
+
+/// How browsers handle the generated links
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    /// The file is downloaded
+    #[default]
+    Attachment,
+    /// The file is opened in the browser if it can show it, e.g. images, videos and PDFs
+    Inline,
+}
+
+impl LinkStyle {
+    /// Builds the link to a stored file
+    ///
+    /// # Arguments
+    /// * `file_domain` - The configured `APP_FILE_DOMAIN` ending with a slash
+    pub fn link(&self, file_domain: &str, file_name: &str) -> String {
+        match self {
+            LinkStyle::Attachment => format!("{}{}", file_domain, file_name),
+            LinkStyle::Inline => format!("{}{}?inline", file_domain, file_name),
+        }
+    }
+}
+
+/// How stored files are named
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileNameStyle {
+    /// The random ID followed by the original name
+    #[default]
+    Original,
+    /// Only the random ID and the extension, the original name is not revealed by the link
+    Random,
+}
+
+/// Preferences of one chat, set by the users of the chat
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// Chat the stored files are copied to as a backup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive_chat: Option<i64>,
+    /// Seconds until links expire when the uploader does not choose, `None` means never
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
+    pub link_style: LinkStyle,
+    pub file_name_style: FileNameStyle,
+    pub language: Language,
+    /// In groups only messages meant for the bot are processed: commands, mentions and replies to the bot
+    pub addressed_only: bool,
+    /// EXIF and similar metadata is removed from photos before they are stored
+    pub strip_metadata: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SettingsStore {
+    chats: HashMap<String, ChatSettings>,
+}
+
+impl SettingsStore {
+    /// Get the settings of the chat, chats without settings get the defaults
+    pub fn get(&self, chat_id: &str) -> ChatSettings {
+        self.chats.get(chat_id).cloned().unwrap_or_default()
+    }
+
+    /// Changes the settings of the chat, chats left with the defaults are not stored
+    pub fn update(&mut self, chat_id: &str, change: impl FnOnce(&mut ChatSettings)) {
+        let mut settings = self.get(chat_id);
+
+        change(&mut settings);
+
+        if settings == ChatSettings::default() {
+            self.chats.remove(chat_id);
+        } else {
+            self.chats.insert(chat_id.to_owned(), settings);
+        }
+    }
+}
+
+pub async fn load_settings() -> Result<SettingsStore, Box<dyn Error>> {
+    let data = match fs::read_to_string(SETTINGS_PATH).await {
+        Ok(data) => data,
+        Err(_) => {
+            debug!("Settings file '{}' not found, starting with default settings", SETTINGS_PATH);
+
+            return Ok(SettingsStore::default());
+        }
+    };
+
+    match serde_json::from_str(&data) {
+        Ok(settings) => Ok(settings),
+        Err(e) => {
+            error!("Failed to parse settings: {}", e);
+
+            Err("Failed to parse settings".into())
+        }
+    }
+}
+
+pub async fn save_settings(settings: &SettingsStore) -> Result<(), Box<dyn Error>> {
+    if let Some((dir_path, _)) = SETTINGS_PATH.rsplit_once('/') {
+        fs::create_dir_all(dir_path).await?;
+    }
+
+    let data = serde_json::to_string_pretty(settings)?;
+    fs::write(SETTINGS_PATH, data).await?;
+
+    debug!("Settings saved to '{}'", SETTINGS_PATH);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_default() {
+        let store = SettingsStore::default();
+
+        assert_eq!(store.get("-100123"), ChatSettings::default());
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let mut store = SettingsStore::default();
+
+        store.update("-100123", |settings| settings.archive_chat = Some(-100456));
+
+        assert_eq!(store.get("-100123").archive_chat, Some(-100456));
+        assert_eq!(store.get("-100789").archive_chat, None);
+
+        store.update("-100123", |settings| settings.archive_chat = None);
+
+        assert!(store.chats.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_partial_settings_use_defaults() {
+        let settings: ChatSettings = serde_json::from_str(r#"{"archive_chat": -100456}"#).unwrap();
+
+        assert_eq!(settings.archive_chat, Some(-100456));
+        assert_eq!(settings.link_style, LinkStyle::Attachment);
+        assert_eq!(settings.language, Language::En);
+    }
+
+    #[tokio::test]
+    async fn test_link() {
+        assert_eq!(LinkStyle::Attachment.link("http://localhost/files/", "abcde_a.pdf"), "http://localhost/files/abcde_a.pdf");
+        assert_eq!(LinkStyle::Inline.link("http://localhost/files/", "abcde_a.pdf"), "http://localhost/files/abcde_a.pdf?inline");
+    }
+}
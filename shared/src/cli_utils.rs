@@ -1,14 +1,90 @@
 use crate::chat_config;
-use crate::config::Config;
+use crate::config::SharedConfig;
 use crate::utils::create_fifo;
 use log::{error, info, warn};
 use std::sync::Arc;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::Sender;
 use tokio::sync::Mutex;
 
-pub async fn handle_cli(permissions: Arc<Mutex<chat_config::PermissionsConfig>>) {
-    let path = Config::instance().await.pipe_path();
+/// Commands of the CLI handled by the queue
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueCommand {
+    /// Queue the failed file with the ID again
+    Requeue(u64),
+    /// Cancel the queued file with the job ID shown in its status message
+    Cancel(String),
+    /// Stop starting queued files, downloads in progress finish
+    Pause,
+    Resume,
+    /// Check the storage against the metadata now, see `gc::collect_garbage`
+    CollectGarbage,
+}
+
+fn parse_queue_command(line: &str) -> Option<QueueCommand> {
+    match line {
+        "pause" => Some(QueueCommand::Pause),
+        "resume" => Some(QueueCommand::Resume),
+        "gc" => Some(QueueCommand::CollectGarbage),
+        _ => {
+            if let Some(id) = line.strip_prefix("cancel ") {
+                let id = id.trim();
+
+                return (!id.is_empty()).then(|| QueueCommand::Cancel(id.to_owned()));
+            }
+
+            let id = line.strip_prefix("requeue ")?;
+
+            match id.trim().parse() {
+                Ok(id) => Some(QueueCommand::Requeue(id)),
+                Err(_) => {
+                    warn!("Invalid failed file ID: {}", id);
+
+                    None
+                }
+            }
+        }
+    }
+}
+
+async fn update_permissions(permissions: &Mutex<chat_config::PermissionsConfig>) {
+    let new_permissions = match chat_config::load_config().await {
+        Ok(new_permissions) => new_permissions,
+        Err(e) => {
+            warn!("Failed to load new permissions config, using old one. Error: {:?}", e);
+
+            return;
+        }
+    };
+
+    let mut permissions = permissions.lock().await;
+
+    *permissions = new_permissions;
+
+    info!("Permissions updated successfully");
+}
+
+/// Reloads the environment variables and the permissions, sent on SIGHUP and with `f2l-cli reload-config`
+/// Settings read at startup keep their value, the changed ones are logged, see `SharedConfig::reload`
+async fn reload_config(config: &SharedConfig, permissions: &Mutex<chat_config::PermissionsConfig>) {
+    let restart_required = config.reload();
+
+    info!("Configuration reloaded");
+
+    if !restart_required.is_empty() {
+        warn!("Restart to apply the changes of {}", restart_required.join(", "));
+    }
+
+    update_permissions(permissions).await;
+}
+
+/// Reads commands sent by the CLI from the FIFO
+///
+/// # Arguments
+/// * `queue_commands` - Receives the commands handled by the queue
+pub async fn handle_cli(config: SharedConfig, permissions: Arc<Mutex<chat_config::PermissionsConfig>>, queue_commands: Sender<QueueCommand>) {
+    let path = config.get().pipe_path();
 
     match create_fifo(&path).await {
         Ok(_) => info!("FIFO created at {}", path),
@@ -40,20 +116,13 @@ pub async fn handle_cli(permissions: Arc<Mutex<chat_config::PermissionsConfig>>)
             }
         } {
             if line.trim() == "update_permissions" {
-                let new_permissions = match chat_config::load_config().await {
-                    Ok(new_permissions) => new_permissions,
-                    Err(e) => {
-                        warn!("Failed to load new permissions config, using old one. Error: {:?}", e);
-
-                        continue;
-                    }
-                };
-
-                let mut permissions = permissions.lock().await;
-
-                *permissions = new_permissions;
-
-                info!("Permissions updated successfully");
+                update_permissions(&permissions).await;
+            } else if line.trim() == "reload_config" {
+                reload_config(&config, &permissions).await;
+            } else if let Some(command) = parse_queue_command(line.trim()) {
+                if let Err(e) = queue_commands.send(command).await {
+                    warn!("Failed to handle {:?}, the queue is not running", e.0);
+                }
             } else if line.trim() == "shutdown" {
                 info!("Shutting down command handled");
 
@@ -61,4 +130,20 @@ pub async fn handle_cli(permissions: Arc<Mutex<chat_config::PermissionsConfig>>)
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_queue_command() {
+        assert_eq!(parse_queue_command("pause"), Some(QueueCommand::Pause));
+        assert_eq!(parse_queue_command("resume"), Some(QueueCommand::Resume));
+        assert_eq!(parse_queue_command("gc"), Some(QueueCommand::CollectGarbage));
+        assert_eq!(parse_queue_command("requeue 12"), Some(QueueCommand::Requeue(12)));
+        assert_eq!(parse_queue_command("requeue twelve"), None);
+        assert_eq!(parse_queue_command("cancel k3x9q0ab"), Some(QueueCommand::Cancel("k3x9q0ab".to_owned())));
+        assert_eq!(parse_queue_command("cancel  "), None);
+        assert_eq!(parse_queue_command("shutdown"), None);
+    }
+}
@@ -0,0 +1,250 @@
+use std::io::{self, Cursor, SeekFrom};
+use std::path::Path;
+
+use async_compression::tokio::bufread::{ZstdDecoder, ZstdEncoder};
+use async_compression::Level;
+use futures::future::BoxFuture;
+use futures::{stream, FutureExt, StreamExt};
+use infer::MatcherType;
+use log::{debug, info};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncBufRead, AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio_util::bytes::Bytes;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::storage::{ByteStream, Storage, StorageBackend, StoredObject};
+
+/// Starts every compressed file, followed by the size of the original file as a big endian u64
+const MAGIC: &[u8; 8] = b"F2LZST01";
+
+const HEADER_LEN: usize = MAGIC.len() + 8;
+
+/// Beginning of a file which is looked at to tell whether it is worth compressing
+const SNIFF_LEN: usize = 8192;
+
+/// Smaller files are stored as they are, the header would take most of the saved space
+const MIN_SIZE: u64 = 1024;
+
+/// Types with a magic number which are not compressed already, other detected types like images, videos and archives are
+/// stored as they are
+const COMPRESSIBLE_TYPES: [&str; 7] = [
+    "application/x-tar",
+    "application/vnd.sqlite3",
+    "application/wasm",
+    "application/x-executable",
+    "application/vnd.microsoft.portable-executable",
+    "image/bmp",
+    "audio/x-wav",
+];
+
+/// Compresses compressible files of another storage with zstd, see `COMPRESSION`
+///
+/// A compressed file is a header of `MAGIC` and the original size, followed by one zstd frame. Files without the header
+/// are served as they are, like files stored before the compression was enabled. Only files handed over with
+/// `put_file` are compressed, the size of a stream is not known before it ends.
+pub struct CompressedStorage {
+    inner: Storage,
+    level: i32,
+}
+
+type Reader = Box<dyn AsyncBufRead + Send + Unpin>;
+
+impl CompressedStorage {
+    pub fn new(inner: Storage, level: i32) -> Self {
+        info!("Compressible files are stored compressed with zstd level {}", level);
+
+        Self { inner, level }
+    }
+
+    /// # Returns
+    /// * `Result` containing the original size if the file is compressed and the file after the header, or the whole
+    ///   file if it is not compressed
+    async fn open(&self, name: &str) -> io::Result<(Option<u64>, Reader)> {
+        let mut data = StreamReader::new(self.inner.get(name).await?);
+        let mut header = Vec::with_capacity(HEADER_LEN);
+
+        (&mut data).take(HEADER_LEN as u64).read_to_end(&mut header).await?;
+
+        match header.strip_prefix(MAGIC) {
+            Some(size) if header.len() == HEADER_LEN => {
+                let size = u64::from_be_bytes(size.try_into().expect("the header has the size of a u64"));
+
+                Ok((Some(size), Box::new(data)))
+            }
+            _ => Ok((None, Box::new(Cursor::new(header).chain(data)))),
+        }
+    }
+}
+
+impl StorageBackend for CompressedStorage {
+    fn put<'a>(&'a self, name: &'a str, data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        self.inner.put(name, data)
+    }
+
+    fn put_file<'a>(&'a self, name: &'a str, path: &'a Path) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            let mut file = File::open(path).await?;
+            let size = file.metadata().await?.len();
+
+            let mut start = Vec::with_capacity(SNIFF_LEN);
+
+            (&mut file).take(SNIFF_LEN as u64).read_to_end(&mut start).await?;
+
+            if size < MIN_SIZE || !is_compressible(&start) {
+                return self.inner.put_file(name, path).await;
+            }
+
+            file.seek(SeekFrom::Start(0)).await?;
+
+            let header = [MAGIC.as_slice(), &size.to_be_bytes()].concat();
+            let encoder = ZstdEncoder::with_quality(BufReader::new(file), Level::Precise(self.level));
+
+            let data = stream::once(async { Ok(Bytes::from(header)) })
+                .chain(ReaderStream::new(encoder))
+                .boxed();
+
+            let stored = self.inner.put(name, data).await?;
+
+            debug!("Compressed {} from {} to {} bytes", name, size, stored);
+
+            fs::remove_file(path).await?;
+
+            Ok(size)
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<ByteStream>> {
+        async move {
+            let data = match self.open(name).await? {
+                (Some(_), data) => ReaderStream::new(ZstdDecoder::new(data)).boxed(),
+                (None, data) => ReaderStream::new(data).boxed(),
+            };
+
+            Ok(data)
+        }.boxed()
+    }
+
+    fn get_compressed<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<ByteStream>>> {
+        async move {
+            let data = match self.open(name).await? {
+                (Some(_), data) => Some(ReaderStream::new(data).boxed()),
+                (None, _) => None,
+            };
+
+            Ok(data)
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.inner.delete(name)
+    }
+
+    fn restore<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.inner.restore(name)
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        self.inner.list()
+    }
+
+    fn stat<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<StoredObject>>> {
+        async move {
+            let stored = match self.inner.stat(name).await? {
+                Some(stored) => stored,
+                None => return Ok(None),
+            };
+
+            // the size is served as the Content-Length, it has to be the size of the original file
+            let size = match self.open(name).await? {
+                (Some(size), _) => size,
+                (None, _) => stored.size,
+            };
+
+            Ok(Some(StoredObject { size, ..stored }))
+        }.boxed()
+    }
+
+    // a link to the storage would serve the compressed file without a Content-Encoding
+    fn redirect_url(&self, _name: &str, _content_disposition: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Tells by the beginning of a file whether compressing it saves space, text like logs, JSON and source code has no
+/// magic number and is compressed
+fn is_compressible(start: &[u8]) -> bool {
+    match infer::get(start) {
+        Some(kind) => kind.matcher_type() == MatcherType::Text || COMPRESSIBLE_TYPES.contains(&kind.mime_type()),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::TryStreamExt;
+
+    use crate::storage::LocalStorage;
+
+    use super::*;
+
+    async fn read_all(data: ByteStream) -> Vec<u8> {
+        data.map_ok(|chunk| chunk.to_vec()).try_concat().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_is_compressible() {
+        assert!(is_compressible(b"2024-06-04 12:00:00 INFO Starting up..."));
+        assert!(is_compressible(b"<?xml version=\"1.0\"?><a></a>"));
+        assert!(is_compressible(&[0x42, 0x4D, 0, 0, 0, 0, 0, 0]));
+        assert!(!is_compressible(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]));
+        assert!(!is_compressible(&[0x1F, 0x8B, 0x08, 0, 0, 0, 0, 0]));
+        assert!(!is_compressible(b"PK\x03\x04\x14\x00\x00\x00"));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_storage() {
+        let root = std::env::temp_dir().join("file2link_test_compressed_storage");
+        let _ = fs::remove_dir_all(&root).await;
+
+        let local: Storage = Arc::new(LocalStorage::new(&root));
+        let storage = CompressedStorage::new(local.clone(), 3);
+
+        fs::create_dir_all(&root).await.unwrap();
+
+        let text = "2024-06-04 12:00:00 INFO Starting up...\n".repeat(1000).into_bytes();
+        let png = [[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A].as_slice(), &[7; 2000]].concat();
+
+        for (name, content) in [("abcde_log.txt", &text), ("fghij_image.png", &png), ("klmno_short.txt", &b"short".to_vec())] {
+            let path = root.join(format!("{}.download", name));
+            fs::write(&path, content).await.unwrap();
+
+            assert_eq!(storage.put_file(name, &path).await.unwrap(), content.len() as u64);
+            assert_eq!(read_all(storage.get(name).await.unwrap()).await, *content);
+            assert_eq!(storage.stat(name).await.unwrap().map(|stored| stored.size), Some(content.len() as u64));
+        }
+
+        let stored = read_all(local.get("abcde_log.txt").await.unwrap()).await;
+
+        assert!(stored.starts_with(MAGIC));
+        assert!(stored.len() < text.len() / 10);
+
+        let compressed = storage.get_compressed("abcde_log.txt").await.unwrap().unwrap();
+        let decoded = ZstdDecoder::new(StreamReader::new(compressed));
+
+        assert_eq!(read_all(ReaderStream::new(decoded).boxed()).await, text);
+
+        assert_eq!(read_all(local.get("fghij_image.png").await.unwrap()).await, png);
+        assert!(storage.get_compressed("fghij_image.png").await.unwrap().is_none());
+        assert_eq!(read_all(local.get("klmno_short.txt").await.unwrap()).await, b"short");
+
+        // streams are stored as they are
+        storage.put("pqrst_stream.txt", stream::iter([Ok(Bytes::from(text.clone()))]).boxed()).await.unwrap();
+
+        assert_eq!(read_all(local.get("pqrst_stream.txt").await.unwrap()).await, text);
+        assert_eq!(read_all(storage.get("pqrst_stream.txt").await.unwrap()).await, text);
+
+        let _ = fs::remove_dir_all(&root).await;
+    }
+}
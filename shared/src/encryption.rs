@@ -0,0 +1,405 @@
+use std::io::{self, ErrorKind};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, OsRng};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use futures::future::BoxFuture;
+use futures::{stream, FutureExt, StreamExt, TryStreamExt};
+use log::info;
+use tokio_util::bytes::{Buf, Bytes, BytesMut};
+
+use crate::storage::{ByteStream, Storage, StorageBackend, StoredObject};
+
+/// Starts every encrypted file, files without it were stored before the encryption was enabled
+const MAGIC: &[u8; 8] = b"F2LENC01";
+
+/// Random part of the nonces of a file, the rest is the chunk counter and the last chunk flag
+const NONCE_PREFIX_LEN: usize = 7;
+
+const HEADER_LEN: usize = MAGIC.len() + NONCE_PREFIX_LEN;
+
+/// Files are encrypted in chunks of this size, so they are streamed and never held in memory as a whole
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const TAG_LEN: usize = 16;
+
+const SEALED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_LEN;
+
+/// Encrypts the files of another storage with AES-256-GCM, see `ENCRYPTION_KEY`
+///
+/// A file is a header of `MAGIC` and a random nonce prefix, followed by the chunks. The nonce of a chunk is the prefix,
+/// the chunk number and a flag for the last chunk, so chunks can not be reordered and a cut off file fails to decrypt.
+/// The last chunk is shorter than `CHUNK_SIZE` and may be empty.
+/// Files are handed over with the default `put_file`, they have to be read to encrypt them anyway.
+pub struct EncryptedStorage {
+    inner: Storage,
+    cipher: Aes256Gcm,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Storage, key: &[u8; 32]) -> Self {
+        info!("Stored files are encrypted");
+
+        Self { inner, cipher: Aes256Gcm::new(key.into()) }
+    }
+
+    /// Files stored before the encryption was enabled are served as they are
+    async fn is_encrypted(&self, name: &str) -> io::Result<bool> {
+        let mut data = self.inner.get(name).await?;
+        let mut start = BytesMut::new();
+
+        while start.len() < MAGIC.len() {
+            match data.try_next().await? {
+                Some(chunk) => start.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+
+        Ok(start.starts_with(MAGIC))
+    }
+}
+
+impl StorageBackend for EncryptedStorage {
+    fn put<'a>(&'a self, name: &'a str, data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            let size = self.inner.put(name, encrypt_stream(self.cipher.clone(), data)).await?;
+
+            Ok(plain_size(size))
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<ByteStream>> {
+        async move {
+            let data = self.inner.get(name).await?;
+
+            Ok(decrypt_stream(self.cipher.clone(), data))
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.inner.delete(name)
+    }
+
+    fn restore<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.inner.restore(name)
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        self.inner.list()
+    }
+
+    fn stat<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<StoredObject>>> {
+        async move {
+            let stored = match self.inner.stat(name).await? {
+                Some(stored) => stored,
+                None => return Ok(None),
+            };
+
+            // the size is served as the Content-Length, it has to be the size of the decrypted file
+            let size = match self.is_encrypted(name).await? {
+                true => plain_size(stored.size),
+                false => stored.size,
+            };
+
+            Ok(Some(StoredObject { size, ..stored }))
+        }.boxed()
+    }
+
+    // a link to the storage would serve the encrypted file
+    fn redirect_url(&self, _name: &str, _content_disposition: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Get the size of the decrypted file from the size of the encrypted one
+fn plain_size(encrypted_size: u64) -> u64 {
+    let sealed = encrypted_size.saturating_sub(HEADER_LEN as u64);
+    let chunks = sealed / SEALED_CHUNK_SIZE as u64 + 1;
+
+    sealed.saturating_sub(chunks * TAG_LEN as u64)
+}
+
+fn nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0; 12];
+
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+
+    nonce
+}
+
+fn encrypt_stream(cipher: Aes256Gcm, data: ByteStream) -> ByteStream {
+    stream::try_unfold((data, Encryptor::new(cipher)), |(mut data, mut encryptor)| async move {
+        loop {
+            if encryptor.finished {
+                return Ok(None);
+            }
+
+            let sealed = match data.try_next().await? {
+                Some(chunk) => encryptor.update(&chunk)?,
+                None => encryptor.finish()?,
+            };
+
+            if !sealed.is_empty() {
+                return Ok(Some((Bytes::from(sealed), (data, encryptor))));
+            }
+        }
+    }).boxed()
+}
+
+fn decrypt_stream(cipher: Aes256Gcm, data: ByteStream) -> ByteStream {
+    stream::try_unfold((data, Decryptor::new(cipher)), |(mut data, mut decryptor)| async move {
+        loop {
+            if decryptor.finished {
+                return Ok(None);
+            }
+
+            let plain = match data.try_next().await? {
+                Some(chunk) => decryptor.update(&chunk, false)?,
+                None => decryptor.update(&[], true)?,
+            };
+
+            if !plain.is_empty() {
+                return Ok(Some((Bytes::from(plain), (data, decryptor))));
+            }
+        }
+    }).boxed()
+}
+
+struct Encryptor {
+    cipher: Aes256Gcm,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    buffer: BytesMut,
+    counter: u32,
+    header_written: bool,
+    finished: bool,
+}
+
+impl Encryptor {
+    fn new(cipher: Aes256Gcm) -> Self {
+        let mut prefix = [0; NONCE_PREFIX_LEN];
+
+        OsRng.fill_bytes(&mut prefix);
+
+        Self { cipher, prefix, buffer: BytesMut::new(), counter: 0, header_written: false, finished: false }
+    }
+
+    /// # Returns
+    /// * `Result` containing the encrypted chunks which are full, may be empty
+    fn update(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut sealed = self.header();
+
+        while self.buffer.len() >= CHUNK_SIZE {
+            let chunk = self.buffer.split_to(CHUNK_SIZE);
+
+            sealed.extend(self.seal(&chunk, false)?);
+        }
+
+        Ok(sealed)
+    }
+
+    /// # Returns
+    /// * `Result` containing the rest of the file as the last chunk
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let mut sealed = self.header();
+        let chunk = self.buffer.split();
+
+        sealed.extend(self.seal(&chunk, true)?);
+        self.finished = true;
+
+        Ok(sealed)
+    }
+
+    fn header(&mut self) -> Vec<u8> {
+        if self.header_written {
+            return Vec::new();
+        }
+
+        self.header_written = true;
+
+        [MAGIC.as_slice(), self.prefix.as_slice()].concat()
+    }
+
+    fn seal(&mut self, chunk: &[u8], last: bool) -> io::Result<Vec<u8>> {
+        let nonce = nonce(&self.prefix, self.counter, last);
+
+        self.counter = self.counter.checked_add(1)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "The file is too big to encrypt"))?;
+
+        self.cipher.encrypt(Nonce::from_slice(&nonce), chunk)
+            .map_err(|_| io::Error::other("Failed to encrypt the file"))
+    }
+}
+
+/// Whether the data read so far is an encrypted file
+enum Content {
+    Unknown,
+    Encrypted([u8; NONCE_PREFIX_LEN]),
+    Plain,
+}
+
+struct Decryptor {
+    cipher: Aes256Gcm,
+    content: Content,
+    buffer: BytesMut,
+    counter: u32,
+    finished: bool,
+}
+
+impl Decryptor {
+    fn new(cipher: Aes256Gcm) -> Self {
+        Self { cipher, content: Content::Unknown, buffer: BytesMut::new(), counter: 0, finished: false }
+    }
+
+    /// # Arguments
+    /// * `end` - The data has ended, the rest of the buffer is the last chunk
+    ///
+    /// # Returns
+    /// * `Result` containing the decrypted chunks which are complete, may be empty
+    fn update(&mut self, data: &[u8], end: bool) -> io::Result<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        self.finished = end;
+
+        if let Content::Unknown = self.content {
+            let checked = self.buffer.len().min(MAGIC.len());
+
+            if self.buffer[..checked] != MAGIC[..checked] || (end && self.buffer.len() < HEADER_LEN) {
+                self.content = Content::Plain;
+            } else if self.buffer.len() >= HEADER_LEN {
+                let mut prefix = [0; NONCE_PREFIX_LEN];
+
+                prefix.copy_from_slice(&self.buffer[MAGIC.len()..HEADER_LEN]);
+                self.buffer.advance(HEADER_LEN);
+                self.content = Content::Encrypted(prefix);
+            } else {
+                return Ok(Vec::new());
+            }
+        }
+
+        let prefix = match self.content {
+            Content::Encrypted(prefix) => prefix,
+            _ => return Ok(self.buffer.split().to_vec()),
+        };
+
+        let mut plain = Vec::new();
+
+        // a full chunk is only known not to be the last one when more data follows it
+        while self.buffer.len() > SEALED_CHUNK_SIZE {
+            let chunk = self.buffer.split_to(SEALED_CHUNK_SIZE);
+
+            plain.extend(self.open(&prefix, &chunk, false)?);
+        }
+
+        if end {
+            let chunk = self.buffer.split();
+
+            plain.extend(self.open(&prefix, &chunk, true)?);
+        }
+
+        Ok(plain)
+    }
+
+    fn open(&mut self, prefix: &[u8; NONCE_PREFIX_LEN], chunk: &[u8], last: bool) -> io::Result<Vec<u8>> {
+        let nonce = nonce(prefix, self.counter, last);
+
+        self.counter = self.counter.wrapping_add(1);
+
+        self.cipher.decrypt(Nonce::from_slice(&nonce), chunk)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "The file is damaged or was encrypted with another key"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::storage::LocalStorage;
+
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn chunks(data: &[u8], size: usize) -> ByteStream {
+        let chunks: Vec<io::Result<Bytes>> = data.chunks(size.max(1)).map(|chunk| Ok(Bytes::copy_from_slice(chunk))).collect();
+
+        stream::iter(chunks).boxed()
+    }
+
+    async fn collect(data: ByteStream) -> io::Result<Vec<u8>> {
+        data.try_fold(Vec::new(), |mut all, chunk| async move {
+            all.extend_from_slice(&chunk);
+
+            Ok(all)
+        }).await
+    }
+
+    fn cipher(key: &[u8; 32]) -> Aes256Gcm {
+        Aes256Gcm::new(key.into())
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        for size in [0, 1, 100, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE + 5] {
+            let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+
+            let encrypted = collect(encrypt_stream(cipher(&KEY), chunks(&data, 1000))).await.unwrap();
+
+            assert!(encrypted.starts_with(MAGIC));
+            assert_eq!(plain_size(encrypted.len() as u64), size as u64, "{}", size);
+
+            let decrypted = collect(decrypt_stream(cipher(&KEY), chunks(&encrypted, 777))).await.unwrap();
+
+            assert_eq!(decrypted, data, "{}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_damaged_files_fail() {
+        let data = vec![1; 2 * CHUNK_SIZE + 10];
+        let encrypted = collect(encrypt_stream(cipher(&KEY), chunks(&data, CHUNK_SIZE))).await.unwrap();
+
+        // cut after the first chunk
+        let cut = &encrypted[..HEADER_LEN + SEALED_CHUNK_SIZE];
+        assert!(collect(decrypt_stream(cipher(&KEY), chunks(cut, 1000))).await.is_err());
+
+        let mut changed = encrypted.clone();
+        changed[HEADER_LEN + 5] ^= 1;
+        assert!(collect(decrypt_stream(cipher(&KEY), chunks(&changed, 1000))).await.is_err());
+
+        assert!(collect(decrypt_stream(cipher(&[8; 32]), chunks(&encrypted, 1000))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plain_files_are_served_as_they_are() {
+        for data in [b"".as_slice(), b"F2L", b"hello world"] {
+            assert_eq!(collect(decrypt_stream(cipher(&KEY), chunks(data, 2))).await.unwrap(), data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_storage() {
+        let root = std::env::temp_dir().join("file2link_test_encrypted_storage");
+        let _ = std::fs::remove_dir_all(&root);
+
+        let local: Storage = Arc::new(LocalStorage::new(&root));
+        let storage = EncryptedStorage::new(local.clone(), &KEY);
+
+        assert_eq!(storage.put("abcde_a.txt", chunks(b"secret content", 4)).await.unwrap(), 14);
+        assert_eq!(storage.stat("abcde_a.txt").await.unwrap().map(|stored| stored.size), Some(14));
+        assert_eq!(collect(storage.get("abcde_a.txt").await.unwrap()).await.unwrap(), b"secret content");
+
+        let on_disk = std::fs::read(root.join("abcde_a.txt")).unwrap();
+        assert!(!on_disk.windows(6).any(|window| window == b"secret"));
+
+        // stored before the encryption was enabled
+        local.put("fghij_b.txt", chunks(b"plain", 5)).await.unwrap();
+
+        assert_eq!(storage.stat("fghij_b.txt").await.unwrap().map(|stored| stored.size), Some(5));
+        assert_eq!(collect(storage.get("fghij_b.txt").await.unwrap()).await.unwrap(), b"plain");
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
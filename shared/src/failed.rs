@@ -0,0 +1,112 @@
+use std::error::Error;
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::utils::format_duration;
+
+const FAILED_PATH: &str = "config/failed.json";
+
+/// A queued file which failed after all its attempts, kept so it can be queued again
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailedFile {
+    pub id: u64,
+    pub chat_id: i64,
+    /// File name or URL of the file
+    pub source: String,
+    pub error: String,
+    /// Unix time in seconds the file failed at
+    pub failed_at: u64,
+    /// Queue entry of the file, only read by the bot
+    pub record: serde_json::Value,
+}
+
+impl FailedFile {
+    /// Get a line describing the file like "#3 file.zip in chat 123, 2h 5m ago: Source unreachable"
+    pub fn summary(&self, now: u64) -> String {
+        format!(
+            "#{} {} in chat {}, {} ago: {}",
+            self.id,
+            self.source,
+            self.chat_id,
+            format_duration(now.saturating_sub(self.failed_at)),
+            self.error,
+        )
+    }
+}
+
+/// The files which failed for good and the highest ID given to one, so the ID of a file which is gone is not given
+/// to another file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct FailedFiles {
+    pub last_id: u64,
+    pub files: Vec<FailedFile>,
+}
+
+/// Failed files as saved before the last ID was kept, a list of the files
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedFailedFiles {
+    Files(FailedFiles),
+    List(Vec<FailedFile>),
+}
+
+pub async fn load_failed() -> Result<FailedFiles, Box<dyn Error>> {
+    let data = match fs::read_to_string(FAILED_PATH).await {
+        Ok(data) => data,
+        Err(_) => {
+            debug!("Failed files '{}' not found, starting with no failed files", FAILED_PATH);
+
+            return Ok(FailedFiles::default());
+        }
+    };
+
+    match serde_json::from_str(&data) {
+        Ok(SavedFailedFiles::Files(files)) => Ok(files),
+        Ok(SavedFailedFiles::List(files)) => Ok(FailedFiles {
+            last_id: files.iter().map(|file| file.id).max().unwrap_or(0),
+            files,
+        }),
+        Err(e) => {
+            error!("Failed to parse failed files: {}", e);
+
+            Err("Failed to parse failed files".into())
+        }
+    }
+}
+
+pub async fn save_failed(files: &FailedFiles) -> Result<(), Box<dyn Error>> {
+    if let Some((dir_path, _)) = FAILED_PATH.rsplit_once('/') {
+        fs::create_dir_all(dir_path).await?;
+    }
+
+    let data = serde_json::to_string_pretty(files)?;
+    fs::write(FAILED_PATH, data).await?;
+
+    debug!("Failed files saved to '{}'", FAILED_PATH);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_summary() {
+        let file = FailedFile {
+            id: 3,
+            chat_id: -100123,
+            source: "https://example.com/file.zip".to_owned(),
+            error: "The server is unreachable".to_owned(),
+            failed_at: 1000,
+            record: serde_json::Value::Null,
+        };
+
+        assert_eq!(
+            file.summary(1000 + 2 * 60 * 60 + 5 * 60),
+            "#3 https://example.com/file.zip in chat -100123, 2h 5m ago: The server is unreachable"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+
+use log::{info, warn};
+use tokio::sync::Mutex;
+
+use crate::metadata::MetadataStore;
+use crate::storage::Storage;
+use crate::utils::format_size;
+
+/// Files created or changed more recently are left alone, they may be in the middle of being stored
+const GRACE_PERIOD: u64 = 60 * 60;
+
+/// What a check of the storage against the metadata found, see `collect_garbage`
+#[derive(Debug, Default, PartialEq)]
+pub struct GcReport {
+    /// Number of files with metadata which were checked
+    pub checked: usize,
+    /// Files whose content is gone from the storage, their metadata was removed
+    pub missing: Vec<String>,
+    /// Files in the storage without metadata
+    pub orphans: Vec<String>,
+    pub orphaned_bytes: u64,
+    /// Number of orphans removed, see `GC_REMOVE_ORPHANS`
+    pub removed_orphans: usize,
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checked {} files: removed the metadata of {} missing files, found {} files without metadata ({}), removed {} of them",
+            self.checked,
+            self.missing.len(),
+            self.orphans.len(),
+            format_size(self.orphaned_bytes),
+            self.removed_orphans,
+        )
+    }
+}
+
+/// Reconciles the storage with the metadata: the metadata of files whose content is gone is removed, files without
+/// metadata are reported and removed with `remove_orphans`
+/// Only files outside of folders are looked for orphans, see `StorageBackend::list`
+///
+/// The metadata is not locked while the storage is checked. The caller saves the metadata if files were missing.
+///
+/// # Arguments
+/// * `now` - Unix time in seconds, files newer than `GRACE_PERIOD` are skipped
+///
+/// # Returns
+/// * `Err` if the storage could not be checked, nothing is removed then
+pub async fn collect_garbage(metadata: &Mutex<MetadataStore>, storage: &Storage, remove_orphans: bool, now: u64) -> io::Result<GcReport> {
+    let (files, versions): (Vec<(String, String, u64)>, Vec<String>) = {
+        let metadata = metadata.lock().await;
+
+        let files = metadata.files()
+            .map(|file| (file.file_name.clone(), file.storage_name(), file.created_at))
+            .collect();
+        let versions = metadata.files()
+            .flat_map(|file| file.versions.iter().map(|version| version.storage_name.clone()))
+            .collect();
+
+        (files, versions)
+    };
+
+    let mut report = GcReport { checked: files.len(), ..GcReport::default() };
+
+    // content addressed files share their content, it is looked up once
+    let mut is_stored: HashMap<&str, bool> = HashMap::new();
+
+    for (file_name, storage_name, created_at) in &files {
+        if created_at.saturating_add(GRACE_PERIOD) > now {
+            continue;
+        }
+
+        let stored = match is_stored.get(storage_name.as_str()) {
+            Some(stored) => *stored,
+            None => {
+                let stored = storage.stat(storage_name).await?.is_some();
+
+                is_stored.insert(storage_name, stored);
+
+                stored
+            }
+        };
+
+        if !stored {
+            report.missing.push(file_name.clone());
+        }
+    }
+
+    // versions which kept the content of the earlier file where it was are not orphans
+    let referenced: HashSet<&str> = files.iter().map(|(_, storage_name, _)| storage_name.as_str())
+        .chain(versions.iter().map(String::as_str))
+        .collect();
+
+    for name in storage.list().await? {
+        if referenced.contains(name.as_str()) {
+            continue;
+        }
+
+        let stored = match storage.stat(&name).await? {
+            Some(stored) => stored,
+            None => continue,
+        };
+
+        if stored.modified_at.is_some_and(|modified_at| modified_at.saturating_add(GRACE_PERIOD) > now) {
+            continue;
+        }
+
+        warn!("File '{}' ({}) has no metadata", name, format_size(stored.size));
+
+        report.orphaned_bytes += stored.size;
+        report.orphans.push(name);
+    }
+
+    let mut metadata = metadata.lock().await;
+
+    for file_name in &report.missing {
+        if metadata.remove(file_name).is_some() {
+            info!("Removed the metadata of file '{}', its content is gone", file_name);
+        }
+    }
+
+    if remove_orphans {
+        for name in &report.orphans {
+            // a file may have been stored under the name since it was listed
+            if metadata.is_stored(name) {
+                continue;
+            }
+
+            match storage.delete(name).await {
+                Ok(_) => {
+                    info!("Removed file '{}' without metadata", name);
+
+                    report.removed_orphans += 1;
+                }
+                Err(e) => warn!("Failed to remove file '{}' without metadata: {}", name, e),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::fs;
+
+    use crate::metadata::FileMetadata;
+    use crate::storage::LocalStorage;
+    use crate::usage::now_seconds;
+
+    use super::*;
+
+    fn file(name: &str, created_at: u64) -> FileMetadata {
+        FileMetadata {
+            file_name: name.to_owned(),
+            size: 5,
+            created_at,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collect_garbage() {
+        let root = std::env::temp_dir().join("file2link_test_gc");
+        let _ = fs::remove_dir_all(&root).await;
+
+        fs::create_dir_all(&root).await.unwrap();
+
+        let storage: Storage = Arc::new(LocalStorage::new(&root));
+
+        for name in ["abcde_kept.txt", "fghij_orphan.txt", ".hidden"] {
+            fs::write(root.join(name), b"hello").await.unwrap();
+        }
+
+        // the files were just written, they are past the grace period an hour later
+        let now = now_seconds() + GRACE_PERIOD;
+
+        let metadata = Mutex::new(MetadataStore::default());
+
+        {
+            let mut metadata = metadata.lock().await;
+
+            metadata.insert(file("abcde_kept.txt", 0));
+            metadata.insert(file("klmno_missing.txt", 0));
+            metadata.insert(file("pqrst_storing.txt", now));
+        }
+
+        let report = collect_garbage(&metadata, &storage, false, now).await.unwrap();
+
+        assert_eq!(report, GcReport {
+            checked: 3,
+            missing: vec!["klmno_missing.txt".to_owned()],
+            orphans: vec!["fghij_orphan.txt".to_owned()],
+            orphaned_bytes: 5,
+            removed_orphans: 0,
+        });
+        assert!(metadata.lock().await.get("klmno_missing.txt").is_none());
+        assert!(metadata.lock().await.get("pqrst_storing.txt").is_some());
+        assert!(root.join("fghij_orphan.txt").exists());
+
+        let report = collect_garbage(&metadata, &storage, true, now).await.unwrap();
+
+        assert!(report.missing.is_empty());
+        assert_eq!(report.removed_orphans, 1);
+        assert!(!root.join("fghij_orphan.txt").exists());
+        assert!(root.join("abcde_kept.txt").exists());
+
+        // the orphan was written just now
+        fs::write(root.join("uvwxy_new.txt"), b"hello").await.unwrap();
+
+        let report = collect_garbage(&metadata, &storage, true, now_seconds()).await.unwrap();
+
+        assert!(report.orphans.is_empty());
+
+        let _ = fs::remove_dir_all(&root).await;
+    }
+}
@@ -1,4 +1,25 @@
 pub mod chat_config;
+pub mod chat_settings;
+pub mod failed;
 pub mod config;
+pub mod encryption;
+pub mod gc;
 pub mod utils;
 pub mod cli_utils;
+pub mod compression;
+pub mod listing;
+pub mod logging;
+pub mod metadata;
+pub mod metrics;
+pub mod migrate;
+pub mod naming;
+pub mod queue_snapshot;
+pub mod s3;
+pub mod secrets;
+pub mod stats;
+pub mod storage;
+pub mod templates;
+pub mod tiering;
+pub mod usage;
+pub mod versions;
+pub mod webdav;
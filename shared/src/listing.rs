@@ -0,0 +1,135 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Seconds a personal listing link stays valid
+pub const LISTING_LINK_LIFETIME: u64 = 24 * 60 * 60;
+
+/// Path of the personal listing page relative to the files route of the server
+pub const LISTING_PATH: &str = "my";
+
+fn mac(secret: &str, owner: &str, expires_at: u64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+
+    mac.update(format!("{}:{}", owner, expires_at).as_bytes());
+
+    mac
+}
+
+fn owner_mac(secret: &str, owner: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+
+    // listing signatures end with the expiry time, so a token is never a valid listing signature and the other way
+    mac.update(format!("{}:private", owner).as_bytes());
+
+    mac
+}
+
+/// Signs the listing of the owner until the given time
+///
+/// # Returns
+/// * `String` containing the hex encoded HMAC-SHA256 signature
+pub fn sign(secret: &str, owner: &str, expires_at: u64) -> String {
+    hex::encode(mac(secret, owner, expires_at).finalize().into_bytes())
+}
+
+/// Checks that the signature was made with the secret and the link has not expired
+pub fn verify(secret: &str, owner: &str, expires_at: u64, signature: &str, now: u64) -> bool {
+    if expires_at <= now {
+        return false;
+    }
+
+    match hex::decode(signature) {
+        Ok(signature) => mac(secret, owner, expires_at).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Get the token which opens the private files of the owner, the same for all of them
+///
+/// # Returns
+/// * `String` containing the hex encoded HMAC-SHA256 of the owner
+pub fn owner_token(secret: &str, owner: &str) -> String {
+    hex::encode(owner_mac(secret, owner).finalize().into_bytes())
+}
+
+/// Checks that the token belongs to one of the owners of a private file
+pub fn verify_owner_token(secret: &str, owners: &[String], token: &str) -> bool {
+    match hex::decode(token) {
+        Ok(token) => owners.iter().any(|owner| owner_mac(secret, owner).verify_slice(&token).is_ok()),
+        Err(_) => false,
+    }
+}
+
+/// Adds the token of the owner to a file link, which may already have a query like "?inline"
+pub fn with_token(link: &str, token: &str) -> String {
+    let separator = if link.contains('?') { '&' } else { '?' };
+
+    format!("{}{}token={}", link, separator, token)
+}
+
+/// Builds a signed link to the files of the owner
+///
+/// # Arguments
+/// * `domain` - The configured `APP_DOMAIN` ending with a slash, the page is served by the server and not by a CDN
+pub fn listing_link(domain: &str, secret: &str, owner: &str, now: u64) -> String {
+    let expires_at = now + LISTING_LINK_LIFETIME;
+
+    format!(
+        "{}files/{}/{}?expires={}&signature={}",
+        domain,
+        LISTING_PATH,
+        owner,
+        expires_at,
+        sign(secret, owner, expires_at)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_verify() {
+        let signature = sign("secret", "42", 1000);
+
+        assert!(verify("secret", "42", 1000, &signature, 999));
+        assert!(!verify("secret", "42", 1000, &signature, 1000));
+        assert!(!verify("secret", "43", 1000, &signature, 999));
+        assert!(!verify("secret", "42", 2000, &signature, 999));
+        assert!(!verify("other", "42", 1000, &signature, 999));
+        assert!(!verify("secret", "42", 1000, "not hex", 999));
+    }
+
+    #[tokio::test]
+    async fn test_listing_link() {
+        let link = listing_link("https://example.com/", "secret", "42", 100);
+        let expires_at = 100 + LISTING_LINK_LIFETIME;
+
+        assert_eq!(
+            link,
+            format!("https://example.com/files/my/42?expires={}&signature={}", expires_at, sign("secret", "42", expires_at))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_owner_token() {
+        let token = owner_token("secret", "42");
+        let owners = vec!["1".to_owned(), "42".to_owned()];
+
+        assert!(verify_owner_token("secret", &owners, &token));
+        assert!(!verify_owner_token("secret", &owners[..1], &token));
+        assert!(!verify_owner_token("other", &owners, &token));
+        assert!(!verify_owner_token("secret", &owners, "not hex"));
+        assert!(!verify_owner_token("secret", &[], &token));
+        // a listing signature does not open private files
+        assert!(!verify_owner_token("secret", &owners, &sign("secret", "42", 0)));
+    }
+
+    #[tokio::test]
+    async fn test_with_token() {
+        assert_eq!(with_token("https://example.com/files/a.txt", "ab12"), "https://example.com/files/a.txt?token=ab12");
+        assert_eq!(with_token("https://example.com/files/a.txt?inline", "ab12"), "https://example.com/files/a.txt?inline&token=ab12");
+    }
+}
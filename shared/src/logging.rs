@@ -0,0 +1,73 @@
+use std::env;
+use std::io::Write;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::Record;
+use pretty_env_logger::env_logger::Builder;
+use serde_json::json;
+
+use crate::config::{self, LogFormat, Variables};
+
+/// Sets up the logger with the levels of `RUST_LOG` and `LOG_LEVELS` in the format of `LOG_FORMAT`
+/// Must be called after `config::load_env`, invalid settings use their default and are reported by `Config::new`
+pub fn init() {
+    let variables = Variables::from_env();
+    let mut builder = match config::log_format(&variables).unwrap_or_default() {
+        LogFormat::Pretty => pretty_env_logger::formatted_builder(),
+        LogFormat::Json => json_builder(),
+    };
+
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+
+    // a module in both replaces the level of RUST_LOG
+    for (module, level) in config::log_levels(&variables).unwrap_or_default() {
+        builder.filter_module(&module, level);
+    }
+
+    builder.init();
+}
+
+fn json_builder() -> Builder {
+    let mut builder = Builder::new();
+
+    builder.format(|buf, record| writeln!(buf, "{}", json_line(record, Utc::now())));
+
+    builder
+}
+
+/// Get a log line like `{"time":"2024-06-04T12:00:00.000Z","level":"INFO","target":"bot::queue","message":"..."}`
+fn json_line(record: &Record, now: DateTime<Utc>) -> String {
+    json!({
+        "time": now.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_json_line() {
+        let now = DateTime::from_timestamp(1717502400, 0).unwrap();
+        let line = json_line(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("bot::queue")
+                .args(format_args!("Failed to download \"{}\"", "a.zip"))
+                .build(),
+            now,
+        );
+
+        assert_eq!(
+            line,
+            r#"{"level":"WARN","message":"Failed to download \"a.zip\"","target":"bot::queue","time":"2024-06-04T12:00:00.000Z"}"#
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::chat_config::Retention;
+use crate::storage::blob_name;
+
+const METADATA_PATH: &str = "config/metadata.json";
+
+/// Length of the random ID prepended to the names of files stored before the naming was configurable, see `id`
+pub const FILE_ID_LENGTH: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FileMetadata {
+    /// Name of the stored file, also used in the download link
+    pub file_name: String,
+    /// Random ID in the file name, see `FILE_NAME_PATTERN`. Unset for files named before the naming was configurable,
+    /// their names start with the ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub size: u64,
+    /// Hex encoded SHA-256 of the stored content
+    pub sha256: Option<String>,
+    /// Telegram `file_unique_id`, same for one file across bots and time
+    pub file_unique_id: Option<String>,
+    /// Unix time in seconds
+    pub created_at: u64,
+    /// Unix time in seconds after which the file is no longer served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Unix time in seconds of the last download of the file or one of its versions, unset for files not downloaded
+    /// since downloads were recorded. Files not downloaded for `COLD_STORAGE_AFTER` are moved to the cold storage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloaded_at: Option<u64>,
+    /// IDs of the users who uploaded the file, several when an upload was deduplicated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+    /// IDs of the chats the file was uploaded in, several when an upload was deduplicated. Empty for files stored before
+    /// chats were recorded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chats: Vec<String>,
+    /// Served only with the token of one of the owners, see `listing::owner_token`. Sent with `--private`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    /// The content is stored under its hash instead of the file name, see `CONTENT_ADDRESSED_STORAGE`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub content_addressed: bool,
+    /// Folder the file is stored in like "2024/06/04", see `STORAGE_LAYOUT`. Unset for files in the top folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// The virus scanner found the file clean, unset for files stored without scanning. Infected files are quarantined
+    /// and never stored
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub scanned: bool,
+    /// Copy of the file uploaded to `MIRROR_CHAT_ID`, unset when the file was not mirrored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<TelegramMirror>,
+    /// Files in the archive, shown on its preview page. Empty for files which are not archives
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub archive_entries: Vec<ArchiveEntry>,
+    /// Earlier files stored under the same name, oldest first, see `FILE_VERSIONS`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<FileVersion>,
+}
+
+/// A file replaced by a newer file of the same name, served at "/files/{file_name}/v/{version}"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileVersion {
+    /// Counts up from 1 with every replaced file
+    pub version: u32,
+    /// Name of the content in the storage, see `versions::keep_version`
+    pub storage_name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub created_at: u64,
+    /// The version is served to the owners of the replaced file, not of the newer one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+}
+
+/// A message with the file in the mirror chat, see `PostProcessorKind::Mirror`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TelegramMirror {
+    pub chat_id: i64,
+    pub message_id: i32,
+}
+
+/// A file in an uploaded zip or tar archive
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    /// Path in the archive like "docs/readme.txt"
+    pub path: String,
+    /// Uncompressed size
+    pub size: u64,
+    /// Name of the extracted file in its link, unset when the archive was not extracted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+}
+
+impl FileMetadata {
+    /// The random ID in the file name
+    pub fn id(&self) -> &str {
+        match &self.id {
+            Some(id) => id,
+            None => self.file_name.get(..FILE_ID_LENGTH).unwrap_or(&self.file_name),
+        }
+    }
+
+    /// Name of the content in the storage, files with the same content addressed content share it
+    pub fn storage_name(&self) -> String {
+        match (self.content_addressed, &self.sha256, &self.folder) {
+            (true, Some(sha256), _) => blob_name(sha256),
+            (_, _, Some(folder)) => format!("{}/{}", folder, self.file_name),
+            _ => self.file_name.clone(),
+        }
+    }
+
+    pub fn version(&self, version: u32) -> Option<&FileVersion> {
+        self.versions.iter().find(|file_version| file_version.version == version)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn add_owner(&mut self, owner: String) {
+        if !self.owners.contains(&owner) {
+            self.owners.push(owner);
+        }
+    }
+
+    pub fn add_chat(&mut self, chat: String) {
+        if !self.chats.contains(&chat) {
+            self.chats.push(chat);
+        }
+    }
+
+    /// Keeps the file at least until the given time, `None` keeps it forever
+    pub fn extend_expiry(&mut self, expires_at: Option<u64>) {
+        self.expires_at = match (self.expires_at, expires_at) {
+            (Some(current), Some(new)) => Some(current.max(new)),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MetadataStore {
+    files: HashMap<String, FileMetadata>,
+}
+
+impl MetadataStore {
+    pub fn get(&self, file_name: &str) -> Option<&FileMetadata> {
+        self.files.get(file_name)
+    }
+
+    pub fn get_mut(&mut self, file_name: &str) -> Option<&mut FileMetadata> {
+        self.files.get_mut(file_name)
+    }
+
+    pub fn insert(&mut self, metadata: FileMetadata) {
+        debug!("Storing metadata for '{}'", metadata.file_name);
+
+        self.files.insert(metadata.file_name.clone(), metadata);
+    }
+
+    pub fn remove(&mut self, file_name: &str) -> Option<FileMetadata> {
+        self.files.remove(file_name)
+    }
+
+    pub fn find_by_unique_id(&self, file_unique_id: &str) -> Option<&FileMetadata> {
+        self.files.values()
+            .find(|file| file.file_unique_id.as_deref() == Some(file_unique_id))
+    }
+
+    pub fn find_by_hash(&self, sha256: &str) -> Option<&FileMetadata> {
+        self.files.values()
+            .find(|file| file.sha256.as_deref() == Some(sha256))
+    }
+
+    pub fn find_by_id_mut(&mut self, id: &str) -> Option<&mut FileMetadata> {
+        self.files.values_mut()
+            .find(|file| file.id() == id)
+    }
+
+    /// Finds files of the owner whose name contains every word of the query, newest first
+    /// Expired files are skipped
+    pub fn search(&self, owner: &str, query: &str, now: u64) -> Vec<&FileMetadata> {
+        let words: Vec<String> = query.split_whitespace()
+            .map(|word| word.to_lowercase())
+            .collect();
+
+        let mut files: Vec<&FileMetadata> = self.files.values()
+            .filter(|file| file.owners.iter().any(|file_owner| file_owner == owner))
+            .filter(|file| !file.is_expired(now))
+            .filter(|file| {
+                let name = file.file_name.to_lowercase();
+
+                words.iter().all(|word| name.contains(word))
+            })
+            .collect();
+
+        files.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.file_name.cmp(&b.file_name)));
+
+        files
+    }
+
+    /// Names of the files which expired at the given time
+    /// Get the names of the files past their expiry or older than the retention of their chats and uploaders
+    pub fn expired(&self, now: u64, retention: &Retention) -> Vec<String> {
+        self.files.values()
+            .filter(|file| file.is_expired(now) || retention.is_expired(file, now))
+            .map(|file| file.file_name.clone())
+            .collect()
+    }
+
+    /// A file or a version of one is stored under the storage name, see `FileMetadata::storage_name`
+    pub fn is_stored(&self, storage_name: &str) -> bool {
+        self.files.values().any(|file| {
+            file.storage_name() == storage_name || file.versions.iter().any(|version| version.storage_name == storage_name)
+        })
+    }
+
+    /// Get the storage names of the files and versions not downloaded since the time, see `COLD_STORAGE_AFTER`
+    /// Files never downloaded count from their upload. Content shared with a file downloaded since is not listed.
+    pub fn idle_storage_names(&self, since: u64) -> Vec<String> {
+        let mut idle = BTreeMap::new();
+
+        for file in self.files.values() {
+            let is_idle = file.downloaded_at.unwrap_or(file.created_at) < since;
+            let names = std::iter::once(file.storage_name())
+                .chain(file.versions.iter().map(|version| version.storage_name.clone()));
+
+            for name in names {
+                *idle.entry(name).or_insert(true) &= is_idle;
+            }
+        }
+
+        idle.into_iter().filter(|(_, is_idle)| *is_idle).map(|(name, _)| name).collect()
+    }
+
+    pub fn files(&self) -> impl Iterator<Item=&FileMetadata> {
+        self.files.values()
+    }
+
+    /// Get the bytes of content in the storage, content shared by several files or versions is counted once
+    pub fn stored_bytes(&self) -> u64 {
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+
+        for file in self.files.values() {
+            sizes.insert(file.storage_name(), file.size);
+
+            for version in &file.versions {
+                sizes.insert(version.storage_name.clone(), version.size);
+            }
+        }
+
+        sizes.values().sum()
+    }
+}
+
+pub async fn load_metadata() -> Result<MetadataStore, Box<dyn Error>> {
+    let data = match fs::read_to_string(METADATA_PATH).await {
+        Ok(data) => data,
+        Err(_) => {
+            debug!("Metadata file '{}' not found, starting with empty metadata", METADATA_PATH);
+
+            return Ok(MetadataStore::default());
+        }
+    };
+
+    match serde_json::from_str(&data) {
+        Ok(metadata) => Ok(metadata),
+        Err(e) => {
+            error!("Failed to parse metadata: {}", e);
+
+            Err("Failed to parse metadata".into())
+        }
+    }
+}
+
+pub async fn save_metadata(metadata: &MetadataStore) -> Result<(), Box<dyn Error>> {
+    if let Some((dir_path, _)) = METADATA_PATH.rsplit_once('/') {
+        fs::create_dir_all(dir_path).await?;
+    }
+
+    let data = serde_json::to_string_pretty(metadata)?;
+    fs::write(METADATA_PATH, data).await?;
+
+    debug!("Metadata saved to '{}'", METADATA_PATH);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, sha256: Option<&str>, file_unique_id: Option<&str>) -> FileMetadata {
+        FileMetadata {
+            file_name: name.to_string(),
+            size: 10,
+            sha256: sha256.map(|s| s.to_string()),
+            file_unique_id: file_unique_id.map(|s| s.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_name() {
+        let mut store = MetadataStore::default();
+        let sha256 = "ab12cd34";
+
+        store.insert(file("abcde_report.pdf", Some(sha256), None));
+
+        let mut copy = file("fghij_report.pdf", Some(sha256), None);
+        copy.content_addressed = true;
+
+        assert_eq!(copy.storage_name(), "ab/12/ab12cd34");
+
+        store.insert(copy);
+
+        assert!(store.is_stored("abcde_report.pdf"));
+        assert!(store.is_stored("ab/12/ab12cd34"));
+        assert!(!store.is_stored("fghij_report.pdf"));
+
+        store.remove("fghij_report.pdf");
+
+        assert!(!store.is_stored("ab/12/ab12cd34"));
+
+        let mut sharded = file("klmno_photo.jpg", Some(sha256), None);
+        sharded.folder = Some("2024/06/04".to_owned());
+
+        assert_eq!(sharded.storage_name(), "2024/06/04/klmno_photo.jpg");
+    }
+
+    #[tokio::test]
+    async fn test_find_by_unique_id() {
+        let mut store = MetadataStore::default();
+
+        store.insert(file("abcde_report.pdf", None, Some("unique1")));
+        store.insert(file("fghij_photo.jpg", None, None));
+
+        assert_eq!(store.find_by_unique_id("unique1").unwrap().file_name, "abcde_report.pdf");
+        assert!(store.find_by_unique_id("unique2").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_find_by_hash() {
+        let mut store = MetadataStore::default();
+
+        store.insert(file("abcde_report.pdf", Some("deadbeef"), None));
+
+        assert_eq!(store.find_by_hash("deadbeef").unwrap().file_name, "abcde_report.pdf");
+        assert!(store.find_by_hash("cafebabe").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_versions_are_stored() {
+        let mut store = MetadataStore::default();
+        let mut current = file("abcde_report.pdf", None, None);
+
+        current.versions.push(FileVersion {
+            version: 1,
+            storage_name: "versions/abcde_report.pdf.v1".to_owned(),
+            size: 10,
+            sha256: None,
+            created_at: 0,
+            owners: Vec::new(),
+            private: false,
+        });
+
+        store.insert(current);
+
+        assert!(store.is_stored("versions/abcde_report.pdf.v1"));
+        assert_eq!(store.get("abcde_report.pdf").unwrap().version(1).unwrap().size, 10);
+        assert!(store.get("abcde_report.pdf").unwrap().version(2).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stored_bytes() {
+        let mut store = MetadataStore::default();
+
+        assert_eq!(store.stored_bytes(), 0);
+
+        store.insert(file("abcde_report.pdf", Some("ab12cd34"), None));
+
+        for name in ["fghij_a.pdf", "klmno_b.pdf"] {
+            let mut copy = file(name, Some("ab12cd34"), None);
+            copy.content_addressed = true;
+
+            store.insert(copy);
+        }
+
+        // the two content addressed files share their content
+        assert_eq!(store.stored_bytes(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_idle_storage_names() {
+        let mut store = MetadataStore::default();
+
+        // uploaded at 0 and never downloaded
+        store.insert(file("abcde_report.pdf", None, None));
+
+        let mut downloaded = file("fghij_photo.jpg", None, None);
+        downloaded.downloaded_at = Some(200);
+
+        store.insert(downloaded);
+
+        for (name, downloaded_at) in [("klmno_a.pdf", 50), ("pqrst_b.pdf", 150)] {
+            let mut copy = file(name, Some("ab12cd34"), None);
+            copy.content_addressed = true;
+            copy.downloaded_at = Some(downloaded_at);
+
+            store.insert(copy);
+        }
+
+        // the shared content was downloaded with the second file
+        assert_eq!(store.idle_storage_names(100), vec!["abcde_report.pdf"]);
+        assert_eq!(store.idle_storage_names(201), vec!["ab/12/ab12cd34", "abcde_report.pdf", "fghij_photo.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let mut store = MetadataStore::default();
+
+        store.insert(file("abcde_report.pdf", Some("deadbeef"), Some("unique1")));
+
+        assert!(store.remove("abcde_report.pdf").is_some());
+        assert!(store.get("abcde_report.pdf").is_none());
+        assert!(store.find_by_hash("deadbeef").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired() {
+        let mut store = MetadataStore::default();
+
+        let mut expiring = file("abcde_report.pdf", None, None);
+        expiring.expires_at = Some(100);
+
+        store.insert(expiring);
+        store.insert(file("fghij_photo.jpg", None, None));
+
+        assert!(store.expired(99, &Retention::default()).is_empty());
+        assert_eq!(store.expired(100, &Retention::default()), vec!["abcde_report.pdf".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_find_by_id_mut() {
+        let mut store = MetadataStore::default();
+
+        store.insert(file("abcde_my_report.pdf", None, None));
+        store.insert(file("a_cde_photo.jpg", None, None));
+
+        assert_eq!(store.find_by_id_mut("a_cde").unwrap().file_name, "a_cde_photo.jpg");
+
+        store.find_by_id_mut("abcde").unwrap().expires_at = Some(100);
+
+        assert_eq!(store.get("abcde_my_report.pdf").unwrap().expires_at, Some(100));
+        assert!(store.find_by_id_mut("abcd").is_none());
+
+        let mut named = file("2024-06-04_x1y2z3w4_report.pdf", None, None);
+        named.id = Some("x1y2z3w4".to_owned());
+
+        store.insert(named);
+
+        assert_eq!(store.find_by_id_mut("x1y2z3w4").unwrap().file_name, "2024-06-04_x1y2z3w4_report.pdf");
+        assert!(store.find_by_id_mut("2024-").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_extend_expiry() {
+        let mut metadata = file("abcde_report.pdf", None, None);
+
+        metadata.expires_at = Some(100);
+        metadata.extend_expiry(Some(50));
+        assert_eq!(metadata.expires_at, Some(100));
+
+        metadata.extend_expiry(Some(200));
+        assert_eq!(metadata.expires_at, Some(200));
+
+        metadata.extend_expiry(None);
+        assert_eq!(metadata.expires_at, None);
+
+        metadata.extend_expiry(Some(300));
+        assert_eq!(metadata.expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let mut store = MetadataStore::default();
+
+        let mut report = file("abcde_Annual_Report.pdf", None, None);
+        report.owners = vec!["1".to_string()];
+        report.created_at = 10;
+
+        let mut old_report = file("fghij_report_draft.pdf", None, None);
+        old_report.owners = vec!["1".to_string(), "2".to_string()];
+        old_report.created_at = 5;
+
+        let mut expired = file("klmno_report.pdf", None, None);
+        expired.owners = vec!["1".to_string()];
+        expired.expires_at = Some(50);
+
+        store.insert(report);
+        store.insert(old_report);
+        store.insert(expired);
+        store.insert(file("pqrst_report.pdf", None, None));
+
+        let names = |files: Vec<&FileMetadata>| files.iter().map(|file| file.file_name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(names(store.search("1", "REPORT", 100)), vec!["abcde_Annual_Report.pdf", "fghij_report_draft.pdf"]);
+        assert_eq!(names(store.search("1", "report draft", 100)), vec!["fghij_report_draft.pdf"]);
+        assert_eq!(names(store.search("2", "", 100)), vec!["fghij_report_draft.pdf"]);
+        assert!(store.search("3", "", 100).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_owner() {
+        let mut metadata = file("abcde_report.pdf", None, None);
+
+        metadata.add_owner("1".to_string());
+        metadata.add_owner("1".to_string());
+        metadata.add_owner("2".to_string());
+
+        assert_eq!(metadata.owners, vec!["1".to_string(), "2".to_string()]);
+    }
+}
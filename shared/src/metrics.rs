@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use crate::stats::StorageStats;
+
+/// Upper bounds in seconds of the processing duration buckets
+const DURATION_BUCKETS: [f64; 8] = [1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+
+/// Counters of the queue and the downloads, rendered in the Prometheus text format by `render`
+#[derive(Default)]
+pub struct Metrics {
+    queue_length: AtomicU64,
+    enqueued: AtomicU64,
+    downloaded_bytes: AtomicU64,
+    expired_files: AtomicU64,
+    reclaimed_bytes: AtomicU64,
+    /// Failed files by the kind of the error
+    failures: Mutex<BTreeMap<&'static str, u64>>,
+    /// Processed files per duration bucket, the last one counts all files
+    durations: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_millis: AtomicU64,
+    /// Last usage of the storage, see `set_storage`
+    storage: Mutex<Option<StorageStats>>,
+}
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+impl Metrics {
+    pub fn set_queue_length(&self, length: usize) {
+        self.queue_length.store(length as u64, Ordering::Relaxed);
+    }
+
+    pub fn enqueued(&self) {
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn downloaded(&self, bytes: usize) {
+        self.downloaded_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records files removed by the cleanup task, the bytes count only content which was deleted from the storage
+    pub fn expired(&self, files: usize, reclaimed_bytes: u64) {
+        self.expired_files.fetch_add(files as u64, Ordering::Relaxed);
+        self.reclaimed_bytes.fetch_add(reclaimed_bytes, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self, kind: &'static str) {
+        *self.failures.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    /// Records how long a queued file took from the start of its download until it was stored or failed
+    pub fn processed(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (index, bound) in DURATION_BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                self.durations[index].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.durations[DURATION_BUCKETS.len()].fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_millis.fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Records the usage of the storage, checked periodically by the bot
+    pub fn set_storage(&self, stats: StorageStats) {
+        *self.storage.lock().unwrap() = Some(stats);
+    }
+
+    /// Renders the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str("# HELP file2link_queue_length Files waiting or being downloaded.\n");
+        text.push_str("# TYPE file2link_queue_length gauge\n");
+        text.push_str(&format!("file2link_queue_length {}\n", self.queue_length.load(Ordering::Relaxed)));
+
+        text.push_str("# HELP file2link_enqueued_total Files added to the queue.\n");
+        text.push_str("# TYPE file2link_enqueued_total counter\n");
+        text.push_str(&format!("file2link_enqueued_total {}\n", self.enqueued.load(Ordering::Relaxed)));
+
+        text.push_str("# HELP file2link_downloaded_bytes_total Bytes downloaded from Telegram and URLs.\n");
+        text.push_str("# TYPE file2link_downloaded_bytes_total counter\n");
+        text.push_str(&format!("file2link_downloaded_bytes_total {}\n", self.downloaded_bytes.load(Ordering::Relaxed)));
+
+        text.push_str("# HELP file2link_expired_files_total Stored files removed after they expired.\n");
+        text.push_str("# TYPE file2link_expired_files_total counter\n");
+        text.push_str(&format!("file2link_expired_files_total {}\n", self.expired_files.load(Ordering::Relaxed)));
+
+        text.push_str("# HELP file2link_reclaimed_bytes_total Bytes freed in the storage by removing expired files.\n");
+        text.push_str("# TYPE file2link_reclaimed_bytes_total counter\n");
+        text.push_str(&format!("file2link_reclaimed_bytes_total {}\n", self.reclaimed_bytes.load(Ordering::Relaxed)));
+
+        text.push_str("# HELP file2link_failures_total Files which failed after all their attempts.\n");
+        text.push_str("# TYPE file2link_failures_total counter\n");
+
+        for (kind, count) in self.failures.lock().unwrap().iter() {
+            text.push_str(&format!("file2link_failures_total{{kind=\"{}\"}} {}\n", kind, count));
+        }
+
+        text.push_str("# HELP file2link_processing_seconds Time from the start of a download until the file was stored or failed.\n");
+        text.push_str("# TYPE file2link_processing_seconds histogram\n");
+
+        for (index, bound) in DURATION_BUCKETS.iter().enumerate() {
+            text.push_str(&format!(
+                "file2link_processing_seconds_bucket{{le=\"{}\"}} {}\n",
+                bound,
+                self.durations[index].load(Ordering::Relaxed),
+            ));
+        }
+
+        let count = self.durations[DURATION_BUCKETS.len()].load(Ordering::Relaxed);
+
+        text.push_str(&format!("file2link_processing_seconds_bucket{{le=\"+Inf\"}} {}\n", count));
+        text.push_str(&format!(
+            "file2link_processing_seconds_sum {}\n",
+            self.duration_sum_millis.load(Ordering::Relaxed) as f64 / 1000.0,
+        ));
+        text.push_str(&format!("file2link_processing_seconds_count {}\n", count));
+
+        if let Some(stats) = self.storage.lock().unwrap().as_ref() {
+            text.push_str("# HELP file2link_stored_files Files with metadata.\n");
+            text.push_str("# TYPE file2link_stored_files gauge\n");
+            text.push_str(&format!("file2link_stored_files {}\n", stats.files));
+
+            text.push_str("# HELP file2link_stored_bytes Bytes of content in the storage.\n");
+            text.push_str("# TYPE file2link_stored_bytes gauge\n");
+            text.push_str(&format!("file2link_stored_bytes {}\n", stats.stored_bytes));
+
+            if let Some(free_bytes) = stats.free_bytes {
+                text.push_str("# HELP file2link_free_bytes Bytes available on the volume of the files directory.\n");
+                text.push_str("# TYPE file2link_free_bytes gauge\n");
+                text.push_str(&format!("file2link_free_bytes {}\n", free_bytes));
+            }
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render() {
+        let metrics = Metrics::default();
+
+        metrics.set_queue_length(3);
+        metrics.enqueued();
+        metrics.enqueued();
+        metrics.downloaded(1024);
+        metrics.expired(2, 2048);
+        metrics.failed("unreachable");
+        metrics.failed("unreachable");
+        metrics.processed(Duration::from_millis(2500));
+        metrics.processed(Duration::from_secs(7200));
+
+        let text = metrics.render();
+
+        assert!(text.contains("file2link_queue_length 3\n"));
+        assert!(text.contains("file2link_enqueued_total 2\n"));
+        assert!(text.contains("file2link_downloaded_bytes_total 1024\n"));
+        assert!(text.contains("file2link_expired_files_total 2\n"));
+        assert!(text.contains("file2link_reclaimed_bytes_total 2048\n"));
+        assert!(text.contains("file2link_failures_total{kind=\"unreachable\"} 2\n"));
+        assert!(text.contains("file2link_processing_seconds_bucket{le=\"1\"} 0\n"));
+        assert!(text.contains("file2link_processing_seconds_bucket{le=\"5\"} 1\n"));
+        assert!(text.contains("file2link_processing_seconds_bucket{le=\"3600\"} 1\n"));
+        assert!(text.contains("file2link_processing_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("file2link_processing_seconds_sum 7202.5\n"));
+        assert!(text.contains("file2link_processing_seconds_count 2\n"));
+        // the storage has not been checked yet
+        assert!(!text.contains("file2link_stored_bytes"));
+
+        metrics.set_storage(StorageStats { files: 2, stored_bytes: 4096, free_bytes: Some(8192) });
+
+        let text = metrics.render();
+
+        assert!(text.contains("file2link_stored_files 2\n"));
+        assert!(text.contains("file2link_stored_bytes 4096\n"));
+        assert!(text.contains("file2link_free_bytes 8192\n"));
+    }
+}
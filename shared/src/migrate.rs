@@ -0,0 +1,205 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures::{StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+
+use crate::metadata::MetadataStore;
+use crate::storage::{ByteStream, Storage};
+
+/// How one file was moved to the new storage
+#[derive(Debug, Clone, PartialEq)]
+pub enum Migrated {
+    /// The file was copied and read back with the same hash, containing its size
+    Copied(u64),
+    /// The new storage already has the file with the same size and hash, like after an interrupted migration
+    Skipped,
+}
+
+/// Totals of a migration, see `migrate`
+#[derive(Debug, Default, PartialEq)]
+pub struct MigrationReport {
+    pub copied: usize,
+    pub copied_bytes: u64,
+    pub skipped: usize,
+    /// Names of the files which could not be copied or verified with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+/// Get the names of everything to migrate: the content of every file with metadata and its versions and the files
+/// outside of folders
+/// Content shared by content addressed files is listed once
+pub async fn storage_names(metadata: &MetadataStore, storage: &Storage) -> io::Result<Vec<String>> {
+    let mut names: BTreeSet<String> = metadata.files()
+        .flat_map(|file| std::iter::once(file.storage_name())
+            .chain(file.versions.iter().map(|version| version.storage_name.clone())))
+        .collect();
+
+    names.extend(storage.list().await?);
+
+    Ok(names.into_iter().collect())
+}
+
+/// Copies the files from one storage to another as they are stored, encrypted and compressed files stay so
+/// Every copy is read back and compared by its SHA-256, the old storage is not changed
+///
+/// # Arguments
+/// * `on_file` - Called after every file with its number from 1, the name and the result, to report the progress
+pub async fn migrate<F>(from: &Storage, to: &Storage, names: &[String], mut on_file: F) -> MigrationReport
+where
+    F: FnMut(usize, &str, &Result<Migrated, String>),
+{
+    let mut report = MigrationReport::default();
+
+    for (index, name) in names.iter().enumerate() {
+        let result = migrate_file(from, to, name).await;
+
+        on_file(index + 1, name, &result);
+
+        match result {
+            Ok(Migrated::Copied(size)) => {
+                report.copied += 1;
+                report.copied_bytes += size;
+            }
+            Ok(Migrated::Skipped) => report.skipped += 1,
+            Err(e) => report.failed.push((name.clone(), e)),
+        }
+    }
+
+    report
+}
+
+pub(crate) async fn migrate_file(from: &Storage, to: &Storage, name: &str) -> Result<Migrated, String> {
+    let stored = match from.stat(name).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return Err("the file is missing".to_owned()),
+        Err(e) => return Err(format!("failed to look up the file: {}", e)),
+    };
+
+    let (size, sha256) = match to.stat(name).await {
+        Ok(Some(copy)) if copy.size == stored.size => {
+            let source = read_hash(from, name).await.map_err(|e| format!("failed to read the file: {}", e))?;
+            let copy = read_hash(to, name).await.map_err(|e| format!("failed to read the copy: {}", e))?;
+
+            if source == copy {
+                return Ok(Migrated::Skipped);
+            }
+
+            copy_file(from, to, name).await?
+        }
+        Ok(_) => copy_file(from, to, name).await?,
+        Err(e) => return Err(format!("failed to look up the copy: {}", e)),
+    };
+
+    match read_hash(to, name).await {
+        Ok(copy) if copy == (size, sha256) => Ok(Migrated::Copied(size)),
+        Ok(_) => Err("the copy differs from the file".to_owned()),
+        Err(e) => Err(format!("failed to read the copy: {}", e)),
+    }
+}
+
+/// # Returns
+/// * `Result` containing the size and the hex encoded SHA-256 of the copied content
+async fn copy_file(from: &Storage, to: &Storage, name: &str) -> Result<(u64, String), String> {
+    let data = from.get(name).await.map_err(|e| format!("failed to read the file: {}", e))?;
+
+    // hashed while it is copied, so the file is not read twice
+    let hasher = Arc::new(Mutex::new((0, Sha256::new())));
+
+    let data = {
+        let hasher = hasher.clone();
+
+        data.inspect_ok(move |chunk| {
+            let mut hasher = hasher.lock().unwrap();
+
+            hasher.0 += chunk.len() as u64;
+            hasher.1.update(chunk);
+        }).boxed()
+    };
+
+    to.put(name, data).await.map_err(|e| format!("failed to copy the file: {}", e))?;
+
+    let (size, hasher) = std::mem::take(&mut *hasher.lock().unwrap());
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+async fn read_hash(storage: &Storage, name: &str) -> io::Result<(u64, String)> {
+    let mut data: ByteStream = storage.get(name).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+
+    while let Some(chunk) = data.try_next().await? {
+        size += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::fs;
+
+    use crate::metadata::FileMetadata;
+    use crate::storage::LocalStorage;
+
+    use super::*;
+
+    fn file(name: &str, sha256: &str, content_addressed: bool) -> FileMetadata {
+        FileMetadata {
+            file_name: name.to_owned(),
+            size: 5,
+            sha256: Some(sha256.to_owned()),
+            content_addressed,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let root = std::env::temp_dir().join("file2link_test_migrate");
+        let _ = fs::remove_dir_all(&root).await;
+
+        let from: Storage = Arc::new(LocalStorage::new(root.join("from")));
+        let to: Storage = Arc::new(LocalStorage::new(root.join("to")));
+
+        fs::create_dir_all(root.join("from/ab/cd")).await.unwrap();
+        fs::write(root.join("from/abcde_report.pdf"), b"report").await.unwrap();
+        fs::write(root.join("from/fghij_legacy.txt"), b"legacy").await.unwrap();
+        fs::write(root.join("from/ab/cd/abcd1234"), b"shared").await.unwrap();
+
+        let mut metadata = MetadataStore::default();
+
+        metadata.insert(file("abcde_report.pdf", "0000", false));
+        metadata.insert(file("klmno_a.txt", "abcd1234", true));
+        metadata.insert(file("pqrst_b.txt", "abcd1234", true));
+        metadata.insert(file("uvwxy_missing.txt", "ffff", false));
+
+        let names = storage_names(&metadata, &from).await.unwrap();
+
+        assert_eq!(names, vec!["ab/cd/abcd1234", "abcde_report.pdf", "fghij_legacy.txt", "uvwxy_missing.txt"]);
+
+        let mut progress = Vec::new();
+
+        let report = migrate(&from, &to, &names, |index, name, result| progress.push((index, name.to_owned(), result.is_ok()))).await;
+
+        assert_eq!(report.copied, 3);
+        assert_eq!(report.copied_bytes, 18);
+        assert_eq!(report.failed, vec![("uvwxy_missing.txt".to_owned(), "the file is missing".to_owned())]);
+        assert_eq!(progress.len(), 4);
+        assert_eq!(progress[3], (4, "uvwxy_missing.txt".to_owned(), false));
+        assert_eq!(fs::read(root.join("to/ab/cd/abcd1234")).await.unwrap(), b"shared");
+
+        // a copy with the same size but another content is copied again
+        fs::write(root.join("to/fghij_legacy.txt"), b"LEGACY").await.unwrap();
+
+        let report = migrate(&from, &to, &names[..3], |_, _, _| {}).await;
+
+        assert_eq!((report.copied, report.skipped), (1, 2));
+        assert_eq!(fs::read(root.join("to/fghij_legacy.txt")).await.unwrap(), b"legacy");
+
+        let _ = fs::remove_dir_all(&root).await;
+    }
+}
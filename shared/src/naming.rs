@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+
+use crate::config::ConfigError;
+
+/// Pattern of `FILE_NAME_PATTERN` when it is not set, the random ID followed by the original name
+pub const DEFAULT_PATTERN: &str = "{id}_{original}";
+
+/// Characters of the random ID when `FILE_ID_ALPHABET` is not set, letters and digits without the ones easily mistaken
+/// for another like `0`/`O`/`o`, `1`/`l`/`I`, `2`/`Z`, `5`/`S`/`s` and `u`/`v`, so IDs read from a screen or a printout
+/// are typed correctly. Letters which only differ in size like `C`/`c` are kept, the ID is case sensitive
+pub const DEFAULT_ALPHABET: &str = "346789ABCDEFGHJKLMNPQRTUVWXYabcdefghijkmnpqrtwxyz";
+
+/// The URL safe alphabet of nanoid, the default before `DEFAULT_ALPHABET`
+pub const URL_SAFE_ALPHABET: &str = "_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Length of the random ID when `FILE_ID_LENGTH` is not set, one more than `metadata::FILE_ID_LENGTH` as the default
+/// alphabet is smaller
+pub const DEFAULT_ID_LENGTH: usize = 6;
+
+/// How stored files are named, see `FILE_NAME_PATTERN`
+///
+/// The pattern supports the placeholders `{id}`, `{original}`, `{date}` and `{user}`, the ID keeps the names unique
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileNaming {
+    pattern: String,
+    id_length: usize,
+    alphabet: Vec<char>,
+    /// The original name is lowercased and everything but letters, digits and dots is replaced with '-'
+    slug: bool,
+}
+
+impl Default for FileNaming {
+    fn default() -> Self {
+        Self::new(DEFAULT_PATTERN, DEFAULT_ID_LENGTH, DEFAULT_ALPHABET, false)
+            .expect("the default naming is valid")
+    }
+}
+
+impl FileNaming {
+    /// # Returns
+    /// * `Err` naming the variable if the pattern has no `{id}`, the ID is not 3 to 21 characters long or the
+    ///   alphabet has fewer than 2 characters or characters which are not safe in links
+    pub fn new(pattern: &str, id_length: usize, alphabet: &str, slug: bool) -> Result<Self, ConfigError> {
+        if !pattern.contains("{id}") {
+            return Err(ConfigError::malformed("FILE_NAME_PATTERN", pattern, "a pattern with {id}"));
+        }
+
+        if pattern.contains(['/', '\\', '?', '#']) {
+            return Err(ConfigError::malformed("FILE_NAME_PATTERN", pattern, "a pattern without '/', '\\', '?' and '#'"));
+        }
+
+        if !(3..=21).contains(&id_length) {
+            return Err(ConfigError::out_of_range("FILE_ID_LENGTH", &id_length.to_string(), "it must be from 3 to 21"));
+        }
+
+        let mut chars: Vec<char> = alphabet.chars().collect();
+
+        chars.sort_unstable();
+        chars.dedup();
+
+        if chars.len() < 2 || !chars.iter().all(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_') {
+            return Err(ConfigError::malformed(
+                "FILE_ID_ALPHABET",
+                alphabet,
+                "at least 2 of the characters a-z, A-Z, 0-9, '-' and '_'",
+            ));
+        }
+
+        Ok(Self { pattern: pattern.to_owned(), id_length, alphabet: chars, slug })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn id_length(&self) -> usize {
+        self.id_length
+    }
+
+    pub fn alphabet(&self) -> &[char] {
+        &self.alphabet
+    }
+
+    pub fn slug(&self) -> bool {
+        self.slug
+    }
+
+    /// Get the name of a new file
+    ///
+    /// # Arguments
+    /// * `id` - Random ID of the file, see `id_length` and `alphabet`
+    /// * `original` - Name of the file without spaces and slashes
+    /// * `user` - ID of the uploader
+    /// * `created_at` - Unix time in seconds, `{date}` is the UTC date like "2024-06-04"
+    pub fn file_name(&self, id: &str, original: &str, user: Option<&str>, created_at: u64) -> String {
+        let original = match self.slug {
+            true => slug(original),
+            false => original.to_owned(),
+        };
+        let date = DateTime::<Utc>::from_timestamp(created_at as i64, 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%d")
+            .to_string();
+
+        self.pattern
+            .replace("{original}", &original)
+            .replace("{date}", &date)
+            .replace("{user}", user.unwrap_or("unknown"))
+            .replace("{id}", id)
+    }
+}
+
+/// Lowercases the name and replaces runs of anything but letters, digits and dots with one '-'
+fn slug(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if c == '.' {
+            // "report-.pdf" reads worse than "report.pdf"
+            if slug.ends_with('-') {
+                slug.pop();
+            }
+
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_matches('-');
+
+    match slug.is_empty() {
+        true => "file".to_owned(),
+        false => slug.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_default_naming() {
+        let naming = FileNaming::default();
+
+        assert_eq!(naming.id_length(), 6);
+        assert_eq!(naming.alphabet().len(), 49);
+        assert!(!naming.alphabet().iter().any(|c| "0Oo1lI5S_-".contains(*c)));
+        assert_eq!(naming.file_name("abcdef", "My_Report.pdf", Some("123"), 0), "abcdef_My_Report.pdf");
+        assert!(FileNaming::new(DEFAULT_PATTERN, 5, URL_SAFE_ALPHABET, false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_file_name() {
+        let naming = FileNaming::new("{date}-{user}-{id}-{original}", 8, "abc123", true).unwrap();
+
+        // 2024-06-04 12:00:00 UTC
+        let created_at = 1717502400;
+
+        assert_eq!(naming.file_name("ab12ab12", "My_Report (final).PDF", Some("123"), created_at), "2024-06-04-123-ab12ab12-my-report-final.pdf");
+        assert_eq!(naming.file_name("ab12ab12", "Отчёт.pdf", None, created_at), "2024-06-04-unknown-ab12ab12-отчёт.pdf");
+        assert_eq!(naming.alphabet(), &['1', '2', '3', 'a', 'b', 'c']);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_naming() {
+        assert!(FileNaming::new("{original}", 5, DEFAULT_ALPHABET, false).is_err());
+        assert!(FileNaming::new("{date}/{id}", 5, DEFAULT_ALPHABET, false).is_err());
+        assert!(FileNaming::new(DEFAULT_PATTERN, 2, DEFAULT_ALPHABET, false).is_err());
+        assert!(FileNaming::new(DEFAULT_PATTERN, 22, DEFAULT_ALPHABET, false).is_err());
+        assert!(FileNaming::new(DEFAULT_PATTERN, 5, "aaaa", false).is_err());
+        assert!(FileNaming::new(DEFAULT_PATTERN, 5, "ab/c", false).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slug() {
+        assert_eq!(slug("My_Report (final).PDF"), "my-report-final.pdf");
+        assert_eq!(slug("__init__.py"), "init.py");
+        assert_eq!(slug("!!!"), "file");
+    }
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::utils::{format_duration, format_size};
+
+const SNAPSHOT_PATH: &str = "config/queue_snapshot.json";
+
+/// State of the queue at one point in time, shown by the admin API and `f2l-cli queue list`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct QueueSnapshot {
+    /// Unix time in seconds the snapshot was taken at
+    pub taken_at: u64,
+    pub paused: bool,
+    /// Jobs in queue order, the ones being downloaded first
+    pub jobs: Vec<QueueJob>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueueJob {
+    pub id: u64,
+    /// ID shown in the status message of the job, used to cancel it
+    #[serde(default)]
+    pub short_id: String,
+    pub source: JobSource,
+    /// File name or URL of the file
+    pub name: String,
+    /// User who sent the file, missing for channel posts
+    pub user_id: Option<u64>,
+    pub chat_id: i64,
+    pub state: JobState,
+    /// Bytes downloaded so far
+    pub downloaded: u64,
+    /// Size of the file, if known yet
+    pub size: Option<u64>,
+    /// Unix time in seconds the file was queued at
+    pub queued_at: u64,
+    /// Unix time in seconds the download may start at, for files scheduled for later
+    #[serde(default)]
+    pub not_before: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobSource {
+    Telegram,
+    Url,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Waiting,
+    /// Waits for `not_before`
+    Scheduled,
+    Downloading,
+}
+
+impl QueueJob {
+    /// Get a line describing the job like
+    /// "#3 k3x9q0ab url https://example.com/a.zip by 42 in chat 42, downloading 5.0 MB of 10.0 MB, queued 2m ago"
+    pub fn summary(&self, now: u64) -> String {
+        let source = match self.source {
+            JobSource::Telegram => "telegram",
+            JobSource::Url => "url",
+        };
+
+        let user = match self.user_id {
+            Some(user_id) => format!(" by {}", user_id),
+            None => String::new(),
+        };
+
+        let state = match (self.state, self.size) {
+            (JobState::Waiting, _) => "waiting".to_owned(),
+            (JobState::Scheduled, _) => format!(
+                "scheduled in {}",
+                format_duration(self.not_before.unwrap_or(now).saturating_sub(now))
+            ),
+            (JobState::Downloading, Some(size)) => format!("downloading {} of {}", format_size(self.downloaded), format_size(size)),
+            (JobState::Downloading, None) => format!("downloading {}", format_size(self.downloaded)),
+        };
+
+        format!(
+            "#{} {} {} {}{} in chat {}, {}, queued {} ago",
+            self.id,
+            self.short_id,
+            source,
+            self.name,
+            user,
+            self.chat_id,
+            state,
+            format_duration(now.saturating_sub(self.queued_at)),
+        )
+    }
+}
+
+pub async fn load_snapshot() -> Result<QueueSnapshot, Box<dyn Error>> {
+    let data = match fs::read_to_string(SNAPSHOT_PATH).await {
+        Ok(data) => data,
+        Err(_) => {
+            debug!("Queue snapshot '{}' not found, the queue is empty", SNAPSHOT_PATH);
+
+            return Ok(QueueSnapshot::default());
+        }
+    };
+
+    match serde_json::from_str(&data) {
+        Ok(snapshot) => Ok(snapshot),
+        Err(e) => {
+            error!("Failed to parse queue snapshot: {}", e);
+
+            Err("Failed to parse queue snapshot".into())
+        }
+    }
+}
+
+pub async fn save_snapshot(snapshot: &QueueSnapshot) -> Result<(), Box<dyn Error>> {
+    if let Some((dir_path, _)) = SNAPSHOT_PATH.rsplit_once('/') {
+        fs::create_dir_all(dir_path).await?;
+    }
+
+    let data = serde_json::to_string_pretty(snapshot)?;
+    fs::write(SNAPSHOT_PATH, data).await?;
+
+    debug!("Queue snapshot saved to '{}'", SNAPSHOT_PATH);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_summary() {
+        let mut job = QueueJob {
+            id: 3,
+            short_id: "k3x9q0ab".to_owned(),
+            source: JobSource::Url,
+            name: "https://example.com/a.zip".to_owned(),
+            user_id: Some(42),
+            chat_id: 42,
+            state: JobState::Waiting,
+            downloaded: 0,
+            size: None,
+            queued_at: 1000,
+            not_before: None,
+        };
+
+        assert_eq!(job.summary(1120), "#3 k3x9q0ab url https://example.com/a.zip by 42 in chat 42, waiting, queued 2m ago");
+
+        job.state = JobState::Scheduled;
+        job.not_before = Some(1120 + 3 * 60 * 60);
+
+        assert_eq!(job.summary(1120), "#3 k3x9q0ab url https://example.com/a.zip by 42 in chat 42, scheduled in 3h 0m, queued 2m ago");
+
+        job.state = JobState::Downloading;
+        job.downloaded = 1024;
+
+        assert_eq!(job.summary(1120), "#3 k3x9q0ab url https://example.com/a.zip by 42 in chat 42, downloading 1.0 KB, queued 2m ago");
+    }
+}
@@ -0,0 +1,68 @@
+use log::warn;
+use serde::Serialize;
+
+use crate::metadata::MetadataStore;
+use crate::utils::get_free_space;
+
+/// Usage of the storage, served by `/api/stats` and exported with the metrics
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StorageStats {
+    /// Files with metadata, expired files which were not removed yet included
+    pub files: usize,
+    /// Bytes of stored content, see `MetadataStore::stored_bytes`
+    pub stored_bytes: u64,
+    /// Bytes available on the volume of `FILES_DIR`, `None` if it could not be read
+    pub free_bytes: Option<u64>,
+}
+
+impl StorageStats {
+    pub fn collect(metadata: &MetadataStore, files_dir: &str) -> Self {
+        let free_bytes = match get_free_space(files_dir) {
+            Ok(free_bytes) => Some(free_bytes),
+            Err(e) => {
+                warn!("Failed to get free space of '{}': {}", files_dir, e);
+
+                None
+            }
+        };
+
+        Self {
+            files: metadata.files().count(),
+            stored_bytes: metadata.stored_bytes(),
+            free_bytes,
+        }
+    }
+
+    /// The free space is known and below the threshold, see `LOW_SPACE_THRESHOLD`
+    pub fn is_low_on_space(&self, threshold: Option<u64>) -> bool {
+        matches!((self.free_bytes, threshold), (Some(free_bytes), Some(threshold)) if free_bytes < threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect() {
+        let stats = StorageStats::collect(&MetadataStore::default(), ".");
+
+        assert_eq!(stats.files, 0);
+        assert_eq!(stats.stored_bytes, 0);
+        assert!(stats.free_bytes.is_some());
+
+        let stats = StorageStats::collect(&MetadataStore::default(), "/does/not/exist");
+
+        assert_eq!(stats.free_bytes, None);
+    }
+
+    #[tokio::test]
+    async fn test_is_low_on_space() {
+        let stats = StorageStats { files: 1, stored_bytes: 10, free_bytes: Some(100) };
+
+        assert!(stats.is_low_on_space(Some(101)));
+        assert!(!stats.is_low_on_space(Some(100)));
+        assert!(!stats.is_low_on_space(None));
+        assert!(!StorageStats { free_bytes: None, ..stats }.is_low_on_space(Some(101)));
+    }
+}
@@ -0,0 +1,434 @@
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use log::{debug, warn};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio_util::bytes::{Bytes, BytesMut};
+use tokio_util::io::ReaderStream;
+
+use crate::config::{Config, StorageConfig};
+use crate::compression::CompressedStorage;
+use crate::encryption::EncryptedStorage;
+use crate::s3::S3Storage;
+use crate::tiering::TieredStorage;
+use crate::webdav::WebDavStorage;
+
+/// Default directory of `LocalStorage`, see `FILES_DIR`
+pub const FILES_PATH: &str = "files";
+
+pub type ByteStream = BoxStream<'static, io::Result<Bytes>>;
+
+/// Storage shared by the queue, the server and the cleanup task
+pub type Storage = Arc<dyn StorageBackend>;
+
+/// Size and modification time of a stored file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoredObject {
+    pub size: u64,
+    /// Unix time in seconds, if the backend knows it
+    pub modified_at: Option<u64>,
+}
+
+/// Where downloaded files are kept and served from
+/// Files are stored under the names their links point to, like "abcde_report.pdf"
+pub trait StorageBackend: Send + Sync {
+    /// Stores the data under the name, replacing a stored file with the same name
+    ///
+    /// # Returns
+    /// * `Result` containing the number of stored bytes
+    fn put<'a>(&'a self, name: &'a str, data: ByteStream) -> BoxFuture<'a, io::Result<u64>>;
+
+    /// Stores a finished local file under the name, the local file is gone afterwards
+    /// Downloads are written and post-processed locally and handed to the storage with this
+    fn put_file<'a>(&'a self, name: &'a str, path: &'a Path) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            let file = File::open(path).await?;
+            let size = self.put(name, ReaderStream::new(file).boxed()).await?;
+
+            fs::remove_file(path).await?;
+
+            Ok(size)
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<ByteStream>>;
+
+    /// Get the content as stored with zstd, so the server can send it with `Content-Encoding: zstd`
+    ///
+    /// # Returns
+    /// * `Result` containing `None` if the file is not stored compressed, the default
+    fn get_compressed<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, io::Result<Option<ByteStream>>> {
+        async { Ok(None) }.boxed()
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Get the names of the stored files outside of folders, so blobs and files stored by date are not listed
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>>;
+
+    /// # Returns
+    /// * `Result` containing `None` if no file is stored under the name
+    fn stat<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<StoredObject>>>;
+
+    /// Get a link the server redirects downloads to instead of streaming the file itself
+    ///
+    /// # Returns
+    /// * `None` if the server streams the file, the default
+    fn redirect_url(&self, _name: &str, _content_disposition: &str) -> Option<String> {
+        None
+    }
+
+    /// Moves a file which was moved to the cold storage back before it is served, see `TieredStorage`
+    /// Does nothing by default
+    fn restore<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+}
+
+/// Get the storage chosen with `STORAGE_BACKEND`, with `COLD_STORAGE_BACKEND` behind it
+/// Files are encrypted when `ENCRYPTION_KEY` is set and compressed with `COMPRESSION`
+pub fn from_config(config: &Config) -> Storage {
+    let storage = backend(config.storage(), config);
+
+    // below the encryption, files are moved between the tiers as they are stored
+    let storage: Storage = match config.cold_storage() {
+        Some(cold_storage) => Arc::new(TieredStorage::new(storage, backend(cold_storage, config))),
+        None => storage,
+    };
+
+    let storage: Storage = match config.encryption_key() {
+        Some(key) => Arc::new(EncryptedStorage::new(storage, &key)),
+        None => storage,
+    };
+
+    // compressed before it is encrypted, encrypted data does not compress
+    match config.compression_level() {
+        Some(level) => Arc::new(CompressedStorage::new(storage, level)),
+        None => storage,
+    }
+}
+
+/// Get the storage without encryption and compression, files are read and written as they are stored
+pub fn backend(storage: StorageConfig, config: &Config) -> Storage {
+    match storage {
+        StorageConfig::Local => Arc::new(LocalStorage::new(config.files_dir())),
+        StorageConfig::S3(s3) => Arc::new(S3Storage::new(s3)),
+        StorageConfig::WebDav(webdav) => Arc::new(WebDavStorage::new(webdav)),
+    }
+}
+
+/// Get the storage name of content stored under its hash, like "ab/cd/abcd..." for the hex encoded SHA-256 "abcd..."
+/// The two levels of folders keep the folders small
+pub fn blob_name(sha256: &str) -> String {
+    match (sha256.get(..2), sha256.get(2..4)) {
+        (Some(first), Some(second)) => format!("{}/{}/{}", first, second, sha256),
+        _ => sha256.to_owned(),
+    }
+}
+
+/// Get the folder of files uploaded at the time in `StorageLayout::Date`, like "2024/06/04" in UTC
+pub fn date_folder(time: u64) -> String {
+    DateTime::<Utc>::from_timestamp(time as i64, 0)
+        .unwrap_or_default()
+        .format("%Y/%m/%d")
+        .to_string()
+}
+
+/// Reads from the stream until a part of the size is full or the stream ends, for backends which upload in parts
+///
+/// # Returns
+/// * `Result` containing `None` if the stream has ended
+pub(crate) async fn read_part(data: &mut ByteStream, part_size: usize) -> io::Result<Option<Bytes>> {
+    let mut part = BytesMut::new();
+
+    while part.len() < part_size {
+        match data.try_next().await? {
+            Some(chunk) => part.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+
+    Ok(Some(part.freeze()).filter(|part| !part.is_empty()))
+}
+
+/// Names may have folders separated by "/" like blobs, see `blob_name`. Empty folders or file names, names starting
+/// with a dot and backslashes are refused by every backend, so the files directory can not be left and the partial
+/// downloads in `.tmp` are not reachable.
+pub(crate) fn check_name(name: &str) -> io::Result<()> {
+    let valid = name.split('/').all(|part| !part.is_empty() && !part.starts_with('.') && !part.contains('\\'));
+
+    if !valid {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("Invalid file name '{}'", name)));
+    }
+
+    Ok(())
+}
+
+/// Keeps the files in a local directory, `files/` by default
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl Default for LocalStorage {
+    fn default() -> Self {
+        Self::new(FILES_PATH)
+    }
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Get the path of a stored file, see `check_name`
+    fn path(&self, name: &str) -> io::Result<PathBuf> {
+        check_name(name)?;
+
+        Ok(self.root.join(name))
+    }
+}
+
+async fn create_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).await,
+        None => Ok(()),
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn put<'a>(&'a self, name: &'a str, mut data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            let path = self.path(name)?;
+            let upload_path = self.root.join(".tmp").join(format!("{}.upload", name.replace('/', "_")));
+
+            fs::create_dir_all(self.root.join(".tmp")).await?;
+            create_parent_dir(&path).await?;
+
+            // written next to the partial downloads, a half written file must not be served
+            let result = async {
+                let mut file = File::create(&upload_path).await?;
+                let mut size = 0;
+
+                while let Some(chunk) = data.try_next().await? {
+                    file.write_all(&chunk).await?;
+                    size += chunk.len() as u64;
+                }
+
+                file.flush().await?;
+                fs::rename(&upload_path, &path).await?;
+
+                Ok(size)
+            }.await;
+
+            if result.is_err() {
+                let _ = fs::remove_file(&upload_path).await;
+            }
+
+            result
+        }.boxed()
+    }
+
+    fn put_file<'a>(&'a self, name: &'a str, path: &'a Path) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            let target = self.path(name)?;
+
+            create_parent_dir(&target).await?;
+
+            match fs::rename(path, &target).await {
+                Ok(_) => Ok(fs::metadata(&target).await?.len()),
+                Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                    debug!("'{}' is on another filesystem than {:?}, copying it", path.display(), self.root);
+
+                    let file = File::open(path).await?;
+                    let size = self.put(name, ReaderStream::new(file).boxed()).await?;
+
+                    if let Err(e) = fs::remove_file(path).await {
+                        warn!("Failed to remove '{}' after copying: {}", path.display(), e);
+                    }
+
+                    Ok(size)
+                }
+                Err(e) => Err(e),
+            }
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<ByteStream>> {
+        async move {
+            let file = File::open(self.path(name)?).await?;
+
+            Ok(ReaderStream::new(file).boxed())
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let path = self.path(name)?;
+
+            fs::remove_file(&path).await?;
+
+            // folders of blobs and dates go with their last file
+            for folder in path.ancestors().skip(1).take_while(|folder| *folder != self.root.as_path()) {
+                if fs::remove_dir(folder).await.is_err() {
+                    break;
+                }
+            }
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        async move {
+            let mut entries = match fs::read_dir(&self.root).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            };
+
+            let mut names = Vec::new();
+
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+
+                if entry.file_type().await?.is_file() && !name.starts_with('.') {
+                    names.push(name);
+                }
+            }
+
+            names.sort();
+
+            Ok(names)
+        }.boxed()
+    }
+
+    fn stat<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<StoredObject>>> {
+        async move {
+            let metadata = match fs::metadata(self.path(name)?).await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                Ok(_) => return Ok(None),
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+
+            let modified_at = metadata.modified().ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs());
+
+            Ok(Some(StoredObject { size: metadata.len(), modified_at }))
+        }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(name: &str) -> LocalStorage {
+        let root = std::env::temp_dir().join(format!("file2link_test_storage_{}", name));
+
+        let _ = std::fs::remove_dir_all(&root);
+
+        LocalStorage::new(root)
+    }
+
+    async fn read_to_end(storage: &dyn StorageBackend, name: &str) -> io::Result<Vec<u8>> {
+        storage.get(name).await?
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+
+                Ok(data)
+            })
+            .await
+    }
+
+    fn bytes(data: &'static str) -> ByteStream {
+        futures::stream::iter(vec![Ok(Bytes::from(data))]).boxed()
+    }
+
+    #[tokio::test]
+    async fn test_local_storage() {
+        let storage = storage("roundtrip");
+
+        assert_eq!(storage.list().await.unwrap(), Vec::<String>::new());
+        assert_eq!(storage.stat("abcde_a.txt").await.unwrap(), None);
+
+        assert_eq!(storage.put("abcde_a.txt", bytes("hello")).await.unwrap(), 5);
+        assert_eq!(storage.put("fghij_b.txt", bytes("hi")).await.unwrap(), 2);
+
+        assert_eq!(read_to_end(&storage, "abcde_a.txt").await.unwrap(), b"hello");
+        assert_eq!(storage.stat("abcde_a.txt").await.unwrap().map(|object| object.size), Some(5));
+        // the upload directory is not listed
+        assert_eq!(storage.list().await.unwrap(), vec!["abcde_a.txt", "fghij_b.txt"]);
+
+        storage.delete("abcde_a.txt").await.unwrap();
+
+        assert_eq!(storage.stat("abcde_a.txt").await.unwrap(), None);
+        assert!(storage.get("abcde_a.txt").await.is_err());
+
+        let _ = std::fs::remove_dir_all(&storage.root);
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_put_file() {
+        let storage = storage("put_file");
+        let path = std::env::temp_dir().join("file2link_test_storage_put_file.txt");
+
+        fs::write(&path, "hello").await.unwrap();
+
+        assert_eq!(storage.put_file("abcde_a.txt", &path).await.unwrap(), 5);
+        assert!(!path.exists());
+        assert_eq!(read_to_end(&storage, "abcde_a.txt").await.unwrap(), b"hello");
+
+        let _ = std::fs::remove_dir_all(&storage.root);
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_blobs() {
+        let storage = storage("blobs");
+        let name = blob_name("abcdef");
+
+        assert_eq!(name, "ab/cd/abcdef");
+        assert_eq!(date_folder(1717495200), "2024/06/04");
+        assert_eq!(storage.put(&name, bytes("hello")).await.unwrap(), 5);
+        assert_eq!(read_to_end(&storage, &name).await.unwrap(), b"hello");
+        assert!(storage.root.join("ab").join("cd").join("abcdef").is_file());
+        // blobs are not link names
+        assert_eq!(storage.list().await.unwrap(), Vec::<String>::new());
+
+        storage.delete(&name).await.unwrap();
+
+        assert_eq!(storage.stat(&name).await.unwrap(), None);
+        assert!(!storage.root.join("ab").exists());
+        assert!(storage.root.exists());
+
+        let _ = std::fs::remove_dir_all(&storage.root);
+    }
+
+    #[tokio::test]
+    async fn test_read_part() {
+        let chunks = vec![Ok(Bytes::from(vec![1; 99])), Ok(Bytes::from(vec![2; 10])), Ok(Bytes::from(vec![3; 5]))];
+        let mut data: ByteStream = futures::stream::iter(chunks).boxed();
+
+        assert_eq!(read_part(&mut data, 100).await.unwrap().map(|part| part.len()), Some(109));
+        assert_eq!(read_part(&mut data, 100).await.unwrap().map(|part| part.len()), Some(5));
+        assert_eq!(read_part(&mut data, 100).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_refuses_paths() {
+        let storage = LocalStorage::default();
+
+        for name in ["", "..", ".tmp", "../config/metadata.json", "a/../b.txt", "a//b.txt", "a/", "a/.tmp", "a\\b.txt"] {
+            assert_eq!(storage.stat(name).await.unwrap_err().kind(), ErrorKind::InvalidInput, "{}", name);
+        }
+    }
+}
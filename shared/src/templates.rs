@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use log::{debug, error, info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::RwLock;
+
+const TEMPLATES_PATH: &str = "config/messages.json";
+
+/// Language of the bot replies, chosen per chat with /settings
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Ru,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::En, Language::Ru];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::En => "English",
+            Language::Ru => "Русский",
+        }
+    }
+
+    /// English templates are read from `config/messages.json`, others from `config/messages.<code>.json`
+    fn templates_path(&self) -> String {
+        match self {
+            Language::En => TEMPLATES_PATH.to_owned(),
+            Language::Ru => TEMPLATES_PATH.replace(".json", ".ru.json"),
+        }
+    }
+}
+
+/// Bot reply templates, placeholders are written as `{name}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MessageTemplates {
+    /// Placeholders: `{position}`
+    pub queue_position: String,
+    pub processing: String,
+    /// Sent with HTML parse mode. Placeholders: `{size}`, `{link}`, `{name}`, `{sha256}`
+    pub downloaded: String,
+    /// Appended to `downloaded` when the file passed the virus scan
+    pub scanned_clean: String,
+    /// Appended to `downloaded` when an archive sent with `--extract` was extracted. Placeholders: `{count}`, `{link}`
+    pub extracted: String,
+    /// Appended to `downloaded` when an archive sent with `--extract` could not be extracted. Placeholders: `{reason}`
+    pub not_extracted: String,
+    /// Reply to messages without a file or link, only in chats with hints enabled
+    pub unsupported: String,
+}
+
+impl Default for MessageTemplates {
+    fn default() -> Self {
+        Self {
+            queue_position: "Queue position: {position}".to_owned(),
+            processing: "Processing file...".to_owned(),
+            downloaded: "Downloaded. Size: {size} bytes\nSHA-256: <code>{sha256}</code>\n\n<b><a href=\"{link}\">{link}</a></b>".to_owned(),
+            scanned_clean: "Scanned: clean".to_owned(),
+            extracted: "Extracted {count} file(s): <a href=\"{link}\">contents</a>".to_owned(),
+            not_extracted: "Not extracted: {reason}".to_owned(),
+            unsupported: "Send me a file or use /url <link>".to_owned(),
+        }
+    }
+}
+
+static INSTANCES: Lazy<RwLock<HashMap<Language, Arc<MessageTemplates>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+impl MessageTemplates {
+    /// Built-in templates of the language
+    pub fn defaults(language: Language) -> Self {
+        match language {
+            Language::En => Self::default(),
+            Language::Ru => Self {
+                queue_position: "Позиция в очереди: {position}".to_owned(),
+                processing: "Обработка файла...".to_owned(),
+                downloaded: "Загружено. Размер: {size} байт\nSHA-256: <code>{sha256}</code>\n\n<b><a href=\"{link}\">{link}</a></b>".to_owned(),
+                scanned_clean: "Проверено антивирусом: угроз нет".to_owned(),
+                extracted: "Распаковано файлов: {count}, <a href=\"{link}\">содержимое</a>".to_owned(),
+                not_extracted: "Архив не распакован: {reason}".to_owned(),
+                unsupported: "Отправьте файл или используйте /url <ссылка>".to_owned(),
+            },
+        }
+    }
+
+    /// Templates of the default language
+    pub async fn instance() -> Arc<MessageTemplates> {
+        Self::for_language(Language::default()).await
+    }
+
+    pub async fn for_language(language: Language) -> Arc<MessageTemplates> {
+        let mut instances = INSTANCES.write().await;
+
+        if let Some(templates) = instances.get(&language) {
+            return templates.clone();
+        }
+
+        let templates = load_templates(language).await.unwrap_or_else(|e| {
+            warn!("Failed to load message templates, using defaults. Error: {}", e);
+
+            MessageTemplates::defaults(language)
+        });
+
+        let templates = Arc::new(templates);
+
+        instances.insert(language, templates.clone());
+
+        templates
+    }
+}
+
+/// Replaces `{name}` placeholders with the given values
+///
+/// # Arguments
+/// * `template` - Template text
+/// * `values` - Pairs of placeholder name and value
+pub fn render(template: &str, values: &[(&str, String)]) -> String {
+    values.iter().fold(template.to_owned(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+pub async fn load_templates(language: Language) -> Result<MessageTemplates, Box<dyn Error>> {
+    let path = language.templates_path();
+
+    let data = match fs::read_to_string(&path).await {
+        Ok(data) => data,
+        Err(_) => {
+            debug!("Templates file '{}' not found, using default messages", path);
+
+            return Ok(MessageTemplates::defaults(language));
+        }
+    };
+
+    match parse_templates(&data, language) {
+        Ok(templates) => {
+            info!("Loaded message templates from '{}'", path);
+
+            Ok(templates)
+        }
+        Err(e) => {
+            error!("Failed to parse message templates: {}", e);
+
+            Err("Failed to parse message templates".into())
+        }
+    }
+}
+
+/// Parses the templates, missing fields keep the built-in templates of the language
+fn parse_templates(data: &str, language: Language) -> Result<MessageTemplates, serde_json::Error> {
+    let mut templates = serde_json::to_value(MessageTemplates::defaults(language))?;
+
+    if let (Some(templates), serde_json::Value::Object(overrides)) = (templates.as_object_mut(), serde_json::from_str(data)?) {
+        templates.extend(overrides);
+    }
+
+    serde_json::from_value(templates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render() {
+        let text = render("Queue position: {position} of {position}", &[("position", "3".to_owned())]);
+
+        assert_eq!(text, "Queue position: 3 of 3");
+    }
+
+    #[tokio::test]
+    async fn test_render_unknown_placeholder() {
+        let text = render("{size} bytes, {unknown}", &[("size", "10".to_owned())]);
+
+        assert_eq!(text, "10 bytes, {unknown}");
+    }
+
+    #[tokio::test]
+    async fn test_partial_templates_use_defaults() {
+        let templates: MessageTemplates = serde_json::from_str(r#"{"processing": "Working on it..."}"#).unwrap();
+
+        assert_eq!(templates.processing, "Working on it...");
+        assert_eq!(templates.queue_position, MessageTemplates::default().queue_position);
+        assert_eq!(templates.downloaded, MessageTemplates::default().downloaded);
+    }
+
+    #[tokio::test]
+    async fn test_partial_templates_use_language_defaults() {
+        let templates = parse_templates(r#"{"processing": "Секунду..."}"#, Language::Ru).unwrap();
+
+        assert_eq!(templates.processing, "Секунду...");
+        assert_eq!(templates.queue_position, MessageTemplates::defaults(Language::Ru).queue_position);
+    }
+
+    #[tokio::test]
+    async fn test_templates_path() {
+        assert_eq!(Language::En.templates_path(), "config/messages.json");
+        assert_eq!(Language::Ru.templates_path(), "config/messages.ru.json");
+    }
+}
@@ -0,0 +1,221 @@
+use std::io;
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{debug, info, warn};
+
+use crate::migrate::migrate_file;
+use crate::storage::{ByteStream, Storage, StorageBackend, StoredObject};
+
+/// Keeps the files not downloaded for `COLD_STORAGE_AFTER` in a second, cheaper storage, see `COLD_STORAGE_BACKEND`
+///
+/// Files are read from whichever storage has them and moved back with `restore` when they are downloaded. New files
+/// are stored in the primary storage, `move_to_cold` moves the idle ones.
+pub struct TieredStorage {
+    primary: Storage,
+    cold: Storage,
+}
+
+impl TieredStorage {
+    pub fn new(primary: Storage, cold: Storage) -> Self {
+        Self { primary, cold }
+    }
+
+    /// Get the storage which has the file, the primary storage if neither has it
+    async fn tier(&self, name: &str) -> io::Result<&Storage> {
+        if self.primary.stat(name).await?.is_none() && self.cold.stat(name).await?.is_some() {
+            return Ok(&self.cold);
+        }
+
+        Ok(&self.primary)
+    }
+
+    /// Removes the copy in the cold storage of a file which was stored again, it would be outdated
+    async fn remove_cold_copy(&self, name: &str) {
+        let result = match self.cold.stat(name).await {
+            Ok(Some(_)) => self.cold.delete(name).await,
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            warn!("Failed to remove the copy of '{}' in the cold storage: {}", name, e);
+        }
+    }
+}
+
+impl StorageBackend for TieredStorage {
+    fn put<'a>(&'a self, name: &'a str, data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            let size = self.primary.put(name, data).await?;
+
+            self.remove_cold_copy(name).await;
+
+            Ok(size)
+        }.boxed()
+    }
+
+    fn put_file<'a>(&'a self, name: &'a str, path: &'a Path) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            let size = self.primary.put_file(name, path).await?;
+
+            self.remove_cold_copy(name).await;
+
+            Ok(size)
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<ByteStream>> {
+        async move { self.tier(name).await?.get(name).await }.boxed()
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move { self.tier(name).await?.delete(name).await }.boxed()
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        async move {
+            let mut names = self.primary.list().await?;
+
+            names.extend(self.cold.list().await?);
+            names.sort();
+            names.dedup();
+
+            Ok(names)
+        }.boxed()
+    }
+
+    fn stat<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<StoredObject>>> {
+        async move {
+            match self.primary.stat(name).await? {
+                Some(stored) => Ok(Some(stored)),
+                None => self.cold.stat(name).await,
+            }
+        }.boxed()
+    }
+
+    // files are restored before they are served, so they are in the primary storage
+    fn redirect_url(&self, name: &str, content_disposition: &str) -> Option<String> {
+        self.primary.redirect_url(name, content_disposition)
+    }
+
+    fn restore<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            if self.primary.stat(name).await?.is_some() || self.cold.stat(name).await?.is_none() {
+                return Ok(());
+            }
+
+            info!("Restoring '{}' from the cold storage", name);
+
+            move_file(&self.cold, &self.primary, name).await.map_err(io::Error::other)
+        }.boxed()
+    }
+}
+
+/// Moves the files from the primary storage to the cold storage, both as they are stored
+/// Files no longer in the primary storage are skipped, like files moved before
+///
+/// # Returns
+/// * The number of moved files and their stored bytes, files which could not be moved are logged and stay
+pub async fn move_to_cold(primary: &Storage, cold: &Storage, names: &[String]) -> (usize, u64) {
+    let (mut moved, mut moved_bytes) = (0, 0);
+
+    for name in names {
+        let size = match primary.stat(name).await {
+            Ok(Some(stored)) => stored.size,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Failed to look up '{}': {}", name, e);
+
+                continue;
+            }
+        };
+
+        match move_file(primary, cold, name).await {
+            Ok(_) => {
+                debug!("Moved '{}' to the cold storage", name);
+
+                moved += 1;
+                moved_bytes += size;
+            }
+            Err(e) => warn!("Failed to move '{}' to the cold storage: {}", name, e),
+        }
+    }
+
+    (moved, moved_bytes)
+}
+
+/// Copies the file to the other storage and removes it once the copy is read back with the same hash
+async fn move_file(from: &Storage, to: &Storage, name: &str) -> Result<(), String> {
+    migrate_file(from, to, name).await?;
+
+    from.delete(name).await.map_err(|e| format!("failed to remove the file: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::{stream, StreamExt, TryStreamExt};
+    use tokio::fs;
+    use tokio_util::bytes::Bytes;
+
+    use crate::storage::LocalStorage;
+
+    use super::*;
+
+    fn bytes(data: &'static str) -> ByteStream {
+        stream::once(async move { Ok(Bytes::from(data)) }).boxed()
+    }
+
+    async fn read(storage: &dyn StorageBackend, name: &str) -> Vec<u8> {
+        storage.get(name).await.unwrap()
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tiered_storage() {
+        let root = std::env::temp_dir().join("file2link_test_tiering");
+        let _ = fs::remove_dir_all(&root).await;
+
+        let primary: Storage = Arc::new(LocalStorage::new(root.join("primary")));
+        let cold: Storage = Arc::new(LocalStorage::new(root.join("cold")));
+        let storage = TieredStorage::new(primary.clone(), cold.clone());
+
+        storage.put("abcde_report.pdf", bytes("report")).await.unwrap();
+        storage.put("fghij_photo.jpg", bytes("photo")).await.unwrap();
+
+        let names = vec!["abcde_report.pdf".to_owned(), "missing.txt".to_owned()];
+
+        assert_eq!(move_to_cold(&primary, &cold, &names).await, (1, 6));
+        assert!(primary.stat("abcde_report.pdf").await.unwrap().is_none());
+
+        // moved files are still listed and read
+        assert_eq!(storage.list().await.unwrap(), vec!["abcde_report.pdf", "fghij_photo.jpg"]);
+        assert_eq!(storage.stat("abcde_report.pdf").await.unwrap().unwrap().size, 6);
+        assert_eq!(read(&storage, "abcde_report.pdf").await, b"report");
+
+        storage.restore("abcde_report.pdf").await.unwrap();
+
+        assert_eq!(read(primary.as_ref(), "abcde_report.pdf").await, b"report");
+        assert!(cold.stat("abcde_report.pdf").await.unwrap().is_none());
+
+        // a file stored again replaces the copy in the cold storage
+        move_to_cold(&primary, &cold, &names).await;
+        storage.put("abcde_report.pdf", bytes("report v2")).await.unwrap();
+
+        assert!(cold.stat("abcde_report.pdf").await.unwrap().is_none());
+        assert_eq!(read(&storage, "abcde_report.pdf").await, b"report v2");
+
+        move_to_cold(&primary, &cold, &names).await;
+        storage.delete("abcde_report.pdf").await.unwrap();
+
+        assert!(storage.stat("abcde_report.pdf").await.unwrap().is_none());
+
+        let _ = fs::remove_dir_all(&root).await;
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+const USAGE_PATH: &str = "config/usage.json";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Window of the per user submission rate limit
+const RATE_WINDOW: u64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct UserUsage {
+    /// Days since the unix epoch (UTC) the counters belong to
+    day: u64,
+    files: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaLimits {
+    pub files_per_day: Option<u64>,
+    pub bytes_per_day: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaExceeded {
+    /// Files stored today
+    pub files: u64,
+    /// Files sent but not stored yet, the ones waiting in the queue and the ones of the refused message
+    pub pending: u64,
+    pub bytes: u64,
+    pub limits: QuotaLimits,
+    /// Seconds until the quota resets at midnight UTC
+    pub resets_in: u64,
+}
+
+impl QuotaExceeded {
+    pub fn message(&self) -> String {
+        let mut message = String::from("Daily quota exceeded.\n");
+
+        if let Some(limit) = self.limits.files_per_day {
+            match self.pending {
+                0 => message.push_str(&format!("Files: {} of {}\n", self.files, limit)),
+                pending => message.push_str(&format!("Files: {} of {}, {} more sent\n", self.files, limit, pending)),
+            }
+        }
+
+        if let Some(limit) = self.limits.bytes_per_day {
+            message.push_str(&format!("Size: {} of {} bytes\n", self.bytes, limit));
+        }
+
+        message.push_str(&format!(
+            "Quota resets in {}h {}m (00:00 UTC)",
+            self.resets_in / 3600,
+            self.resets_in % 3600 / 60,
+        ));
+
+        message
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    pub limit: u64,
+    /// Seconds until the user can submit again
+    pub retry_in: u64,
+}
+
+impl RateLimited {
+    pub fn message(&self) -> String {
+        format!(
+            "Too many files, at most {} per minute are accepted. Try again in {}s.",
+            self.limit,
+            self.retry_in,
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct UsageTracker {
+    users: HashMap<String, UserUsage>,
+    /// Submission times within the rate limit window, kept in memory only
+    #[serde(skip)]
+    submissions: HashMap<String, VecDeque<u64>>,
+    /// Files accepted but not queued yet, see `reserve`, kept in memory only
+    #[serde(skip)]
+    reserved: HashMap<String, u64>,
+}
+
+pub fn now_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl UsageTracker {
+    fn usage_for_day(&self, user_id: &str, day: u64) -> UserUsage {
+        match self.users.get(user_id) {
+            Some(usage) if usage.day == day => usage.clone(),
+            _ => UserUsage { day, ..Default::default() },
+        }
+    }
+
+    /// Checks whether the user can submit the files today
+    /// Files are only recorded once stored, so the ones still in the queue and the reserved ones count too
+    ///
+    /// # Arguments
+    /// * `user_id` - Telegram user ID
+    /// * `files` - Number of files sent now together with the files of the user waiting in the queue
+    /// * `limits` - Configured daily limits
+    /// * `now` - Current unix time in seconds
+    pub fn check(&self, user_id: &str, files: u64, limits: &QuotaLimits, now: u64) -> Result<(), QuotaExceeded> {
+        let usage = self.usage_for_day(user_id, now / SECONDS_PER_DAY);
+        let pending = files + self.reserved.get(user_id).copied().unwrap_or_default();
+
+        let files_exceeded = limits.files_per_day.is_some_and(|limit| usage.files + pending > limit);
+        // the size of the files is not known before they are downloaded
+        let bytes_exceeded = limits.bytes_per_day.is_some_and(|limit| usage.bytes >= limit);
+
+        if files_exceeded || bytes_exceeded {
+            debug!("User '{}' exceeded daily quota: {:?}, {} file(s) pending", user_id, usage, pending);
+
+            return Err(QuotaExceeded {
+                files: usage.files,
+                pending,
+                bytes: usage.bytes,
+                limits: limits.clone(),
+                resets_in: SECONDS_PER_DAY - now % SECONDS_PER_DAY,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Accepts `count` new submissions if the user stays within `limit` submissions per minute
+    ///
+    /// # Arguments
+    /// * `user_id` - Telegram user ID
+    /// * `count` - Number of files in the submission
+    /// * `limit` - Configured submissions per minute
+    /// * `now` - Current unix time in seconds
+    pub fn submit(&mut self, user_id: &str, count: u64, limit: u64, now: u64) -> Result<(), RateLimited> {
+        let submissions = self.submissions.entry(user_id.to_owned()).or_default();
+
+        while submissions.front().is_some_and(|time| time + RATE_WINDOW <= now) {
+            submissions.pop_front();
+        }
+
+        if submissions.len() as u64 + count > limit {
+            debug!("User '{}' exceeded the rate limit", user_id);
+
+            let retry_in = submissions.front()
+                .map(|time| time + RATE_WINDOW - now)
+                .unwrap_or(RATE_WINDOW);
+
+            return Err(RateLimited { limit, retry_in });
+        }
+
+        submissions.extend(std::iter::repeat_n(now, count as usize));
+
+        Ok(())
+    }
+
+    /// Counts files accepted by `check` until they are queued, after that the queue counts them, see `release`
+    pub fn reserve(&mut self, user_id: &str, files: u64) {
+        *self.reserved.entry(user_id.to_owned()).or_default() += files;
+    }
+
+    /// Gives back files of `reserve`, once they are queued or refused
+    pub fn release(&mut self, user_id: &str, files: u64) {
+        if let Some(reserved) = self.reserved.get_mut(user_id) {
+            *reserved = reserved.saturating_sub(files);
+
+            if *reserved == 0 {
+                self.reserved.remove(user_id);
+            }
+        }
+    }
+
+    /// Records a successfully stored file for the user
+    pub fn record(&mut self, user_id: &str, bytes: u64, now: u64) {
+        let mut usage = self.usage_for_day(user_id, now / SECONDS_PER_DAY);
+
+        usage.files += 1;
+        usage.bytes += bytes;
+
+        debug!("Recorded usage for user '{}': {:?}", user_id, usage);
+
+        self.users.insert(user_id.to_owned(), usage);
+    }
+}
+
+pub async fn load_usage() -> Result<UsageTracker, Box<dyn Error>> {
+    let data = match fs::read_to_string(USAGE_PATH).await {
+        Ok(data) => data,
+        Err(_) => {
+            debug!("Usage file '{}' not found, starting with empty usage", USAGE_PATH);
+
+            return Ok(UsageTracker::default());
+        }
+    };
+
+    match serde_json::from_str(&data) {
+        Ok(usage) => Ok(usage),
+        Err(e) => {
+            error!("Failed to parse usage: {}", e);
+
+            Err("Failed to parse usage".into())
+        }
+    }
+}
+
+pub async fn save_usage(usage: &UsageTracker) -> Result<(), Box<dyn Error>> {
+    if let Some((dir_path, _)) = USAGE_PATH.rsplit_once('/') {
+        fs::create_dir_all(dir_path).await?;
+    }
+
+    let data = serde_json::to_string_pretty(usage)?;
+    fs::write(USAGE_PATH, data).await?;
+
+    debug!("Usage saved to '{}'", USAGE_PATH);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+
+    fn limits(files: Option<u64>, bytes: Option<u64>) -> QuotaLimits {
+        QuotaLimits { files_per_day: files, bytes_per_day: bytes }
+    }
+
+    #[tokio::test]
+    async fn test_check_without_limits() {
+        let mut usage = UsageTracker::default();
+
+        usage.record("user1", 1000, 10 * DAY);
+
+        assert!(usage.check("user1", 1, &limits(None, None), 10 * DAY).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_check_files_limit() {
+        let mut usage = UsageTracker::default();
+
+        usage.record("user1", 10, 10 * DAY);
+        assert!(usage.check("user1", 1, &limits(Some(2), None), 10 * DAY).is_ok());
+
+        usage.record("user1", 10, 10 * DAY);
+        let exceeded = usage.check("user1", 1, &limits(Some(2), None), 10 * DAY + 3600).unwrap_err();
+
+        assert_eq!(exceeded.files, 2);
+        assert_eq!(exceeded.pending, 1);
+        assert_eq!(exceeded.bytes, 20);
+        assert_eq!(exceeded.resets_in, DAY - 3600);
+    }
+
+    #[tokio::test]
+    async fn test_check_bytes_limit() {
+        let mut usage = UsageTracker::default();
+
+        usage.record("user1", 500, 10 * DAY);
+
+        assert!(usage.check("user1", 1, &limits(None, Some(500)), 10 * DAY).is_err());
+        assert!(usage.check("user2", 1, &limits(None, Some(500)), 10 * DAY).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_check_counts_pending_files() {
+        let mut usage = UsageTracker::default();
+
+        usage.record("user1", 10, 10 * DAY);
+
+        // the whole message is counted, not only the files stored before
+        assert!(usage.check("user1", 9, &limits(Some(10), None), 10 * DAY).is_ok());
+        assert!(usage.check("user1", 10, &limits(Some(10), None), 10 * DAY).is_err());
+
+        usage.reserve("user1", 5);
+
+        assert!(usage.check("user1", 4, &limits(Some(10), None), 10 * DAY).is_ok());
+        assert_eq!(usage.check("user1", 5, &limits(Some(10), None), 10 * DAY).unwrap_err().pending, 10);
+
+        usage.release("user1", 5);
+
+        assert!(usage.check("user1", 9, &limits(Some(10), None), 10 * DAY).is_ok());
+        assert!(usage.reserved.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_usage_resets_next_day() {
+        let mut usage = UsageTracker::default();
+
+        usage.record("user1", 500, 10 * DAY);
+
+        assert!(usage.check("user1", 1, &limits(Some(1), None), 10 * DAY).is_err());
+        assert!(usage.check("user1", 1, &limits(Some(1), None), 11 * DAY).is_ok());
+
+        usage.record("user1", 100, 11 * DAY);
+
+        assert_eq!(usage.users["user1"], UserUsage { day: 11, files: 1, bytes: 100 });
+    }
+
+    #[tokio::test]
+    async fn test_submit_rate_limit() {
+        let mut usage = UsageTracker::default();
+
+        assert!(usage.submit("user1", 2, 3, 100).is_ok());
+        assert!(usage.submit("user1", 1, 3, 120).is_ok());
+        assert_eq!(usage.submit("user1", 1, 3, 130), Err(RateLimited { limit: 3, retry_in: 30 }));
+        assert!(usage.submit("user2", 1, 3, 130).is_ok());
+
+        assert!(usage.submit("user1", 2, 3, 160).is_ok());
+        assert!(usage.submit("user1", 1, 3, 170).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submit_larger_than_limit() {
+        let mut usage = UsageTracker::default();
+
+        assert_eq!(usage.submit("user1", 4, 3, 100), Err(RateLimited { limit: 3, retry_in: RATE_WINDOW }));
+    }
+
+    #[tokio::test]
+    async fn test_submissions_are_not_saved() {
+        let mut usage = UsageTracker::default();
+
+        usage.submit("user1", 1, 3, 100).unwrap();
+
+        assert!(!serde_json::to_string(&usage).unwrap().contains("submissions"));
+    }
+
+    #[tokio::test]
+    async fn test_quota_exceeded_message() {
+        let exceeded = QuotaExceeded {
+            files: 5,
+            pending: 0,
+            bytes: 2048,
+            limits: limits(Some(5), Some(4096)),
+            resets_in: 2 * 3600 + 30 * 60,
+        };
+
+        assert_eq!(
+            exceeded.message(),
+            "Daily quota exceeded.\nFiles: 5 of 5\nSize: 2048 of 4096 bytes\nQuota resets in 2h 30m (00:00 UTC)"
+        );
+    }
+}
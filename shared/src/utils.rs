@@ -60,4 +60,205 @@ pub async fn create_fifo(path: &str) -> Result<(), String> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Parses a duration like "30m", "24h" or "7d" into seconds
+///
+/// Supported units are `s`, `m`, `h`, `d` and `w`
+pub fn parse_duration(text: &str) -> Option<u64> {
+    let text = text.trim().to_lowercase();
+    let unit = text.chars().last()?;
+    let value: u64 = text[..text.len() - unit.len_utf8()].parse().ok()?;
+
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    value.checked_mul(multiplier).filter(|seconds| *seconds > 0)
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Parses a time of day like "03:00" or "23:30" into seconds after midnight
+pub fn parse_time_of_day(text: &str) -> Option<u64> {
+    let (hours, minutes) = text.trim().split_once(':')?;
+    let hours: u64 = hours.parse().ok().filter(|hours| *hours < 24)?;
+    let minutes: u64 = minutes.parse().ok().filter(|minutes| *minutes < 60)?;
+
+    Some(hours * 60 * 60 + minutes * 60)
+}
+
+/// Get the Unix time of the next occurrence of the time of day (UTC) after `now`
+pub fn next_time_of_day(now: u64, time_of_day: u64) -> u64 {
+    let time = now - now % DAY + time_of_day;
+
+    if time > now { time } else { time + DAY }
+}
+
+/// Formats a time of day given in seconds after midnight like "03:00"
+pub fn format_time_of_day(time_of_day: u64) -> String {
+    format!("{:02}:{:02}", time_of_day / (60 * 60) % 24, time_of_day / 60 % 60)
+}
+
+/// Formats seconds like "2d 3h 4m", seconds are only shown for durations under a minute
+pub fn format_duration(seconds: u64) -> String {
+    if seconds < 60 {
+        return format!("{}s", seconds);
+    }
+
+    let parts = [
+        (seconds / (24 * 60 * 60), "d"),
+        (seconds / (60 * 60) % 24, "h"),
+        (seconds / 60 % 60, "m"),
+    ];
+
+    parts.iter()
+        .skip_while(|(value, _)| *value == 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Formats a size in bytes like "1.5 GB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Get the space available to unprivileged users on the filesystem containing the path
+pub fn get_free_space(path: &str) -> io::Result<u64> {
+    let c_path = std::ffi::CString::new(path)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Reserves disk space for the whole file without changing its size, so it is not fragmented while it is written and
+/// a full disk is noticed before anything is written
+///
+/// # Returns
+/// * `Result` containing `false` if the filesystem can not reserve space, the file is written as usual then
+/// * `Err` of the kind `StorageFull` if the disk is too small
+#[cfg(target_os = "linux")]
+pub fn preallocate(file: &std::fs::File, size: u64) -> io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    let size = libc::off_t::try_from(size).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    if unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, size) } == 0 {
+        return Ok(true);
+    }
+
+    let e = io::Error::last_os_error();
+
+    match e.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => Ok(false),
+        _ => Err(e),
+    }
+}
+
+/// Reserving space without changing the size is only supported on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate(_file: &std::fs::File, _size: u64) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Some(30));
+        assert_eq!(parse_duration("30m"), Some(30 * 60));
+        assert_eq!(parse_duration("24h"), Some(24 * 60 * 60));
+        assert_eq!(parse_duration("7D"), Some(7 * 24 * 60 * 60));
+        assert_eq!(parse_duration("2w"), Some(14 * 24 * 60 * 60));
+    }
+
+    #[tokio::test]
+    async fn test_parse_duration_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("0h"), None);
+        assert_eq!(parse_duration("24"), None);
+        assert_eq!(parse_duration("24y"), None);
+        assert_eq!(parse_duration("-1h"), None);
+    }
+
+    #[tokio::test]
+    async fn test_parse_time_of_day() {
+        assert_eq!(parse_time_of_day("03:00"), Some(3 * 60 * 60));
+        assert_eq!(parse_time_of_day("23:59"), Some(23 * 60 * 60 + 59 * 60));
+        assert_eq!(parse_time_of_day("24:00"), None);
+        assert_eq!(parse_time_of_day("12:60"), None);
+        assert_eq!(parse_time_of_day("3"), None);
+    }
+
+    #[tokio::test]
+    async fn test_next_time_of_day() {
+        // 2023-02-01 05:25:40 UTC
+        let now = 1675229140;
+        let midnight = now - now % DAY;
+
+        assert_eq!(next_time_of_day(now, 6 * 60 * 60), midnight + 6 * 60 * 60);
+        assert_eq!(next_time_of_day(now, 3 * 60 * 60), midnight + DAY + 3 * 60 * 60);
+        assert_eq!(format_time_of_day(3 * 60 * 60 + 5 * 60), "03:05");
+    }
+
+    #[tokio::test]
+    async fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(60 * 60 + 5), "1h 0m");
+        assert_eq!(format_duration(2 * 24 * 60 * 60 + 3 * 60 * 60 + 4 * 60), "2d 3h 4m");
+    }
+
+    #[tokio::test]
+    async fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(20 * 1024 * 1024), "20.0 MB");
+    }
+
+    #[tokio::test]
+    async fn test_get_free_space() {
+        assert!(get_free_space(".").is_ok());
+        assert!(get_free_space("/nonexistent/path").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_preallocate() {
+        let path = std::env::temp_dir().join("file2link_test_preallocate");
+        let file = std::fs::File::create(&path).unwrap();
+
+        assert!(preallocate(&file, 1024 * 1024).is_ok());
+        assert_eq!(file.metadata().unwrap().len(), 0);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,147 @@
+use std::io;
+
+use log::info;
+
+use crate::metadata::{FileMetadata, FileVersion};
+use crate::storage::StorageBackend;
+
+/// Folder the content of a replaced file is copied to when the new file is stored under the same storage name
+pub const VERSIONS_FOLDER: &str = "versions";
+
+/// Keeps the file replaced by a new file of the same name as a version of the new file, see `FILE_VERSIONS`
+/// The content is copied to `VERSIONS_FOLDER` when the new file would overwrite it. Content addressed files and files
+/// in another date folder keep their content where it is.
+///
+/// Must be called before the content of the new file is stored.
+///
+/// # Arguments
+/// * `retained` - How many versions are kept, zero keeps none and the replaced file is overwritten
+///
+/// # Returns
+/// * `Result` containing the versions which no longer fit, the caller removes their content unless it is still linked
+pub async fn keep_version(
+    storage: &dyn StorageBackend,
+    file: &mut FileMetadata,
+    replaced: FileMetadata,
+    retained: usize,
+) -> io::Result<Vec<FileVersion>> {
+    let mut versions = replaced.versions.clone();
+
+    if retained == 0 {
+        return Ok(versions);
+    }
+
+    let version = versions.last().map_or(1, |last| last.version + 1);
+    let replaced_name = replaced.storage_name();
+
+    let storage_name = match replaced_name == file.storage_name() && !replaced.content_addressed {
+        true => {
+            let storage_name = format!("{}/{}.v{}", VERSIONS_FOLDER, replaced_name, version);
+
+            storage.put(&storage_name, storage.get(&replaced_name).await?).await?;
+
+            storage_name
+        }
+        false => replaced_name,
+    };
+
+    info!("Keeping '{}' as version {} stored as '{}'", replaced.file_name, version, storage_name);
+
+    versions.push(FileVersion {
+        version,
+        storage_name,
+        size: replaced.size,
+        sha256: replaced.sha256,
+        created_at: replaced.created_at,
+        owners: replaced.owners,
+        private: replaced.private,
+    });
+
+    let dropped = versions.len().saturating_sub(retained);
+
+    file.versions = versions.split_off(dropped);
+
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use tokio::fs;
+
+    use crate::storage::LocalStorage;
+
+    use super::*;
+
+    fn file(name: &str, size: u64) -> FileMetadata {
+        FileMetadata {
+            file_name: name.to_owned(),
+            size,
+            created_at: size,
+            owners: vec![size.to_string()],
+            ..Default::default()
+        }
+    }
+
+    async fn read(storage: &LocalStorage, name: &str) -> Vec<u8> {
+        storage.get(name).await.unwrap()
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_keep_version() {
+        let root = std::env::temp_dir().join("file2link_test_versions");
+        let _ = fs::remove_dir_all(&root).await;
+
+        fs::create_dir_all(&root).await.unwrap();
+
+        let storage = LocalStorage::new(&root);
+        let mut current = file("abc_report.pdf", 1);
+
+        fs::write(root.join("abc_report.pdf"), b"first").await.unwrap();
+
+        for size in 2..=4 {
+            let mut new = file("abc_report.pdf", size);
+
+            let dropped = keep_version(&storage, &mut new, current, 2).await.unwrap();
+
+            // the new content overwrites the old one
+            fs::write(root.join("abc_report.pdf"), format!("v{}", size)).await.unwrap();
+
+            assert_eq!(dropped.len(), usize::from(size == 4));
+
+            current = new;
+        }
+
+        let versions: Vec<u32> = current.versions.iter().map(|version| version.version).collect();
+
+        assert_eq!(versions, vec![2, 3]);
+        assert_eq!(current.version(2).unwrap().owners, vec!["2"]);
+        assert_eq!(current.version(3).unwrap().storage_name, "versions/abc_report.pdf.v3");
+        assert_eq!(read(&storage, "versions/abc_report.pdf.v2").await, b"v2");
+        assert_eq!(read(&storage, "versions/abc_report.pdf.v3").await, b"v3");
+        assert_eq!(read(&storage, "abc_report.pdf").await, b"v4");
+        assert!(current.version(1).is_none());
+
+        // a file in another date folder is not overwritten, its content stays where it is
+        let mut new = file("abc_report.pdf", 5);
+        new.folder = Some("2024/06/04".to_owned());
+
+        keep_version(&storage, &mut new, current.clone(), 2).await.unwrap();
+
+        assert_eq!(new.version(4).unwrap().storage_name, "abc_report.pdf");
+
+        // without versions the earlier ones are dropped
+        let mut new = file("abc_report.pdf", 5);
+
+        let dropped = keep_version(&storage, &mut new, current, 0).await.unwrap();
+
+        assert_eq!(dropped.len(), 2);
+        assert!(new.versions.is_empty());
+
+        let _ = fs::remove_dir_all(&root).await;
+    }
+}
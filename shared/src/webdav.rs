@@ -0,0 +1,458 @@
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::DateTime;
+use futures::future::BoxFuture;
+use futures::{FutureExt, Stream, StreamExt, TryStreamExt};
+use log::{debug, info, warn};
+use reqwest::{Body, Method, RequestBuilder, Response, StatusCode};
+use tokio_util::bytes::Bytes;
+
+use crate::config::WebDavConfig;
+use crate::s3::{unescape_xml, uri_encode};
+use crate::storage::{check_name, read_part, ByteStream, StorageBackend, StoredObject};
+
+/// Files bigger than this are uploaded to Nextcloud in chunks of this size, see `WebDavStorage::put_chunked`
+const CHUNK_SIZE: usize = 10 * 1024 * 1024;
+
+/// Properties asked for with PROPFIND
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getcontentlength/><d:getlastmodified/></d:prop></d:propfind>"#;
+
+/// Makes the names of chunked upload folders unique within the process
+static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Stores the files in a WebDAV folder, like a Nextcloud or ownCloud folder
+pub struct WebDavStorage {
+    config: WebDavConfig,
+    /// Folder of the Nextcloud chunked uploads of the user, `None` if files are uploaded with one request
+    uploads_url: Option<String>,
+    client: reqwest::Client,
+}
+
+/// A file or folder in a PROPFIND answer
+#[derive(Debug, Clone, PartialEq)]
+struct DavEntry {
+    name: String,
+    collection: bool,
+    size: u64,
+    modified_at: Option<u64>,
+}
+
+impl WebDavStorage {
+    pub fn new(config: WebDavConfig) -> Self {
+        let uploads_url = match config.chunking {
+            true => nextcloud_uploads_url(&config.url),
+            false => None,
+        };
+
+        info!("Storing files in the WebDAV folder {}", config.url);
+
+        if let Some(uploads_url) = &uploads_url {
+            debug!("Uploading big files in chunks to {}", uploads_url);
+        }
+
+        Self { config, uploads_url, client: reqwest::Client::new() }
+    }
+
+    fn url(&self, name: &str) -> String {
+        let path = name.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+
+        format!("{}{}", self.config.url, path)
+    }
+
+    /// Creates the folders of a name like "ab/cd/abcd...", WebDAV servers do not create them on upload
+    async fn create_folders(&self, name: &str) -> io::Result<()> {
+        let folders: Vec<&str> = name.split('/').collect();
+
+        for depth in 1..folders.len() {
+            let url = format!("{}/", self.url(&folders[..depth].join("/")));
+            let response = self.request("MKCOL", &url)?.send().await.map_err(io::Error::other)?;
+
+            // 405 Method Not Allowed means the folder exists
+            if !response.status().is_success() && response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                return Err(io::Error::other(format!("Failed to create the folder {}: {}", url, response.status())));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request(&self, method: &str, url: &str) -> io::Result<RequestBuilder> {
+        let method = Method::from_bytes(method.as_bytes()).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+
+        Ok(self.client.request(method, url).basic_auth(&self.config.username, Some(&self.config.password)))
+    }
+
+    async fn send(&self, request: RequestBuilder) -> io::Result<Response> {
+        let response = request.send().await.map_err(io::Error::other)?;
+
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status = response.status();
+
+        let kind = match status {
+            StatusCode::NOT_FOUND => ErrorKind::NotFound,
+            StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => ErrorKind::PermissionDenied,
+            StatusCode::INSUFFICIENT_STORAGE => ErrorKind::StorageFull,
+            _ => ErrorKind::Other,
+        };
+
+        Err(io::Error::new(kind, format!("WebDAV server answered {}", status)))
+    }
+
+    async fn propfind(&self, url: &str, depth: &str) -> io::Result<Vec<DavEntry>> {
+        let request = self.request("PROPFIND", url)?
+            .header("Depth", depth)
+            .header(reqwest::header::CONTENT_TYPE, "application/xml")
+            .body(PROPFIND_BODY);
+
+        let response = self.send(request).await?;
+        let body = response.text().await.map_err(io::Error::other)?;
+
+        Ok(dav_entries(&body))
+    }
+
+    /// Uploads the file with the Nextcloud chunked upload, so no request is bigger than `CHUNK_SIZE`
+    /// The chunks are put into an upload folder and joined by moving the folder to the file.
+    async fn put_chunked(&self, uploads_url: &str, name: &str, first_chunk: Bytes, data: ByteStream) -> io::Result<u64> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let upload_url = format!("{}/file2link-{}-{}", uploads_url, nanos, UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let destination = self.url(name);
+
+        self.send(self.request("MKCOL", &upload_url)?.header("Destination", &destination)).await?;
+
+        debug!("Uploading {} in chunks to {}", name, upload_url);
+
+        match self.upload_chunks(&upload_url, &destination, first_chunk, data).await {
+            Ok(size) => Ok(size),
+            Err(e) => {
+                // chunks of an unfinished upload are kept until Nextcloud cleans them up after days
+                let remove = self.request("DELETE", &upload_url);
+
+                if let Err(e) = async { self.send(remove?).await }.await {
+                    warn!("Failed to remove the chunks of {}: {}", name, e);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    async fn upload_chunks(&self, upload_url: &str, destination: &str, first_chunk: Bytes, mut data: ByteStream) -> io::Result<u64> {
+        let mut size = 0;
+        let mut chunk_number = 1;
+        let mut chunk = Some(first_chunk);
+
+        while let Some(body) = chunk {
+            size += body.len() as u64;
+
+            let request = self.request("PUT", &format!("{}/{:05}", upload_url, chunk_number))?
+                .header("Destination", destination)
+                .header(reqwest::header::CONTENT_LENGTH, body.len())
+                .body(body);
+
+            self.send(request).await?;
+
+            chunk_number += 1;
+            chunk = read_part(&mut data, CHUNK_SIZE).await?;
+        }
+
+        let request = self.request("MOVE", &format!("{}/.file", upload_url))?
+            .header("Destination", destination)
+            .header("Overwrite", "T")
+            .header("OC-Total-Length", size);
+
+        self.send(request).await?;
+
+        Ok(size)
+    }
+
+    /// Uploads the file with one request, the body is streamed
+    async fn put_streamed(&self, name: &str, data: ByteStream) -> io::Result<u64> {
+        let size = Arc::new(AtomicU64::new(0));
+
+        let counted = {
+            let size = size.clone();
+
+            data.inspect_ok(move |chunk| {
+                size.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }).boxed()
+        };
+
+        let request = self.request("PUT", &self.url(name))?
+            .body(Body::wrap_stream(SyncStream(Mutex::new(counted))));
+
+        self.send(request).await?;
+
+        Ok(size.load(Ordering::Relaxed))
+    }
+}
+
+impl StorageBackend for WebDavStorage {
+    fn put<'a>(&'a self, name: &'a str, mut data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        async move {
+            check_name(name)?;
+
+            self.create_folders(name).await?;
+
+            let uploads_url = match &self.uploads_url {
+                Some(uploads_url) => uploads_url,
+                None => return self.put_streamed(name, data).await,
+            };
+
+            let first_chunk = read_part(&mut data, CHUNK_SIZE).await?.unwrap_or_default();
+
+            if first_chunk.len() < CHUNK_SIZE {
+                return self.put_streamed(name, futures::stream::once(async { Ok(first_chunk) }).boxed()).await;
+            }
+
+            self.put_chunked(uploads_url, name, first_chunk, data).await
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<ByteStream>> {
+        async move {
+            check_name(name)?;
+
+            let response = self.send(self.request("GET", &self.url(name))?).await?;
+
+            Ok(response.bytes_stream().map_err(io::Error::other).boxed())
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            check_name(name)?;
+
+            self.send(self.request("DELETE", &self.url(name))?).await?;
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn list(&self) -> BoxFuture<'_, io::Result<Vec<String>>> {
+        async move {
+            let entries = match self.propfind(&self.config.url, "1").await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            };
+
+            let mut names: Vec<String> = entries.into_iter()
+                .filter(|entry| !entry.collection && check_name(&entry.name).is_ok())
+                .map(|entry| entry.name)
+                .collect();
+
+            names.sort();
+
+            Ok(names)
+        }.boxed()
+    }
+
+    fn stat<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Option<StoredObject>>> {
+        async move {
+            check_name(name)?;
+
+            let entries = match self.propfind(&self.url(name), "0").await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+
+            Ok(entries.into_iter()
+                .find(|entry| !entry.collection)
+                .map(|entry| StoredObject { size: entry.size, modified_at: entry.modified_at }))
+        }.boxed()
+    }
+}
+
+/// `reqwest::Body::wrap_stream` needs a `Sync` stream, the mutex is never locked as polling has exclusive access
+struct SyncStream(Mutex<ByteStream>);
+
+impl Stream for SyncStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let stream = self.get_mut().0.get_mut().unwrap_or_else(|e| e.into_inner());
+
+        stream.poll_next_unpin(cx)
+    }
+}
+
+/// Get the folder of the chunked uploads of a Nextcloud files URL
+/// "https://cloud.example.com/remote.php/dav/files/alice/file2link/" has "https://cloud.example.com/remote.php/dav/uploads/alice"
+///
+/// # Returns
+/// * `None` if the URL is not a Nextcloud files URL
+fn nextcloud_uploads_url(url: &str) -> Option<String> {
+    let (server, path) = url.split_once("/remote.php/dav/files/")?;
+    let user = path.split('/').next().filter(|user| !user.is_empty())?;
+
+    Some(format!("{}/remote.php/dav/uploads/{}", server, user))
+}
+
+/// Get the entries of a PROPFIND multistatus answer, the name is the last segment of the href
+fn dav_entries(xml: &str) -> Vec<DavEntry> {
+    xml_elements(xml, "response").into_iter()
+        .filter_map(|response| {
+            let href = xml_elements(response, "href").into_iter().next()?;
+            let name = percent_decode(&unescape_xml(href.trim()));
+            let name = name.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_owned();
+
+            let size = xml_elements(response, "getcontentlength").into_iter()
+                .find_map(|size| size.trim().parse().ok())
+                .unwrap_or_default();
+
+            let modified_at = xml_elements(response, "getlastmodified").into_iter()
+                .find_map(|modified| DateTime::parse_from_rfc2822(modified.trim()).ok())
+                .map(|modified| modified.timestamp() as u64);
+
+            Some(DavEntry { name, collection: !xml_elements(response, "collection").is_empty(), size, modified_at })
+        })
+        .collect()
+}
+
+/// Get the contents of all elements with the name in any namespace, like "d:href" and "D:href" for "href"
+/// Empty elements like "<d:collection/>" have empty contents, elements of the same name must not be nested.
+fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut contents = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let tag = &rest[..end];
+        let tag_name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default();
+
+        if tag.starts_with('/') || tag_name.rsplit(':').next() != Some(name) {
+            continue;
+        }
+
+        rest = &rest[end + 1..];
+
+        if tag.ends_with('/') {
+            contents.push("");
+
+            continue;
+        }
+
+        match rest.find(&format!("</{}>", tag_name)) {
+            Some(close) => {
+                contents.push(&rest[..close]);
+                rest = &rest[close..];
+            }
+            None => break,
+        }
+    }
+
+    contents
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escaped = Some(bytes[index])
+            .filter(|byte| *byte == b'%')
+            .and_then(|_| text.get(index + 1..index + 3))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(url: &str, chunking: bool) -> WebDavStorage {
+        WebDavStorage::new(WebDavConfig {
+            url: url.to_owned(),
+            username: "alice".to_owned(),
+            password: "app-password".to_owned(),
+            chunking,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_nextcloud_uploads_url() {
+        assert_eq!(
+            nextcloud_uploads_url("https://cloud.example.com/remote.php/dav/files/alice/file2link/"),
+            Some("https://cloud.example.com/remote.php/dav/uploads/alice".to_owned())
+        );
+        assert_eq!(nextcloud_uploads_url("https://cloud.example.com/remote.php/dav/files/"), None);
+        assert_eq!(nextcloud_uploads_url("https://dav.example.com/file2link/"), None);
+
+        assert!(storage("https://cloud.example.com/remote.php/dav/files/alice/", true).uploads_url.is_some());
+        assert!(storage("https://cloud.example.com/remote.php/dav/files/alice/", false).uploads_url.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_url() {
+        let storage = storage("https://dav.example.com/file2link/", true);
+
+        assert_eq!(storage.url("abcde_my report#1.pdf"), "https://dav.example.com/file2link/abcde_my%20report%231.pdf");
+        assert_eq!(storage.url("ab/cd/abcdef"), "https://dav.example.com/file2link/ab/cd/abcdef");
+    }
+
+    #[tokio::test]
+    async fn test_dav_entries() {
+        let xml = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+ <d:response>
+  <d:href>/remote.php/dav/files/alice/file2link/</d:href>
+  <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype><d:getlastmodified>Tue, 04 Jun 2024 10:00:00 GMT</d:getlastmodified></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+  <d:propstat><d:prop><d:getcontentlength/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>
+ </d:response>
+ <d:response>
+  <d:href>/remote.php/dav/files/alice/file2link/abcde_my%20report.pdf</d:href>
+  <d:propstat><d:prop><d:resourcetype/><d:getcontentlength>1024</d:getcontentlength><d:getlastmodified>Tue, 04 Jun 2024 10:00:00 GMT</d:getlastmodified></d:prop></d:propstat>
+ </d:response>
+ <D:response xmlns:D="DAV:"><D:href>/file2link/fghij_a&amp;b.txt</D:href><D:propstat><D:prop><D:getcontentlength>5</D:getcontentlength></D:prop></D:propstat></D:response>
+</d:multistatus>"#;
+
+        assert_eq!(dav_entries(xml), vec![
+            DavEntry { name: "file2link".to_owned(), collection: true, size: 0, modified_at: Some(1717495200) },
+            DavEntry { name: "abcde_my report.pdf".to_owned(), collection: false, size: 1024, modified_at: Some(1717495200) },
+            DavEntry { name: "fghij_a&b.txt".to_owned(), collection: false, size: 5, modified_at: None },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b%C3%A9%2"), "a bé%2");
+        assert_eq!(percent_decode("%zz100%"), "%zz100%");
+    }
+
+    #[tokio::test]
+    async fn test_sync_stream() {
+        let data: ByteStream = futures::stream::iter(vec![Ok(Bytes::from("he")), Ok(Bytes::from("llo"))]).boxed();
+        let chunks: Vec<Bytes> = SyncStream(Mutex::new(data)).try_collect().await.unwrap();
+
+        assert_eq!(chunks.concat(), b"hello");
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use shared::chat_config::PermissionsConfig;
+use shared::config::SharedConfig;
+use shared::gc;
+use shared::metadata::{save_metadata, FileMetadata, MetadataStore};
+use shared::metrics::METRICS;
+use shared::storage::{self, Storage};
+use shared::tiering;
+use shared::usage::now_seconds;
+use shared::utils::format_size;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::{interval, sleep};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+const TIERING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically deletes expired files from the storage together with their metadata and versions, see `FILE_TTL`, `--expire` and
+/// the `retention` of the permissions
+/// Content shared by content addressed files is kept until the last file using it expires
+pub async fn remove_expired_files(metadata: Arc<Mutex<MetadataStore>>, permissions: Arc<Mutex<PermissionsConfig>>, storage: Storage) {
+    let mut interval = interval(CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // copied before the metadata is locked, so the two locks are never held together
+        let retention = permissions.lock().await.retention().clone();
+
+        // the files are removed from the metadata first, so no new file is linked to content which is deleted below
+        let expired: Vec<FileMetadata> = {
+            let mut metadata = metadata.lock().await;
+
+            let expired: Vec<FileMetadata> = metadata.expired(now_seconds(), &retention).iter()
+                .filter_map(|file_name| metadata.remove(file_name))
+                .collect();
+
+            if !expired.is_empty() {
+                if let Err(e) = save_metadata(&metadata).await {
+                    warn!("Failed to save metadata: {}", e);
+                }
+            }
+
+            expired
+        };
+
+        if expired.is_empty() {
+            debug!("No expired files to remove");
+
+            continue;
+        }
+
+        let mut reclaimed_bytes = 0;
+
+        // expired files may share their content too, it is deleted once
+        let mut deleted = HashSet::new();
+
+        for file in &expired {
+            let file_name = &file.file_name;
+            let storage_name = file.storage_name();
+
+            // the earlier versions of the file go with it
+            for version in &file.versions {
+                if deleted.contains(&version.storage_name) || is_linked(&metadata, &version.storage_name).await {
+                    continue;
+                }
+
+                deleted.insert(version.storage_name.clone());
+
+                match storage.delete(&version.storage_name).await {
+                    Ok(_) => reclaimed_bytes += version.size,
+                    Err(e) => warn!("Failed to remove version {} of expired file '{}': {}", version.version, file_name, e),
+                }
+            }
+
+            if deleted.contains(&storage_name) {
+                info!("Removed expired file '{}', its content was shared with another expired file", file_name);
+
+                continue;
+            }
+
+            if is_linked(&metadata, &storage_name).await {
+                info!("Removed expired file '{}', its content is still linked", file_name);
+
+                continue;
+            }
+
+            deleted.insert(storage_name.clone());
+
+            match storage.delete(&storage_name).await {
+                Ok(_) => {
+                    info!("Removed expired file '{}' ({})", file_name, format_size(file.size));
+
+                    reclaimed_bytes += file.size;
+                }
+                Err(e) => warn!("Failed to remove expired file '{}': {}", file_name, e),
+            }
+        }
+
+        info!("Removed {} expired files, reclaimed {}", expired.len(), format_size(reclaimed_bytes));
+
+        METRICS.expired(expired.len(), reclaimed_bytes);
+    }
+}
+
+/// Checks right before the content is deleted whether a file still uses it, one may have been linked to it since
+/// The lock is not held while the content is deleted, like in `gc::collect_garbage`
+async fn is_linked(metadata: &Mutex<MetadataStore>, storage_name: &str) -> bool {
+    metadata.lock().await.is_stored(storage_name)
+}
+
+/// Checks the storage against the metadata every `GC_INTERVAL` and whenever the CLI asks with `f2l-cli gc`
+/// Without `GC_INTERVAL` the storage is only checked on demand, see `gc::collect_garbage`
+pub async fn collect_garbage(config: SharedConfig, metadata: Arc<Mutex<MetadataStore>>, storage: Storage, requests: Arc<Notify>) {
+    loop {
+        // read every time, the interval may change with a reload, see `SharedConfig::reload`
+        let config = config.get();
+
+        match config.gc_interval() {
+            Some(seconds) => tokio::select! {
+                _ = sleep(Duration::from_secs(seconds)) => {}
+                _ = requests.notified() => {}
+            },
+            None => requests.notified().await,
+        }
+
+        info!("Checking the storage against the metadata");
+
+        let report = match gc::collect_garbage(&metadata, &storage, config.gc_remove_orphans(), now_seconds()).await {
+            Ok(report) => report,
+            Err(e) => {
+                warn!("Failed to check the storage: {}", e);
+
+                continue;
+            }
+        };
+
+        info!("{}", report);
+
+        if !report.missing.is_empty() {
+            if let Err(e) = save_metadata(&*metadata.lock().await).await {
+                warn!("Failed to save metadata: {}", e);
+            }
+        }
+    }
+}
+
+/// Moves the files not downloaded for `COLD_STORAGE_AFTER` to `COLD_STORAGE_BACKEND` every hour, returns at once
+/// without a cold storage
+/// Files are moved back by the server when they are downloaded, see `TieredStorage`
+pub async fn move_idle_files(config: SharedConfig, metadata: Arc<Mutex<MetadataStore>>) {
+    // the storages are only read at startup
+    let startup_config = config.get();
+
+    let cold_storage = match startup_config.cold_storage() {
+        Some(cold_storage) => cold_storage,
+        None => return,
+    };
+
+    // the files are moved as they are stored, encrypted and compressed files stay so
+    let primary = storage::backend(startup_config.storage(), &startup_config);
+    let cold = storage::backend(cold_storage, &startup_config);
+
+    let mut interval = interval(TIERING_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let since = now_seconds().saturating_sub(config.get().cold_storage_after());
+        let names = metadata.lock().await.idle_storage_names(since);
+
+        let (moved, moved_bytes) = tiering::move_to_cold(&primary, &cold, &names).await;
+
+        if moved > 0 {
+            info!("Moved {} idle files to the cold storage ({})", moved, format_size(moved_bytes));
+        }
+
+        // the downloads recorded by the server decide what is moved, they have to survive a restart
+        if let Err(e) = save_metadata(&*metadata.lock().await).await {
+            warn!("Failed to save metadata: {}", e);
+        }
+    }
+}
@@ -1,120 +1,348 @@
-use bot::queue::process_queue;
+use bot::alerts::notify_admin;
+use bot::queue::{cancel_job, drain_queue, load_queue, notify_queue, pause_queue, process_queue, requeue_failed, resume_queue};
 use std::error::Error;
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bot::bot::{Bot as BotTrait, TeloxideBot};
-use bot::queue::FileQueueType;
+use bot::queue::{FileQueueType, QueueEvent, QueueManager};
 use cli::utils::send_command;
-use log::{error, info};
+use log::{error, info, warn};
 use tokio::net::TcpListener;
 use tokio::signal;
 use tokio::spawn;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::task::{JoinError, JoinSet};
 
+mod cleanup;
 mod server;
 use shared::chat_config;
-use shared::config;
+use shared::cli_utils::QueueCommand;
+use shared::chat_settings;
+use shared::config::{self, Config, SharedConfig};
+use shared::logging;
+use shared::metadata;
+use shared::secrets;
+use shared::storage::{self, Storage};
+use shared::usage;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> ExitCode {
     config::load_env();
 
-    pretty_env_logger::init();
+    logging::init();
 
+    match run().await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Starts the bots, the queue and the server and runs them until the shutdown
+///
+/// # Returns
+/// * `Err` with the reason the bot could not start or the error a task stopped with, `main` exits with it
+async fn run() -> Result<(), Box<dyn Error>> {
     info!("Starting up...");
 
-    let server_port = config::Config::instance().await.server_port();
-    info!("Server port: {}", server_port);
+    secrets::load_secrets().await;
+
+    bot::ping::mark_started(env!("CARGO_PKG_VERSION"), option_env!("GIT_COMMIT"));
+
+    let config = SharedConfig::new(Config::new());
+
+    let startup_config = config.get();
+
+    if !startup_config.errors().is_empty() {
+        let invalid: Vec<&str> = startup_config.errors().iter().map(|e| e.var()).collect();
+
+        warn!("{} variable(s) are invalid and use their default: {}", invalid.len(), invalid.join(", "));
+    }
+
+    startup_config.validate().map_err(|e| format!("Invalid configuration: {}", e))?;
+
+    if let Some(provider) = startup_config.secrets_provider() {
+        spawn(secrets::refresh_secrets(config.clone(), provider));
+    }
+
+    let listen_addr = startup_config.listen_addr();
+    info!("Server address: {}", listen_addr);
+
+    // bound before the bots start, so a taken port stops the startup
+    let listener = TcpListener::bind(listen_addr).await
+        .map_err(|e| format!("Failed to listen on {}: {}", listen_addr, e))?;
 
     let raw_permissions = chat_config::load_config()
-        .await.expect("Failed to load config");
+        .await.map_err(|e| format!("Failed to load config: {}", e))?;
 
     let permissions = Arc::new(Mutex::new(raw_permissions));
 
-    let file_queue: FileQueueType = Arc::new(Mutex::new(Vec::new()));
+    spawn(chat_config::watch_config(Arc::clone(&permissions)));
 
-    let bot = match TeloxideBot::new(config::Config::instance().await, permissions.clone(), file_queue.clone()) {
-        Ok(bot) => bot,
-        Err(e) => {
-            error!("Failed to create bot: {}", e);
+    let raw_usage = usage::load_usage()
+        .await.map_err(|e| format!("Failed to load usage: {}", e))?;
 
-            return Err("Failed to create bot".into());
-        }
-    };
+    let usage = Arc::new(Mutex::new(raw_usage));
+
+    let raw_metadata = metadata::load_metadata()
+        .await.map_err(|e| format!("Failed to load metadata: {}", e))?;
+
+    let metadata = Arc::new(Mutex::new(raw_metadata));
+
+    let raw_settings = chat_settings::load_settings()
+        .await.map_err(|e| format!("Failed to load settings: {}", e))?;
+
+    let settings = Arc::new(Mutex::new(raw_settings));
+
+    let storage: Storage = storage::from_config(&config.get());
 
-    let bot_clone = Arc::new(bot);
+    let file_queue: FileQueueType = Arc::new(Mutex::new(QueueManager::new()));
+
+    let mut bots = Vec::new();
+
+    // checked by `validate`
+    for token in startup_config.bot_tokens()? {
+        let bot = TeloxideBot::new(config.clone(), token, permissions.clone(), usage.clone(), metadata.clone(), settings.clone(), file_queue.clone())
+            .map_err(|e| format!("Failed to create bot: {}", e))?;
+
+        bots.push(Arc::new(bot));
+    }
+
+    info!("Starting {} bot(s)", bots.len());
+
+    let teloxide_bots: Vec<_> = bots.iter().map(|bot| bot.get_teloxide_bot()).collect();
+
+    let restored_queue = load_queue(&config, &teloxide_bots)
+        .await.map_err(|e| format!("Failed to load queue: {}", e))?;
+
+    let restored_files = restored_queue.len();
+
+    *file_queue.lock().await = restored_queue.with_order(config.get().queue_order());
 
     let (tx, rx) = mpsc::channel(100);
 
-    let bot_task = {
+    if restored_files > 0 {
+        notify_queue(&tx, QueueEvent::Resumed)?;
+    }
+
+    // queue commands sent with the CLI
+    let (queue_commands_tx, mut queue_commands_rx) = mpsc::channel(10);
+
+    let gc_requests = Arc::new(Notify::new());
+
+    {
+        let gc_requests = Arc::clone(&gc_requests);
+        let file_queue = Arc::clone(&file_queue);
         let tx = tx.clone();
-        let bot = Arc::clone(&bot_clone);
 
         spawn(async move {
-            bot.run(tx).await;
+            while let Some(command) = queue_commands_rx.recv().await {
+                match command {
+                    QueueCommand::Requeue(id) => {
+                        if let Err(e) = requeue_failed(&file_queue, &tx, id).await {
+                            warn!("Failed to queue failed file #{} again: {}", id, e);
+                        }
+                    }
+                    QueueCommand::Cancel(job) => match cancel_job(&file_queue, &tx, &job, None).await {
+                        0 => warn!("There is no queued file with the job ID {}", job),
+                        _ => info!("Cancelled the file with the job ID {}", job),
+                    },
+                    QueueCommand::Pause => info!("{}", pause_queue(&file_queue).await),
+                    QueueCommand::Resume => info!("{}", resume_queue(&file_queue, &tx).await),
+                    QueueCommand::CollectGarbage => gc_requests.notify_one(),
+                }
+            }
+        });
+    }
+
+    // any of the bots can send alerts, the admin chat only has to have one of them
+    let alert_bot = bots.first().map(|bot| bot.get_teloxide_bot());
+
+    let bot_task = {
+        let mut bot_tasks = JoinSet::new();
+
+        for bot in bots {
+            let tx = tx.clone();
+
+            bot_tasks.spawn(async move {
+                bot.run(tx).await;
+            });
+        }
+
+        let alert_bot = alert_bot.clone();
+        let config = config.clone();
+
+        spawn(async move {
+            let mut crashed = 0;
+
+            while let Some(result) = bot_tasks.join_next().await {
+                if let Err(e) = result {
+                    error!("Bot crashed: {}", e);
+
+                    if let Some(bot) = &alert_bot {
+                        notify_admin(bot, &config.get(), format!("Bot crashed: {}", e)).await;
+                    }
+
+                    crashed += 1;
+                }
+            }
+
+            match crashed {
+                0 => Ok(()),
+                _ => Err(format!("{} bot(s) crashed", crashed)),
+            }
         })
     };
 
     let queue_processor_task = {
         let file_queue: FileQueueType = Arc::clone(&file_queue);
+        let usage = Arc::clone(&usage);
+        let metadata = Arc::clone(&metadata);
+        let settings = Arc::clone(&settings);
+        let storage = Arc::clone(&storage);
+        let config = config.clone();
+        let tx = tx.clone();
+
+        spawn(async move {
+            process_queue(config, file_queue, usage, metadata, settings, storage, tx, rx).await
+                .map_err(|e| format!("Failed to process queue: {}", e))
+        })
+    };
 
-        let bot = Arc::clone(&bot_clone);
+    let cleanup_task = {
+        let metadata = Arc::clone(&metadata);
+        let permissions = Arc::clone(&permissions);
+        let storage = Arc::clone(&storage);
+        let config = config.clone();
 
         spawn(async move {
-            if let Err(e) = process_queue(bot, file_queue, rx).await {
-                error!("Failed to process queue: {}", e);
-            }
+            tokio::join!(
+                cleanup::remove_expired_files(Arc::clone(&metadata), permissions, Arc::clone(&storage)),
+                cleanup::collect_garbage(config.clone(), Arc::clone(&metadata), storage, gc_requests),
+                cleanup::move_idle_files(config, metadata),
+            );
+
+            Ok(())
+        })
+    };
+
+    let storage_task = {
+        let metadata = Arc::clone(&metadata);
+        let alert_bot = alert_bot.clone();
+        let config = config.clone();
+
+        spawn(async move {
+            bot::alerts::watch_storage(alert_bot, config, metadata).await;
+
+            Ok(())
         })
     };
 
-    let server_task = spawn(async move {
-        let app = server::create_app().await;
+    let server_task = {
+        let file_queue = Arc::clone(&file_queue);
+        let config = config.clone();
+        let tx = tx.clone();
 
-        let addr: String = format!("0.0.0.0:{}", server_port);
-        let listener = TcpListener::bind(&addr).await
-            .expect("Failed to bind to address");
+        let local_addr = listener.local_addr()
+            .map_err(|e| format!("Failed to get the server address: {}", e))?;
 
-        let local_addr = listener.local_addr().unwrap();
-        let ip = local_addr.ip().to_string();
-        let port = local_addr.port();
+        spawn(async move {
+            let app = server::create_app(config, metadata, storage, file_queue, tx).await;
 
-        info!("Server is running at http://{}:{}/", ip, port);
+            // SocketAddr puts IPv6 addresses in brackets like a URL
+            info!("Server is running at http://{}/", local_addr);
 
-        if let Err(e) = axum::serve(listener, app).await {
-            error!("Server error: {}", e);
-        }
-    });
+            axum::serve(listener, app).await.map_err(|e| format!("Server error: {}", e))
+        })
+    };
 
     let ctrl_c_task = {
+        let pipe_path = config.get().pipe_path();
+
         spawn(async move {
-            signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+            signal::ctrl_c().await.map_err(|e| format!("Failed to listen for Ctrl+C: {}", e))?;
 
             info!("Received Ctrl+C, shutting down...");
 
-            match send_command(&config::Config::instance().await.pipe_path(), "shutdown").await {
+            match send_command(&pipe_path, "shutdown").await {
                 Ok(_) => info!("Command 'shutdown' sent"),
                 Err(_) => error!("Failed to send shutdown command")
             };
+
+            Ok(())
+        })
+    };
+
+    // the configuration is reloaded by the command handler, like with `f2l-cli reload-config`
+    let reload_task = {
+        let pipe_path = config.get().pipe_path();
+
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+            .map_err(|e| format!("Failed to listen for SIGHUP: {}", e))?;
+
+        spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading the configuration...");
+
+                match send_command(&pipe_path, "reload_config").await {
+                    Ok(_) => info!("Command 'reload_config' sent"),
+                    Err(_) => error!("Failed to send reload_config command")
+                };
+            }
+
+            Ok(())
         })
     };
 
     let update_cli_task = {
         let permissions = Arc::clone(&permissions);
+        let config = config.clone();
 
         spawn(async move {
-            shared::cli_utils::handle_cli(permissions).await;
+            shared::cli_utils::handle_cli(config, permissions, queue_commands_tx).await;
+
+            Ok(())
         })
     };
 
-    tokio::select! {
-        _ = bot_task => {},
-        _ = queue_processor_task => {},
-        _ = server_task => {},
-        _ = update_cli_task => {},
-        _ = ctrl_c_task => {},
-    }
+    // the first task to end stops the bot, `main` exits with a failure if it ended with an error
+    let result = tokio::select! {
+        result = bot_task => task_result("Bots", result),
+        result = queue_processor_task => {
+            let result = task_result("Queue processor", result);
+
+            if let (Err(e), Some(bot)) = (&result, &alert_bot) {
+                notify_admin(bot, &config.get(), e.clone()).await;
+            }
+
+            result
+        },
+        result = cleanup_task => task_result("Cleanup", result),
+        result = storage_task => task_result("Storage watcher", result),
+        result = server_task => task_result("Server", result),
+        result = update_cli_task => task_result("Command handler", result),
+        result = reload_task => task_result("SIGHUP handler", result),
+        result = ctrl_c_task => task_result("Ctrl+C handler", result),
+    };
 
     info!("Shutting down gracefully");
 
-    Ok(())
+    let shutdown_timeout = Duration::from_secs(config.get().shutdown_timeout());
+
+    drain_queue(&file_queue, &tx, shutdown_timeout).await;
+
+    result.map_err(Into::into)
+}
+
+/// Get the result of a task of `run`, a panic is an error too
+fn task_result(name: &str, result: Result<Result<(), String>, JoinError>) -> Result<(), String> {
+    match result {
+        Ok(result) => result,
+        Err(e) => Err(format!("{} crashed: {}", name, e)),
+    }
 }
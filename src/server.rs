@@ -1,53 +1,212 @@
-use std::{convert::Infallible, fs::{self, File}, io::Read, path::PathBuf};
+use std::{collections::HashMap, convert::Infallible, io::ErrorKind, sync::Arc};
 
 use axum::response::IntoResponse;
 use axum::{
     body::Body,
-    extract,
-    response::{Html, Response},
-    routing::{get, Router},
+    extract::{self, State},
+    response::{sse::{Event, KeepAlive, Sse}, Html, Response},
+    routing::{delete, get, Router},
+    Json,
 };
-use http::{header::CONTENT_TYPE, StatusCode};
+use bot::queue::{cancel_job, next_progress, FileQueueType, QueueEvent};
+use futures::stream;
+use http::{header::{ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LOCATION, VARY}, HeaderMap, StatusCode};
 use log::{debug, error, info, warn};
 use mime_guess::from_path;
-
-use crate::config::Config;
-
-pub async fn create_app() -> Router {
-    let enable_files_route = Config::instance().await.enable_files_route();
+use shared::listing;
+use shared::metadata::MetadataStore;
+use shared::metrics::METRICS;
+use shared::stats::StorageStats;
+use shared::storage::Storage;
+use shared::usage::now_seconds;
+use shared::utils::{format_duration, format_size};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+
+use crate::config::SharedConfig;
+
+pub async fn create_app(config: SharedConfig, metadata: Arc<Mutex<MetadataStore>>, storage: Storage, file_queue: FileQueueType, tx: Sender<QueueEvent>) -> Router {
+    let features = config.get().features();
+    let admin_api_token = config.get().admin_api_token().filter(|_| features.admin_api);
 
     let mut router = Router::new()
-        .route("/", get(root))
-        .route("/files/:id", get(files_id));
+        .route("/files/:id", get(files_id))
+        .route("/files/:id/v/:version", get(file_version))
+        .route(&format!("/files/{}/:owner", listing::LISTING_PATH), get(files_of_owner));
+
+    if features.web_ui {
+        router = router.route("/", get(root));
+    }
 
-    if enable_files_route {
+    if features.files_listing {
         router = router.route("/files", get(files_list));
     }
 
-    router.fallback(not_found_handler)
+    if features.metrics {
+        router = router.route("/metrics", get(metrics));
+    }
+
+    let mut router = router.fallback(not_found_handler).with_state(FilesState { config: config.clone(), metadata: metadata.clone(), storage });
+
+    if let Some(token) = admin_api_token {
+        router = router.merge(
+            Router::new()
+                .route("/api/queue", get(queue_snapshot))
+                .route("/api/queue/events", get(queue_events))
+                .route("/api/queue/:id", delete(queue_cancel))
+                .route("/api/files/:id", get(file_info))
+                .route("/api/stats", get(storage_stats))
+                .with_state(AdminState { token, config, file_queue, tx, metadata })
+        );
+    }
+
+    router
 }
 
-/// ignores folders and shows only files
-async fn files_list() -> Result<Response<Body>, Infallible> {
-    info!("Files list accessed");
+#[derive(Clone)]
+struct FilesState {
+    config: SharedConfig,
+    metadata: Arc<Mutex<MetadataStore>>,
+    storage: Storage,
+}
 
-    let folder_path = PathBuf::from("files");
+#[derive(Clone)]
+struct AdminState {
+    token: String,
+    config: SharedConfig,
+    file_queue: FileQueueType,
+    tx: Sender<QueueEvent>,
+    metadata: Arc<Mutex<MetadataStore>>,
+}
 
-    debug!("Listing files in folder path: {:?}", folder_path);
+/// Jobs in the queue with their progress, the same as `f2l-cli queue list` shows
+async fn queue_snapshot(State(admin): State<AdminState>, headers: HeaderMap) -> Response<Body> {
+    if !is_authorized(&headers, &admin.token) {
+        warn!("Unauthorized admin API request");
 
-    if !folder_path.is_dir() {
-        warn!("Path is not a directory: {:?}", folder_path);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
 
-        return Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Body::empty())
-            .unwrap());
+    let snapshot = admin.file_queue.lock().await.snapshot(now_seconds());
+
+    Json(snapshot).into_response()
+}
+
+/// Progress of the started files as server-sent events, each carrying a JSON `ProgressEvent`
+async fn queue_events(State(admin): State<AdminState>, headers: HeaderMap) -> Response<Body> {
+    if !is_authorized(&headers, &admin.token) {
+        warn!("Unauthorized admin API request");
+
+        return StatusCode::UNAUTHORIZED.into_response();
     }
 
-    let entries = match fs::read_dir(&folder_path) {
-        Ok(entries) => entries,
+    let events = admin.file_queue.lock().await.subscribe_progress();
+
+    let stream = stream::unfold(events, |mut events| async move {
+        let event = next_progress(&mut events).await?;
+
+        Some((Event::default().json_data(event), events))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// Cancels the job with the short ID shown in its status message, the same as `f2l-cli cancel`
+async fn queue_cancel(
+    State(admin): State<AdminState>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<String>,
+) -> Response<Body> {
+    if !is_authorized(&headers, &admin.token) {
+        warn!("Unauthorized admin API request");
+
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match cancel_job(&admin.file_queue, &admin.tx, &id, None).await {
+        0 => StatusCode::NOT_FOUND.into_response(),
+        _ => {
+            info!("[{}] Cancelled by the admin API", id);
+
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+/// Metadata of a stored file like its size and SHA-256, the ID is the name in its link
+async fn file_info(
+    State(admin): State<AdminState>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<String>,
+) -> Response<Body> {
+    if !is_authorized(&headers, &admin.token) {
+        warn!("Unauthorized admin API request");
+
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match admin.metadata.lock().await.get(&id) {
+        Some(file) => Json(file.clone()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Files and bytes in the storage and the free space in the files directory, see `StorageStats`
+async fn storage_stats(State(admin): State<AdminState>, headers: HeaderMap) -> Response<Body> {
+    if !is_authorized(&headers, &admin.token) {
+        warn!("Unauthorized admin API request");
+
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let files_dir = admin.config.get().files_dir();
+
+    Json(StorageStats::collect(&*admin.metadata.lock().await, &files_dir)).into_response()
+}
+
+/// Tells whether the `Accept-Encoding` header lists zstd without `q=0`
+fn accepts_zstd(headers: &HeaderMap) -> bool {
+    headers.get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+
+            name.eq_ignore_ascii_case("zstd") && !parts.any(|param| {
+                param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+            })
+        })
+}
+
+/// Checks the `Authorization: Bearer <token>` header of an admin API request
+fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| tokens_match(token, given))
+}
+
+/// Compares in constant time, so the token can not be guessed from response times
+fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Queue and download metrics in the Prometheus text format
+async fn metrics() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], METRICS.render())
+}
+
+/// ignores folders and shows only files
+async fn files_list(State(files): State<FilesState>) -> Result<Response<Body>, Infallible> {
+    info!("Files list accessed");
+
+    let mut names = match files.storage.list().await {
+        Ok(names) => names,
         Err(e) => {
-            error!("Failed to read directory: {:?}. Error: {}", folder_path, e);
+            error!("Failed to list stored files: {}", e);
 
             return Ok(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -56,27 +215,25 @@ async fn files_list() -> Result<Response<Body>, Infallible> {
         }
     };
 
-    let mut html = String::from("<h1>Files in directory</h1><ul>");
+    {
+        let metadata = files.metadata.lock().await;
+
+        // content addressed files and files in date folders are not stored under their names
+        names.extend(metadata.files()
+            .filter(|file| file.storage_name() != file.file_name)
+            .map(|file| file.file_name.clone()));
+        // private files are only listed to their owners, see `files_of_owner`
+        names.retain(|name| !metadata.get(name).is_some_and(|file| file.private));
+    }
 
-    for entry in entries {
-        match entry {
-            Ok(entry) => {
-                let path = entry.path();
+    names.sort();
 
-                if path.is_file() {
-                    if let Some(file_name) = path.file_name() {
-                        let file_name = file_name.to_string_lossy();
+    debug!("Listing {} stored files", names.len());
 
-                        html.push_str(&format!("<li><a href=\"/files/{}\">{}</a></li>", file_name, file_name));
-                    }
-                }
-            }
-            Err(e) => {
-                error!("Failed to read directory entry: {:?}. Error: {}", folder_path, e);
+    let mut html = String::from("<h1>Files in directory</h1><ul>");
 
-                continue;
-            }
-        }
+    for file_name in names {
+        html.push_str(&format!("<li><a href=\"/files/{}\">{}</a></li>", file_name, file_name));
     }
 
     html.push_str("</ul>");
@@ -88,62 +245,390 @@ async fn files_list() -> Result<Response<Body>, Infallible> {
         .unwrap())
 }
 
-async fn files_id(extract::Path(id): extract::Path<String>) -> Result<Response<Body>, Infallible> {
-    let file_path = format!("files/{}", id);
-    let file_path = PathBuf::from(&file_path);
+/// Lists the files of one uploader, the link is signed by the bot on /mylink
+async fn files_of_owner(
+    State(files): State<FilesState>,
+    extract::Path(owner): extract::Path<String>,
+    extract::Query(query): extract::Query<HashMap<String, String>>,
+) -> Result<Response<Body>, Infallible> {
+    let secret = match files.config.get().listing_secret() {
+        Some(secret) => secret,
+        None => return Ok((StatusCode::NOT_FOUND, not_found_handler().await).into_response()),
+    };
 
-    debug!("Requested file path: {:?}", file_path);
+    let expires_at = query.get("expires").and_then(|expires| expires.parse::<u64>().ok());
+    let signature = query.get("signature");
+    let now = now_seconds();
 
-    if !file_path.exists() {
-        warn!("File not found: {:?}", file_path);
+    let is_valid = match (expires_at, signature) {
+        (Some(expires_at), Some(signature)) => listing::verify(&secret, &owner, expires_at, signature, now),
+        _ => false,
+    };
 
-        let body = not_found_handler().await;
+    if !is_valid {
+        warn!("Invalid or expired listing link for owner {}", owner);
 
         return Ok((
-            StatusCode::NOT_FOUND,
-            [(CONTENT_TYPE, "text/html")],
-            body,
+            StatusCode::FORBIDDEN,
+            Html("<h1>403 Forbidden</h1><p>The link is invalid or has expired. Send /mylink to the bot for a new one.</p>"),
         ).into_response());
     }
 
-    let mut file = match File::open(&file_path) {
-        Ok(file) => file,
+    info!("Files of owner {} accessed", owner);
+
+    let metadata = files.metadata.lock().await;
+    let files = metadata.search(&owner, "", now);
+    let token = listing::owner_token(&secret, &owner);
+
+    let mut html = format!("<h1>Your files</h1><p>{} file(s)</p><ul>", files.len());
+
+    for file in files {
+        let name = escape_html(&file.file_name);
+        let expiry = match file.expires_at {
+            Some(expires_at) => format!(", expires in {}", format_duration(expires_at.saturating_sub(now))),
+            None => String::new(),
+        };
+        let checksum = match &file.sha256 {
+            Some(sha256) => format!("<br><small>SHA-256: <code>{}</code></small>", sha256),
+            None => String::new(),
+        };
+
+        let link = match file.private {
+            true => listing::with_token(&name, &token),
+            false => name.clone(),
+        };
+
+        // relative to /files/my/, so the links keep working behind a proxy prefix
+        html.push_str(&format!(
+            "<li><a href=\"../{}\">{}</a> ({}{}){}</li>",
+            link,
+            name,
+            format_size(file.size),
+            expiry,
+            checksum
+        ));
+    }
+
+    html.push_str("</ul>");
+
+    Ok(Html(html).into_response())
+}
+
+/// Get the value of a parameter in the query, "" for flags like "inline" in "?inline&token=..."
+fn query_value<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?.split('&').find_map(|pair| match pair.split_once('=') {
+        Some((key, value)) => Some(value).filter(|_| key == name),
+        None => Some("").filter(|_| pair == name),
+    })
+}
+
+/// Private files are served only with the token of one of their owners, see `listing::owner_token`
+/// Without `LISTING_SECRET` no token is valid
+fn is_accessible(private: bool, owners: &[String], secret: Option<&str>, token: Option<&str>) -> bool {
+    if !private {
+        return true;
+    }
+
+    match (secret, token) {
+        (Some(secret), Some(token)) => listing::verify_owner_token(secret, owners, token),
+        _ => false,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+async fn files_id(
+    State(files): State<FilesState>,
+    extract::Path(id): extract::Path<String>,
+    extract::RawQuery(query): extract::RawQuery,
+    headers: HeaderMap,
+) -> Result<Response<Body>, Infallible> {
+    debug!("Requested file: {}", id);
+
+    let query = query.as_deref();
+    let token = query_value(query, "token");
+
+    if query_value(query, "preview").is_some() {
+        return Ok(file_preview(&files, &id, token).await);
+    }
+
+    let secret = files.config.get().listing_secret();
+    let mut metadata = files.metadata.lock().await;
+
+    // only files stored before the metadata are served by their storage name, the content of other files like the
+    // ones in date folders, content addressed files and versions would skip the checks of their metadata
+    if metadata.get(&id).is_none() && (id.contains('/') || metadata.is_stored(&id)) {
+        warn!("File not found: {}", id);
+
+        return Ok((StatusCode::NOT_FOUND, not_found_handler().await).into_response());
+    }
+
+    // content addressed files are stored under their hash, files without metadata under their name
+    let (is_expired, is_accessible, storage_name, sha256) = match metadata.get_mut(&id) {
+        Some(file) => {
+            let now = now_seconds();
+            let is_expired = file.is_expired(now);
+            let is_accessible = is_accessible(file.private, &file.owners, secret.as_deref(), token);
+
+            // kept in the primary storage while it is downloaded, see `COLD_STORAGE_AFTER`
+            if !is_expired && is_accessible {
+                file.downloaded_at = Some(now);
+            }
+
+            (is_expired, is_accessible, file.storage_name(), file.sha256.clone())
+        }
+        None => (false, true, id.clone(), None),
+    };
+
+    drop(metadata);
+
+    if is_expired {
+        info!("File expired: {}", id);
+    }
+
+    // private files without a valid token look like missing files
+    if !is_accessible {
+        warn!("Private file requested without a valid token: {}", id);
+    }
+
+    Ok(serve_file(&files, &id, &storage_name, sha256, !is_expired && is_accessible, query_value(query, "inline").is_some(), &headers).await)
+}
+
+/// Serves an earlier file stored under the name, see `FILE_VERSIONS`. Versions of private files need the token of
+/// their owners like the files
+async fn file_version(
+    State(files): State<FilesState>,
+    extract::Path((id, version)): extract::Path<(String, u32)>,
+    extract::RawQuery(query): extract::RawQuery,
+    headers: HeaderMap,
+) -> Result<Response<Body>, Infallible> {
+    debug!("Requested version {} of file: {}", version, id);
+
+    let query = query.as_deref();
+    let secret = files.config.get().listing_secret();
+
+    let file_version = {
+        let now = now_seconds();
+        let mut metadata = files.metadata.lock().await;
+
+        metadata.get_mut(&id)
+            .filter(|file| !file.is_expired(now))
+            .and_then(|file| {
+                let file_version = file.version(version)?.clone();
+                let is_accessible = is_accessible(file_version.private, &file_version.owners, secret.as_deref(), query_value(query, "token"));
+
+                // versions are moved to the cold storage with their file, see `COLD_STORAGE_AFTER`
+                if is_accessible {
+                    file.downloaded_at = Some(now);
+                }
+
+                Some((file_version.storage_name, file_version.sha256, is_accessible))
+            })
+    };
+
+    let (storage_name, sha256, is_accessible) = match file_version {
+        Some(file_version) => file_version,
+        None => {
+            warn!("Version {} of file not found: {}", version, id);
+
+            return Ok((StatusCode::NOT_FOUND, not_found_handler().await).into_response());
+        }
+    };
+
+    Ok(serve_file(&files, &id, &storage_name, sha256, is_accessible, query_value(query, "inline").is_some(), &headers).await)
+}
+
+/// Sends the stored content with its checksum, or the not found page if it is not there or not available
+async fn serve_file(
+    files: &FilesState,
+    id: &str,
+    storage_name: &str,
+    sha256: Option<String>,
+    is_available: bool,
+    inline: bool,
+    headers: &HeaderMap,
+) -> Response<Body> {
+    // files moved to the cold storage are moved back when they are downloaded
+    if is_available {
+        match files.storage.restore(storage_name).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::InvalidInput => {}
+            Err(e) => warn!("Failed to restore file: {}. Error: {}", id, e),
+        }
+    }
+
+    let stored = match files.storage.stat(storage_name).await {
+        Ok(stored) => stored.filter(|_| is_available),
+        // names the storage refuses, like paths out of it, are not there
+        Err(e) if e.kind() == ErrorKind::InvalidInput => None,
         Err(e) => {
-            error!("Failed to open file: {:?}. Error: {}", file_path, e);
+            error!("Failed to look up file: {}. Error: {}", id, e);
 
-            return Ok(Response::builder()
+            return Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
                 .body(Body::empty())
-                .unwrap());
+                .unwrap();
         }
     };
 
-    let mut buffer = Vec::new();
+    let stored = match stored {
+        Some(stored) => stored,
+        None => {
+            warn!("File not found: {}", id);
 
-    if let Err(e) = file.read_to_end(&mut buffer) {
-        error!("Failed to read file: {:?}. Error: {}", file_path, e);
+            let body = not_found_handler().await;
 
-        return Ok(Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Body::empty())
-            .unwrap());
-    }
+            return (
+                StatusCode::NOT_FOUND,
+                [(CONTENT_TYPE, "text/html")],
+                body,
+            ).into_response();
+        }
+    };
 
-    let content_type = from_path(&file_path)
+    let content_type = from_path(id)
         .first_or_octet_stream()
         .to_string();
 
-    let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
-    let content_disposition = format!("attachment; filename=\"{}\"", file_name);
+    // "?inline" links open in the browser, see `LinkStyle`
+    let disposition = if inline { "inline" } else { "attachment" };
+    let content_disposition = format!("{}; filename=\"{}\"", disposition, id);
 
-    info!("Serving file: {:?} with content type: {}", file_path, content_type);
+    if let Some(url) = files.storage.redirect_url(storage_name, &content_disposition) {
+        info!("Redirecting to the storage for file: {}", id);
 
-    Ok(Response::builder()
+        return Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(LOCATION, url)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    // compressed files are sent as they are stored to clients which can decompress them, see `COMPRESSION`
+    let compressed = match accepts_zstd(headers) {
+        true => files.storage.get_compressed(storage_name).await,
+        false => Ok(None),
+    };
+
+    let compressed = match compressed {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            error!("Failed to open file: {}. Error: {}", id, e);
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+
+    if let Some(data) = compressed {
+        info!("Serving file: {} compressed with zstd, content type: {}", id, content_type);
+
+        let mut response = Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_ENCODING, "zstd")
+            .header(VARY, "Accept-Encoding")
+            .header("Content-Disposition", content_disposition);
+
+        // the tag differs from the uncompressed response, the bodies differ
+        if let Some(sha256) = sha256 {
+            response = response
+                .header(ETAG, format!("\"{}.zst\"", sha256))
+                .header("X-Checksum-Sha256", sha256);
+        }
+
+        return response.body(Body::from_stream(data)).unwrap();
+    }
+
+    let data = match files.storage.get(storage_name).await {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Failed to open file: {}. Error: {}", id, e);
+
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+
+    info!("Serving file: {} with content type: {}", id, content_type);
+
+    let mut response = Response::builder()
         .status(StatusCode::OK)
         .header(CONTENT_TYPE, content_type)
-        .header("Content-Disposition", content_disposition)
-        .body(buffer.into())
-        .unwrap())
+        .header(CONTENT_LENGTH, stored.size)
+        .header("Content-Disposition", content_disposition);
+
+    // lets downloaders check the file without asking the bot
+    if let Some(sha256) = sha256 {
+        response = response
+            .header(ETAG, format!("\"{}\"", sha256))
+            .header("X-Checksum-Sha256", sha256);
+    }
+
+    response.body(Body::from_stream(data)).unwrap()
+}
+
+/// Page with the details of a file and the files in it if it is an archive, see `PostProcessorKind::Archive`
+/// Files extracted with `--extract` are linked
+async fn file_preview(files: &FilesState, id: &str, token: Option<&str>) -> Response<Body> {
+    let now = now_seconds();
+    let secret = files.config.get().listing_secret();
+    let file = files.metadata.lock().await.get(id)
+        .filter(|file| !file.is_expired(now) && is_accessible(file.private, &file.owners, secret.as_deref(), token))
+        .cloned();
+
+    let file = match file {
+        Some(file) => file,
+        None => return (StatusCode::NOT_FOUND, not_found_handler().await).into_response(),
+    };
+
+    info!("Preview of file {} accessed", id);
+
+    // the token opens every private file of the owner, so it is passed on to the download and the extracted files
+    let link = |file_name: &str| match (file.private, token) {
+        (true, Some(token)) => listing::with_token(&escape_html(file_name), token),
+        _ => escape_html(file_name),
+    };
+
+    let name = escape_html(&file.file_name);
+    let mut html = format!("<h1>{}</h1><p>{}", name, format_size(file.size));
+
+    if let Some(expires_at) = file.expires_at {
+        html.push_str(&format!(", expires in {}", format_duration(expires_at.saturating_sub(now))));
+    }
+
+    if let Some(sha256) = &file.sha256 {
+        html.push_str(&format!("<br><small>SHA-256: <code>{}</code></small>", sha256));
+    }
+
+    // relative to /files/, so the links keep working behind a proxy prefix
+    html.push_str(&format!("</p><p><a href=\"{}\">Download</a></p>", link(&file.file_name)));
+
+    if !file.archive_entries.is_empty() {
+        html.push_str(&format!("<h2>Contents</h2><p>{} file(s)</p><ul>", file.archive_entries.len()));
+
+        for entry in &file.archive_entries {
+            let path = escape_html(&entry.path);
+
+            match &entry.file_name {
+                Some(file_name) => html.push_str(&format!("<li><a href=\"{}\">{}</a> ({})</li>", link(file_name), path, format_size(entry.size))),
+                None => html.push_str(&format!("<li>{} ({})</li>", path, format_size(entry.size))),
+            }
+        }
+
+        html.push_str("</ul>");
+    }
+
+    Html(html).into_response()
 }
 
 async fn root() -> Html<&'static str> {
@@ -161,4 +646,63 @@ async fn not_found_handler() -> Html<&'static str> {
     <p>The page you are looking for does not exist.</p>\
     <a href=\"/\">Go back to the homepage</a>\
     ")
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use bot::queue::QueueManager;
+    use shared::config::Config;
+    use shared::metadata::FileMetadata;
+    use shared::storage::LocalStorage;
+    use tokio::fs;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn file(name: &str, folder: Option<&str>, private: bool) -> FileMetadata {
+        FileMetadata {
+            file_name: name.to_owned(),
+            size: 6,
+            owners: vec!["123".to_owned()],
+            private,
+            folder: folder.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    async fn status(app: &Router, uri: &str) -> StatusCode {
+        let request = http::Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_files_id_hides_stored_content() {
+        let root = std::env::temp_dir().join("file2link_test_server_files");
+        let _ = fs::remove_dir_all(&root).await;
+
+        fs::create_dir_all(root.join("2026/10/16")).await.unwrap();
+        fs::write(root.join("2026/10/16/abcdef_secret.pdf"), b"secret").await.unwrap();
+        fs::write(root.join("ghijk_old.pdf"), b"legacy").await.unwrap();
+
+        let mut metadata = MetadataStore::default();
+        metadata.insert(file("abcdef_secret.pdf", Some("2026/10/16"), true));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let app = create_app(
+            SharedConfig::new(Config::default()),
+            Arc::new(Mutex::new(metadata)),
+            Arc::new(LocalStorage::new(&root)),
+            Arc::new(Mutex::new(QueueManager::new())),
+            tx,
+        ).await;
+
+        assert_eq!(status(&app, "/files/abcdef_secret.pdf").await, StatusCode::NOT_FOUND);
+        // the storage path is decoded into the ID, it must not skip the check of the private file
+        assert_eq!(status(&app, "/files/2026%2F10%2F16%2Fabcdef_secret.pdf").await, StatusCode::NOT_FOUND);
+        // files stored before the metadata are still served by their name
+        assert_eq!(status(&app, "/files/ghijk_old.pdf").await, StatusCode::OK);
+
+        let _ = fs::remove_dir_all(&root).await;
+    }
+}